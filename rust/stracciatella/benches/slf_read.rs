@@ -0,0 +1,160 @@
+//! This file contains a benchmark of multi-threaded reads from a SLF archive.
+//!
+//! Run it with `cargo bench -p stracciatella --bench slf_read`.
+//!
+//! It compares an archive backed by a filesystem file, which uses positional
+//! reads, to the same file wrapped in a generic virtual file, which
+//! serializes the reads with a lock.
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stracciatella::file_formats::slf::{SlfEncoding, SlfEntry, SlfEntryState, SlfHeader};
+use stracciatella::fs::{File, OpenOptions, TempDir};
+use stracciatella::unicode::Nfc;
+use stracciatella::vfs::dir::DirFsFile;
+use stracciatella::vfs::slf::SlfFs;
+use stracciatella::vfs::{VfsFile, VfsLayer};
+
+/// Number of files in the archive.
+const NUM_ENTRIES: usize = 64;
+/// Size of each file in the archive.
+const ENTRY_BYTES: usize = 256 * 1024;
+/// Size of each read.
+const READ_BYTES: usize = 16 * 1024;
+/// Number of times each thread reads all files.
+const ROUNDS: usize = 8;
+
+fn main() {
+    let temp = TempDir::new().expect("TempDir");
+    let path = temp.path().join("bench.slf");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .expect("create slf");
+    create_slf(&mut file);
+
+    println!("threads  file (MiB/s)  generic (MiB/s)");
+    for threads in [1, 2, 4, 8] {
+        let file: Box<dyn VfsFile> = Box::new(DirFsFile::open(&path).expect("open slf"));
+        let file_time = bench(SlfFs::new(file).expect("SlfFs"), threads);
+        let generic: Box<dyn VfsFile> =
+            Box::new(GenericFile(DirFsFile::open(&path).expect("open slf")));
+        let generic_time = bench(SlfFs::new(generic).expect("SlfFs"), threads);
+        println!(
+            "{:>7}  {:>12.1}  {:>15.1}",
+            threads,
+            throughput(file_time, threads),
+            throughput(generic_time, threads)
+        );
+    }
+
+    temp.close().expect("close temp dir");
+}
+
+/// Reads all files of the archive in each thread and returns the elapsed time.
+fn bench(slf_fs: Arc<SlfFs>, threads: usize) -> Duration {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|thread_index| {
+            let slf_fs = slf_fs.clone();
+            thread::spawn(move || {
+                let mut buf = vec![0u8; READ_BYTES];
+                for round in 0..ROUNDS {
+                    for i in 0..NUM_ENTRIES {
+                        // every thread starts with a different file
+                        let i = (i + thread_index + round) % NUM_ENTRIES;
+                        let path = Nfc::caseless_path(&format!("file{}.dat", i));
+                        let mut file = slf_fs.open(&path).expect("open");
+                        while file.read(&mut buf).expect("read") > 0 {}
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("join");
+    }
+    start.elapsed()
+}
+
+/// Returns the read throughput in MiB per second.
+fn throughput(elapsed: Duration, threads: usize) -> f64 {
+    let bytes = (threads * ROUNDS * NUM_ENTRIES * ENTRY_BYTES) as f64;
+    bytes / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+}
+
+/// Filesystem file that is not recognized as one, so the reads of SlfFs use a lock.
+#[derive(Debug)]
+struct GenericFile(DirFsFile);
+
+impl VfsFile for GenericFile {
+    fn len(&self) -> io::Result<u64> {
+        self.0.len()
+    }
+}
+
+impl fmt::Display for GenericFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Read for GenericFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for GenericFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl Write for GenericFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Writes a SLF archive.
+fn create_slf(file: &mut File) {
+    let header = SlfHeader {
+        library_name: "bench.slf".to_owned(),
+        library_path: String::new(),
+        num_entries: NUM_ENTRIES as i32,
+        ok_entries: NUM_ENTRIES as i32,
+        sort: 0xFFFF,
+        version: 0x200,
+        contains_subdirectories: 0,
+        encoding: SlfEncoding::Utf8,
+    };
+    header.to_output(file).expect("write header");
+    let mut entries = Vec::new();
+    for i in 0..NUM_ENTRIES {
+        let offset = file.stream_position().expect("position");
+        file.write_all(&vec![i as u8; ENTRY_BYTES])
+            .expect("write data");
+        entries.push(SlfEntry {
+            file_path: format!("file{}.dat", i),
+            offset: offset as u32,
+            length: ENTRY_BYTES as u32,
+            state: SlfEntryState::Ok,
+            file_time: 0,
+        });
+    }
+    entries.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    header
+        .entries_to_output(file, &entries)
+        .expect("write entries");
+    file.sync_all().expect("sync_all");
+}
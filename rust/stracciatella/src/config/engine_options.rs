@@ -128,7 +128,7 @@ impl EngineOptions {
         ja2_json.apply_to_engine_options(&mut engine_options)?;
         cli.apply_to_engine_options(&mut engine_options)?;
 
-        if engine_options.vanilla_game_dir == PathBuf::from("") {
+        if engine_options.vanilla_game_dir.as_os_str().is_empty() {
             return Err(EngineOptionsError::MissingGameDir);
         }
 
@@ -17,14 +17,20 @@ pub trait StracciatellaReadExt: Read {
 
     /// Reads a nul terminated fixed size string.
     fn read_fixed_string(&mut self, num_bytes: usize) -> Result<String> {
+        // must be nul terminated and valid utf8
+        let bytes = self.read_fixed_bytes(num_bytes)?;
+        String::from_utf8(bytes).map_err(|e| Error::new(InvalidData, e))
+    }
+
+    /// Reads the bytes of a nul terminated fixed size string, without the nul terminator.
+    fn read_fixed_bytes(&mut self, num_bytes: usize) -> Result<Vec<u8>> {
         let mut buffer = vec![0u8; num_bytes];
         self.read_exact(&mut buffer)?;
-        // must be nul terminated and valid utf8
         match buffer.iter().position(|&byte| byte == 0) {
-            Some(position) => match ::std::str::from_utf8(&buffer[..position]) {
-                Ok(s) => Ok(s.to_string()),
-                Err(e) => Err(Error::new(InvalidData, e)),
-            },
+            Some(position) => {
+                buffer.truncate(position);
+                Ok(buffer)
+            }
             None => Err(Error::new(InvalidData, "string is not nul terminated")),
         }
     }
@@ -40,8 +46,12 @@ pub trait StracciatellaWriteExt: Write {
 
     /// Write a nul terminated fixed size string, unused space is zeroed.
     fn write_fixed_string(&mut self, num_bytes: usize, string: &str) -> Result<()> {
+        self.write_fixed_bytes(num_bytes, string.as_bytes())
+    }
+
+    /// Write the bytes of a nul terminated fixed size string, unused space is zeroed.
+    fn write_fixed_bytes(&mut self, num_bytes: usize, string_bytes: &[u8]) -> Result<()> {
         let mut buffer = vec![0u8; num_bytes];
-        let string_bytes = string.as_bytes();
         if string_bytes.len() >= buffer.len() {
             return Err(Error::new(InvalidInput, "string is too long"));
         }
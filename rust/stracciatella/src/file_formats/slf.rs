@@ -23,7 +23,10 @@
 //! Probably the special names for current directory "." and parent directory ".." are not supported.
 //! The header contains a library path, it is a path relative to the default directory (Data dir).
 //! Each entry contains a file path, it is a path relative to the library path.
-//! The encoding of the paths is unknown, but so far I've only seen ASCII.
+//! The encoding of the strings is not stored, most archives only use ASCII.
+//! Archives of localized releases use the legacy codepage of the release, like
+//! CP1251 in the Russian releases, CP1250 in the Polish release and GBK in the Chinese release.
+//! Strings that are valid UTF-8 are read as UTF-8, see [`SlfEncoding`].
 //!
 //!
 //! # Header Structure
@@ -55,14 +58,37 @@
 //!  * 2 byte unsigned number with name usReserved2 (not used)
 //!  * 2 byte padding (4 byte alignment)
 //!
+//!
+//! # Writing
+//!
+//! [`SlfBuilder`] assembles a complete archive from files on disk, in VFS layers or in memory.
+//! The data is laid out in the order of the entries, which are sorted case-insensitively by file path
+//! like `_stricmp` does, by comparing the lowercase paths.
+//!
+//! Like datalib98, files can also be appended to an existing archive in place.
+//! The data of the new files replaces the entries at the end of the archive and entries
+//! that are replaced by a new version get state Old, their data stays in the archive.
+//! [`compact_archive`] writes a copy of an archive without the data of Old and Deleted entries.
+//!
 
-use std::io::ErrorKind::InvalidInput;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::io::ErrorKind::{InvalidData, InvalidInput};
 use std::io::{Cursor, Error, Read, Result, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use encoding_rs::{Encoding, GBK, WINDOWS_1250, WINDOWS_1251, WINDOWS_1252};
 
+use crate::config::VanillaVersion;
 use crate::file_formats::{StracciatellaReadExt, StracciatellaWriteExt};
+use crate::fs;
+use crate::unicode::Nfc;
+use crate::vfs::VfsLayer;
 
 /// Number of bytes of the header in the library file.
 pub const HEADER_BYTES: u32 = 532;
@@ -81,13 +107,13 @@ pub struct SlfHeader {
     /// Name of the library.
     ///
     /// Usually it's the name of the library file in uppercase.
-    /// Nul terminated string of 256 bytes, unused bytes are zeroed, unknown encoding (saw ASCII).
+    /// Nul terminated string of 256 bytes, unused bytes are zeroed, in the encoding of the archive.
     pub library_name: String,
 
     /// Base path of the files in the library.
     ///
     /// Empty or terminated by '\\'.
-    /// Nul terminated string of 256 bytes, unused bytes are zeroed, unknown encoding (saw ASCII).
+    /// Nul terminated string of 256 bytes, unused bytes are zeroed, in the encoding of the archive.
     pub library_path: String,
 
     /// Number of entries that are available.
@@ -105,6 +131,11 @@ pub struct SlfHeader {
     /// TODO 0 when there are 0 '\\' characters in library_path (0 '\\' characters in the file names either, do they count?)
     ///      1 when there is 1 '\\' character in library_path (0-2 '\\' characters in the file names)
     pub contains_subdirectories: u8,
+
+    /// Encoding of the library name, the library path and the file paths of the entries.
+    ///
+    /// Not stored in the archive.
+    pub encoding: SlfEncoding,
 }
 
 /// Entry of the archive.
@@ -151,18 +182,93 @@ pub enum SlfEntryState {
     Unknown(u8),
 }
 
+/// Encoding of the strings of an archive.
+///
+/// ASCII is the same in all encodings.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SlfEncoding {
+    /// UTF-8, used for archives where all strings are valid UTF-8.
+    #[default]
+    Utf8,
+
+    /// Windows-1250, Central European, used by the Polish release.
+    Windows1250,
+
+    /// Windows-1251, Cyrillic, used by the Russian releases.
+    Windows1251,
+
+    /// Windows-1252, Western European, used by the other european releases.
+    Windows1252,
+
+    /// GBK, Simplified Chinese, used by the Chinese release.
+    Gbk,
+}
+
 impl SlfHeader {
     /// Read the header from input.
+    ///
+    /// The legacy encoding of archives with strings that are not valid UTF-8 is detected.
     #[allow(dead_code)]
     pub fn from_input<T>(input: &mut T) -> Result<Self>
+    where
+        T: Read + Seek,
+    {
+        Self::from_input_with_encoding(input, None)
+    }
+
+    /// Read the header from input.
+    ///
+    /// The legacy encoding is used for archives with strings that are not valid UTF-8,
+    /// it is detected from the strings of the archive when it is `None`.
+    /// The entries are read to detect the encoding, use `with_entries_from_input` to keep them.
+    pub fn from_input_with_encoding<T>(
+        input: &mut T,
+        legacy_encoding: Option<SlfEncoding>,
+    ) -> Result<Self>
+    where
+        T: Read + Seek,
+    {
+        let (mut header, strings) = Self::raw_from_input(input)?;
+        // The entries are only needed for the encoding here, errors are reported when they are read
+        let raw_entries = header.raw_entries_from_input(input).unwrap_or_default();
+        input.seek(SeekFrom::Start(u64::from(HEADER_BYTES)))?;
+        header.detect_encoding(&strings, &raw_entries, legacy_encoding);
+        header.library_name = header.encoding.decode(&strings[0])?;
+        header.library_path = header.encoding.decode(&strings[1])?;
+        Ok(header)
+    }
+
+    /// Read the header and the entries from input.
+    ///
+    /// The encoding is chosen like in `from_input_with_encoding`, the entries are only read once.
+    pub fn with_entries_from_input<T>(
+        input: &mut T,
+        legacy_encoding: Option<SlfEncoding>,
+    ) -> Result<(Self, Vec<SlfEntry>)>
+    where
+        T: Read + Seek,
+    {
+        let (mut header, strings) = Self::raw_from_input(input)?;
+        let raw_entries = header.raw_entries_from_input(input)?;
+        header.detect_encoding(&strings, &raw_entries, legacy_encoding);
+        header.library_name = header.encoding.decode(&strings[0])?;
+        header.library_path = header.encoding.decode(&strings[1])?;
+        let entries = header.decode_entries(raw_entries)?;
+        Ok((header, entries))
+    }
+
+    /// Read the header from input with the undecoded bytes of the library name and the library path.
+    ///
+    /// The strings of the returned header are empty and the encoding is UTF-8.
+    fn raw_from_input<T>(input: &mut T) -> Result<(Self, [Vec<u8>; 2])>
     where
         T: Read + Seek,
     {
         input.seek(SeekFrom::Start(0))?;
 
         let mut handle = input.take(u64::from(HEADER_BYTES));
-        let library_name = handle.read_fixed_string(256)?;
-        let library_path = handle.read_fixed_string(256)?;
+        let library_name = handle.read_fixed_bytes(256)?;
+        let library_path = handle.read_fixed_bytes(256)?;
         let num_entries = handle.read_i32::<LE>()?;
         let ok_entries = handle.read_i32::<LE>()?;
         let sort = handle.read_u16::<LE>()?;
@@ -171,15 +277,52 @@ impl SlfHeader {
         handle.read_unused(7)?;
         assert_eq!(handle.limit(), 0);
 
-        Ok(Self {
-            library_name,
-            library_path,
+        let header = Self {
+            library_name: String::new(),
+            library_path: String::new(),
             num_entries,
             ok_entries,
             sort,
             version,
             contains_subdirectories,
-        })
+            encoding: SlfEncoding::Utf8,
+        };
+        Ok((header, [library_name, library_path]))
+    }
+
+    /// Sets the encoding for the undecoded strings of the header and the entries.
+    ///
+    /// The legacy encoding is only used if a string is not valid UTF-8, it is detected when `None`.
+    fn detect_encoding(
+        &mut self,
+        strings: &[Vec<u8>; 2],
+        raw_entries: &[(Vec<u8>, SlfEntry)],
+        legacy_encoding: Option<SlfEncoding>,
+    ) {
+        let all_strings = || {
+            strings
+                .iter()
+                .chain(raw_entries.iter().map(|(file_path, _)| file_path))
+                .map(Vec::as_slice)
+        };
+        self.encoding = if all_strings().all(|x| std::str::from_utf8(x).is_ok()) {
+            SlfEncoding::Utf8
+        } else {
+            legacy_encoding.unwrap_or_else(|| SlfEncoding::detect(all_strings()))
+        };
+    }
+
+    /// Decodes the file paths of entries that were read with `raw_entries_from_input`.
+    fn decode_entries(&self, raw_entries: Vec<(Vec<u8>, SlfEntry)>) -> Result<Vec<SlfEntry>> {
+        raw_entries
+            .into_iter()
+            .map(|(file_path, entry)| {
+                Ok(SlfEntry {
+                    file_path: self.encoding.decode(&file_path)?,
+                    ..entry
+                })
+            })
+            .collect()
     }
 
     /// Write this header to output.
@@ -190,8 +333,8 @@ impl SlfHeader {
     {
         let mut buffer = Vec::with_capacity(HEADER_BYTES as usize);
         let mut cursor = Cursor::new(&mut buffer);
-        cursor.write_fixed_string(256, &self.library_name)?;
-        cursor.write_fixed_string(256, &self.library_path)?;
+        cursor.write_fixed_bytes(256, &self.encoding.encode(&self.library_name)?)?;
+        cursor.write_fixed_bytes(256, &self.encoding.encode(&self.library_path)?)?;
         cursor.write_i32::<LE>(self.num_entries)?;
         cursor.write_i32::<LE>(self.ok_entries)?;
         cursor.write_u16::<LE>(self.sort)?;
@@ -209,6 +352,14 @@ impl SlfHeader {
     /// Read the entries from the input.
     #[allow(dead_code)]
     pub fn entries_from_input<T>(&self, input: &mut T) -> Result<Vec<SlfEntry>>
+    where
+        T: Read + Seek,
+    {
+        self.decode_entries(self.raw_entries_from_input(input)?)
+    }
+
+    /// Read the entries from the input with the undecoded bytes of the file paths.
+    fn raw_entries_from_input<T>(&self, input: &mut T) -> Result<Vec<(Vec<u8>, SlfEntry)>>
     where
         T: Read + Seek,
     {
@@ -220,13 +371,18 @@ impl SlfHeader {
         }
 
         let num_entries = self.num_entries as u32;
-        let num_bytes = num_entries * ENTRY_BYTES;
+        let num_bytes = num_entries.checked_mul(ENTRY_BYTES).ok_or_else(|| {
+            Error::new(
+                InvalidInput,
+                format!("unexpected number of entries {}", self.num_entries),
+            )
+        })?;
         input.seek(SeekFrom::End(-(i64::from(num_bytes))))?;
 
         let mut handle = input.take(u64::from(num_bytes));
         let mut entries = Vec::new();
         for _ in 0..num_entries {
-            let file_path = handle.read_fixed_string(256)?;
+            let file_path = handle.read_fixed_bytes(256)?;
             let offset = handle.read_u32::<LE>()?;
             let length = handle.read_u32::<LE>()?;
             let state: SlfEntryState = handle.read_u8()?.into();
@@ -234,13 +390,14 @@ impl SlfHeader {
             let file_time = handle.read_u64::<LE>()?;
             handle.read_unused(4)?;
 
-            entries.push(SlfEntry {
-                file_path,
+            let entry = SlfEntry {
+                file_path: String::new(),
                 offset,
                 length,
                 state,
                 file_time,
-            });
+            };
+            entries.push((file_path, entry));
         }
         assert_eq!(handle.limit(), 0);
 
@@ -268,7 +425,7 @@ impl SlfHeader {
         let mut buffer = Vec::with_capacity(num_bytes as usize);
         let mut cursor = Cursor::new(&mut buffer);
         for entry in entries {
-            cursor.write_fixed_string(256, &entry.file_path)?;
+            cursor.write_fixed_bytes(256, &self.encoding.encode(&entry.file_path)?)?;
             cursor.write_u32::<LE>(entry.offset)?;
             cursor.write_u32::<LE>(entry.length)?;
             cursor.write_u8(entry.state.into())?;
@@ -278,13 +435,7 @@ impl SlfHeader {
         }
         assert_eq!(buffer.len(), num_bytes as usize);
 
-        let mut end_of_data = HEADER_BYTES;
-        for entry in entries {
-            let end_of_entry = entry.offset + entry.length;
-            if end_of_data < end_of_entry {
-                end_of_data = end_of_entry;
-            }
-        }
+        let end_of_data = end_of_data(entries)?;
 
         match output.seek(SeekFrom::End(-(i64::from(num_bytes)))) {
             Ok(position) if position >= u64::from(end_of_data) => {}
@@ -320,6 +471,26 @@ impl SlfEntry {
         }
     }
 
+    /// Convert system time to the file time of an entry.
+    ///
+    /// Times before 1 Jan 1601 are clamped to 0.
+    pub fn file_time_from_system_time(time: SystemTime) -> u64 {
+        let to_filetime =
+            |x: Duration| x.as_secs() * 10_000_000 + u64::from(x.subsec_nanos()) / 100;
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => UNIX_EPOCH_AS_FILETIME.saturating_add(to_filetime(after)),
+            Err(err) => UNIX_EPOCH_AS_FILETIME.saturating_sub(to_filetime(err.duration())),
+        }
+    }
+
+    /// Returns the key that entries are sorted by, the file path first and the state second (Old < Ok).
+    pub fn sort_key(&self) -> (String, bool) {
+        (
+            self.file_path.to_ascii_lowercase(),
+            self.state != SlfEntryState::Old,
+        )
+    }
+
     /// Read the entry data from the input.
     #[allow(dead_code)]
     pub fn data_from_input<T>(&self, input: &mut T) -> Result<Vec<u8>>
@@ -360,6 +531,772 @@ impl SlfEntry {
     }
 }
 
+impl SlfEncoding {
+    /// Decodes the bytes of a string.
+    pub fn decode(self, bytes: &[u8]) -> Result<String> {
+        match self.encoding() {
+            None => String::from_utf8(bytes.to_vec()).map_err(|e| Error::new(InvalidData, e)),
+            Some(encoding) => encoding
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .map(|x| x.into_owned())
+                .ok_or_else(|| {
+                    Error::new(
+                        InvalidData,
+                        format!("string is not valid {}", encoding.name()),
+                    )
+                }),
+        }
+    }
+
+    /// Decodes the bytes of a string, invalid bytes are replaced with U+FFFD.
+    fn decode_lossy(self, bytes: &[u8]) -> String {
+        match self.encoding() {
+            None => String::from_utf8_lossy(bytes).into_owned(),
+            Some(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+        }
+    }
+
+    /// Encodes a string, fails if the encoding does not support a character of the string.
+    pub fn encode(self, string: &str) -> Result<Vec<u8>> {
+        match self.encoding() {
+            None => Ok(string.as_bytes().to_vec()),
+            Some(encoding) => {
+                let (bytes, _, unmappable) = encoding.encode(string);
+                if unmappable {
+                    return Err(Error::new(
+                        InvalidInput,
+                        format!("{:?} can not be encoded as {}", string, encoding.name()),
+                    ));
+                }
+                Ok(bytes.into_owned())
+            }
+        }
+    }
+
+    /// Guesses the encoding of the strings of an archive.
+    ///
+    /// The strings are UTF-8 if they are all valid UTF-8 and GBK if the non-ASCII bytes are pairs
+    /// in the GB2312 range that decode to CJK characters. Otherwise they are Windows-1251 if most
+    /// non-ASCII characters form words without ASCII letters, like Cyrillic words do. Latin strings are
+    /// Windows-1252 if all non-ASCII bytes are Latin-1 letters, like the umlauts and accents of western
+    /// european languages, and Windows-1250 if they also use other letters, like most Polish words do.
+    /// Prefer a known encoding, like the one of the vanilla version, over a guess.
+    pub fn detect<'a, I>(strings: I) -> SlfEncoding
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let strings: Vec<&[u8]> = strings.into_iter().collect();
+        if strings.iter().all(|x| std::str::from_utf8(x).is_ok()) {
+            return SlfEncoding::Utf8;
+        }
+        let is_gbk = strings.iter().all(|x| {
+            non_ascii_runs(x).iter().all(|run| {
+                run.len() % 2 == 0 && x[run.clone()].iter().all(|b| (0xA1..=0xFE).contains(b))
+            }) && GBK
+                .decode_without_bom_handling_and_without_replacement(x)
+                .is_some_and(|x| x.chars().all(|c| c.is_ascii() || is_cjk(c)))
+        });
+        if is_gbk {
+            return SlfEncoding::Gbk;
+        }
+
+        let mut non_ascii = 0;
+        let mut next_to_letter = 0;
+        for string in &strings {
+            for run in non_ascii_runs(string) {
+                let before = run.start.checked_sub(1).map(|x| string[x]);
+                let after = string.get(run.end).copied();
+                non_ascii += run.len();
+                if before
+                    .into_iter()
+                    .chain(after)
+                    .any(|x| x.is_ascii_alphabetic())
+                {
+                    next_to_letter += run.len();
+                }
+            }
+        }
+        if next_to_letter * 2 < non_ascii {
+            return SlfEncoding::Windows1251;
+        }
+        let is_latin_1 = strings.iter().all(|x| {
+            non_ascii_runs(x)
+                .into_iter()
+                .flat_map(|run| &x[run])
+                .all(|b| *b >= 0xC0 && *b != 0xD7 && *b != 0xF7)
+        });
+        if is_latin_1 {
+            SlfEncoding::Windows1252
+        } else {
+            SlfEncoding::Windows1250
+        }
+    }
+
+    /// Returns the legacy encoding, `None` for UTF-8.
+    fn encoding(self) -> Option<&'static Encoding> {
+        match self {
+            SlfEncoding::Utf8 => None,
+            SlfEncoding::Windows1250 => Some(WINDOWS_1250),
+            SlfEncoding::Windows1251 => Some(WINDOWS_1251),
+            SlfEncoding::Windows1252 => Some(WINDOWS_1252),
+            SlfEncoding::Gbk => Some(GBK),
+        }
+    }
+}
+
+/// Builder of a new SLF archive.
+///
+/// Files are added with a path relative to the library path, '/' and '\\' are both accepted as directory separators.
+/// The data of files on disk and in VFS layers is only read when the archive is written.
+#[derive(Debug)]
+pub struct SlfBuilder {
+    /// Name of the library, see `SlfHeader::library_name`.
+    library_name: String,
+
+    /// Base path of the files in the library, see `SlfHeader::library_path`.
+    library_path: String,
+
+    /// Encoding of the strings, see `SlfHeader::encoding`.
+    encoding: SlfEncoding,
+
+    /// Files of the library by lowercase file path.
+    files: BTreeMap<String, SlfBuilderFile>,
+}
+
+/// File that is added to an `SlfBuilder`.
+#[derive(Debug)]
+struct SlfBuilderFile {
+    /// Path of the file from the library path.
+    file_path: String,
+
+    /// FILETIME of the entry.
+    file_time: u64,
+
+    /// Where the data comes from.
+    source: SlfBuilderSource,
+}
+
+/// Source of the data of a file that is added to an `SlfBuilder`.
+#[derive(Debug)]
+enum SlfBuilderSource {
+    /// Data in memory.
+    Data(Vec<u8>),
+
+    /// File on disk.
+    Disk(PathBuf),
+
+    /// File in a VFS layer.
+    Layer(Arc<dyn VfsLayer>, Nfc),
+}
+
+impl SlfBuilder {
+    /// Creates a builder of an archive without files.
+    ///
+    /// The library path is relative to the Data dir, pass an empty path for the Data dir itself.
+    pub fn new(library_name: &str, library_path: &str) -> Self {
+        Self::new_with_encoding(library_name, library_path, SlfEncoding::Utf8)
+    }
+
+    /// Creates a builder of an archive without files that has the strings in a legacy encoding.
+    pub fn new_with_encoding(
+        library_name: &str,
+        library_path: &str,
+        encoding: SlfEncoding,
+    ) -> Self {
+        let library_path = library_path.replace('/', "\\");
+        let library_path = library_path.trim_matches('\\');
+        Self {
+            library_name: library_name.to_owned(),
+            library_path: if library_path.is_empty() {
+                String::new()
+            } else {
+                format!("{}\\", library_path)
+            },
+            encoding,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a file with data in memory.
+    pub fn add_data(
+        &mut self,
+        file_path: &str,
+        data: Vec<u8>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        self.add(file_path, modified, SlfBuilderSource::Data(data))
+    }
+
+    /// Adds a file on disk, the file time is the modification time of the file.
+    pub fn add_file(&mut self, file_path: &str, path: &Path) -> Result<()> {
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(Error::new(
+                InvalidInput,
+                format!("{:?} is not a file", path),
+            ));
+        }
+        let source = SlfBuilderSource::Disk(path.to_owned());
+        self.add(file_path, metadata.modified().ok(), source)
+    }
+
+    /// Adds all files in a directory on disk and its subdirectories, with paths relative to the directory.
+    ///
+    /// Returns the number of added files.
+    pub fn add_dir(&mut self, dir_path: &Path) -> Result<usize> {
+        let paths = fs::find_all_files_in_dir(dir_path, false, true)?;
+        for path in &paths {
+            let relative = path
+                .strip_prefix(dir_path)
+                .ok()
+                .and_then(|x| x.to_str())
+                .ok_or_else(|| Error::new(InvalidInput, format!("unsupported path {:?}", path)))?;
+            self.add_file(&relative.replace(std::path::MAIN_SEPARATOR, "/"), path)?;
+        }
+        Ok(paths.len())
+    }
+
+    /// Adds a file of a VFS layer, the file time is the modification time in the layer if it is known.
+    pub fn add_layer_file(
+        &mut self,
+        file_path: &str,
+        layer: &Arc<dyn VfsLayer>,
+        layer_path: &Nfc,
+    ) -> Result<()> {
+        let metadata = layer.metadata(layer_path)?;
+        if metadata.is_dir {
+            return Err(Error::new(
+                InvalidInput,
+                format!("{} is not a file in {}", layer_path, layer),
+            ));
+        }
+        let source = SlfBuilderSource::Layer(layer.clone(), layer_path.clone());
+        self.add(file_path, metadata.modified, source)
+    }
+
+    /// Adds all files in a directory of a VFS layer and its subdirectories, with paths relative to the directory.
+    ///
+    /// Returns the number of added files.
+    pub fn add_layer_dir(&mut self, layer: &Arc<dyn VfsLayer>, dir_path: &Nfc) -> Result<usize> {
+        let dir_path = dir_path.trim_matches('/');
+        let paths = layer.read_dir_recursive(&Nfc::from(dir_path))?;
+        for path in &paths {
+            let relative = path[dir_path.len()..].trim_start_matches('/');
+            self.add_layer_file(relative, layer, path)?;
+        }
+        Ok(paths.len())
+    }
+
+    /// Writes the archive to output, which should be empty.
+    ///
+    /// Returns the entries of the archive in the order they were written.
+    pub fn write<T>(&self, output: &mut T) -> Result<Vec<SlfEntry>>
+    where
+        T: Write + Seek,
+    {
+        if self.files.is_empty() {
+            return Err(Error::new(
+                InvalidInput,
+                "an archive needs at least one file",
+            ));
+        }
+        let num_entries = i32::try_from(self.files.len())
+            .map_err(|_| Error::new(InvalidInput, "too many files"))?;
+        let contains_subdirectories = !self.library_path.is_empty()
+            || self.files.values().any(|x| x.file_path.contains('\\'));
+        let header = SlfHeader {
+            library_name: self.library_name.clone(),
+            library_path: self.library_path.clone(),
+            num_entries,
+            ok_entries: num_entries,
+            sort: 0xFFFF,
+            version: 0x0200,
+            contains_subdirectories: u8::from(contains_subdirectories),
+            encoding: self.encoding,
+        };
+        header.to_output(output)?;
+        let entries = self.write_data(output)?;
+        header.entries_to_output(output, &entries)?;
+
+        Ok(entries)
+    }
+
+    /// Writes the archive to a new file, an existing file is replaced.
+    ///
+    /// Returns the entries of the archive in the order they were written.
+    pub fn write_to_path(&self, path: &Path) -> Result<Vec<SlfEntry>> {
+        let mut output = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let entries = self.write(&mut output)?;
+        output.sync_all()?;
+        Ok(entries)
+    }
+
+    /// Appends the files to an existing archive in place.
+    ///
+    /// Entries with state Ok and the path of an appended file get state Old.
+    /// The library name, path and encoding of the builder are not used, the file paths are relative to the library path of the archive
+    /// and use the encoding of the archive.
+    /// The archive is corrupt if writing fails halfway.
+    /// Returns all entries of the archive in the order they were written.
+    pub fn append_to<T>(&self, archive: &mut T) -> Result<Vec<SlfEntry>>
+    where
+        T: Read + Write + Seek,
+    {
+        let (header, old_entries) = SlfHeader::with_entries_from_input(archive, None)?;
+        for file in self.files.values() {
+            if header.encoding.encode(&file.file_path)?.len() >= 256 {
+                return Err(Error::new(
+                    InvalidInput,
+                    format!("file path {:?} is too long", file.file_path),
+                ));
+            }
+        }
+        let end_of_data = end_of_data(&old_entries)?;
+        archive.seek(SeekFrom::Start(u64::from(end_of_data)))?;
+        let new_entries = self.write_data(archive)?;
+
+        let mut entries: Vec<SlfEntry> = old_entries
+            .into_iter()
+            .map(|mut entry| {
+                let key = entry.file_path.to_ascii_lowercase();
+                if entry.state == SlfEntryState::Ok && self.files.contains_key(&key) {
+                    entry.state = SlfEntryState::Old;
+                }
+                entry
+            })
+            .chain(new_entries)
+            .collect();
+        entries.sort_by_cached_key(SlfEntry::sort_key);
+        let num_entries =
+            i32::try_from(entries.len()).map_err(|_| Error::new(InvalidInput, "too many files"))?;
+        let ok_entries = entries
+            .iter()
+            .filter(|x| x.state == SlfEntryState::Ok)
+            .count() as i32;
+        let contains_subdirectories = header.contains_subdirectories != 0
+            || entries.iter().any(|x| x.file_path.contains('\\'));
+        let header = SlfHeader {
+            num_entries,
+            ok_entries,
+            contains_subdirectories: u8::from(contains_subdirectories),
+            ..header
+        };
+        header.to_output(archive)?;
+        header.entries_to_output(archive, &entries)?;
+
+        Ok(entries)
+    }
+
+    /// Appends the files to an existing archive file in place, see `append_to`.
+    pub fn append_to_path(&self, path: &Path) -> Result<Vec<SlfEntry>> {
+        let mut archive = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let entries = self.append_to(&mut archive)?;
+        archive.sync_all()?;
+        Ok(entries)
+    }
+
+    /// Writes the data of the files at the current position of output.
+    ///
+    /// Returns the entries of the files.
+    fn write_data<T>(&self, output: &mut T) -> Result<Vec<SlfEntry>>
+    where
+        T: Write + Seek,
+    {
+        let mut entries = Vec::with_capacity(self.files.len());
+        for file in self.files.values() {
+            let offset = output.stream_position()?;
+            let length = match &file.source {
+                SlfBuilderSource::Data(data) => {
+                    output.write_all(data)?;
+                    data.len() as u64
+                }
+                SlfBuilderSource::Disk(path) => io::copy(&mut fs::File::open(path)?, output)?,
+                SlfBuilderSource::Layer(layer, path) => io::copy(&mut layer.open(path)?, output)?,
+            };
+            let too_large = || {
+                Error::new(
+                    InvalidInput,
+                    format!("{} does not fit in the archive", file.file_path),
+                )
+            };
+            // the end of the data must fit too, see `end_of_data`
+            u32::try_from(offset + length).map_err(|_| too_large())?;
+            entries.push(SlfEntry {
+                file_path: file.file_path.clone(),
+                offset: u32::try_from(offset).map_err(|_| too_large())?,
+                length: u32::try_from(length).map_err(|_| too_large())?,
+                state: SlfEntryState::Ok,
+                file_time: file.file_time,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Adds a file, paths are unique case-insensitively.
+    fn add(
+        &mut self,
+        file_path: &str,
+        modified: Option<SystemTime>,
+        source: SlfBuilderSource,
+    ) -> Result<()> {
+        let file_path = file_path.replace('/', "\\");
+        let file_path = file_path.trim_matches('\\');
+        if file_path.is_empty()
+            || file_path
+                .split('\\')
+                .any(|x| x.is_empty() || x == "." || x == "..")
+        {
+            return Err(Error::new(
+                InvalidInput,
+                format!("invalid file path {:?}", file_path),
+            ));
+        }
+        if self.encoding.encode(file_path)?.len() >= 256
+            || self.encoding.encode(&self.library_path)?.len() >= 256
+        {
+            return Err(Error::new(
+                InvalidInput,
+                format!("file path {:?} is too long", file_path),
+            ));
+        }
+        let key = file_path.to_ascii_lowercase();
+        if self.files.contains_key(&key) {
+            return Err(Error::new(
+                InvalidInput,
+                format!("duplicate file path {:?}", file_path),
+            ));
+        }
+        let file_time = modified
+            .map(SlfEntry::file_time_from_system_time)
+            .unwrap_or_default();
+        self.files.insert(
+            key,
+            SlfBuilderFile {
+                file_path: file_path.to_owned(),
+                file_time,
+                source,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Writes a copy of an archive that only contains the entries with state Ok and their data.
+///
+/// The order of the entries and the header fields are kept.
+/// Returns the entries of the copy in the order they were written.
+pub fn compact_archive<T, U>(input: &mut T, output: &mut U) -> Result<Vec<SlfEntry>>
+where
+    T: Read + Seek,
+    U: Write + Seek,
+{
+    let (header, entries) = SlfHeader::with_entries_from_input(input, None)?;
+    let mut entries: Vec<SlfEntry> = entries
+        .into_iter()
+        .filter(|x| x.state == SlfEntryState::Ok)
+        .collect();
+    if entries.is_empty() {
+        return Err(Error::new(InvalidInput, "the archive has no Ok entries"));
+    }
+    let header = SlfHeader {
+        num_entries: entries.len() as i32,
+        ok_entries: entries.len() as i32,
+        ..header
+    };
+    header.to_output(output)?;
+
+    let mut offset = HEADER_BYTES;
+    for entry in &mut entries {
+        input.seek(SeekFrom::Start(u64::from(entry.offset)))?;
+        let copied = io::copy(&mut (&mut *input).take(u64::from(entry.length)), output)?;
+        if copied != u64::from(entry.length) {
+            return Err(Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("data of {} is truncated", entry.file_path),
+            ));
+        }
+        entry.offset = offset;
+        offset = offset
+            .checked_add(entry.length)
+            .ok_or_else(|| Error::new(InvalidInput, "entries do not fit in the archive"))?;
+    }
+    header.entries_to_output(output, &entries)?;
+
+    Ok(entries)
+}
+
+/// Compacts an archive file in place, see `compact_archive`.
+///
+/// The compacted archive is written to a temporary file in the same directory that replaces the archive.
+pub fn compact_archive_file(path: &Path) -> Result<Vec<SlfEntry>> {
+    let dir = path
+        .parent()
+        .filter(|x| !x.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut output = fs::NamedTempFile::new_in(dir)?;
+    let entries = compact_archive(&mut fs::File::open(path)?, output.as_file_mut())?;
+    output.as_file().sync_all()?;
+    output.persist(path).map_err(|err| err.error)?;
+    Ok(entries)
+}
+
+/// Checks the integrity of an SLF archive.
+#[derive(Clone, Debug)]
+pub struct SlfValidator {
+    /// Whether entries that are not sorted by `SlfEntry::sort_key` are reported.
+    pub check_order: bool,
+
+    /// Legacy encoding of archives with strings that are not valid UTF-8, detected when `None`.
+    pub legacy_encoding: Option<SlfEncoding>,
+}
+
+/// Problem of an SLF archive that was found by `SlfValidator`.
+///
+/// Entries are identified by their index in the entries of the archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SlfProblem {
+    /// The header cannot be read.
+    InvalidHeader(String),
+
+    /// The number of entries is not positive or the entries do not fit in the archive.
+    InvalidNumEntries { num_entries: i32, file_len: u64 },
+
+    /// The number of entries with state Ok does not match the header.
+    OkEntriesMismatch { expected: i32, actual: usize },
+
+    /// An entry has an unknown state.
+    UnknownState { index: usize, state: u8 },
+
+    /// The data of an entry with state Ok or Old is not between the header and the entries.
+    DataOutOfBounds {
+        index: usize,
+        offset: u32,
+        length: u32,
+    },
+
+    /// The data of two entries with state Ok or Old overlaps.
+    OverlappingData { index: usize, other: usize },
+
+    /// Two entries with state Ok have the same file path case-insensitively.
+    DuplicatePath { index: usize, other: usize },
+
+    /// An entry is sorted before the previous entry.
+    Unsorted { index: usize },
+
+    /// A string is not valid in the encoding of the archive.
+    ///
+    /// The index is `None` for the library name and the library path.
+    UndecodableString {
+        index: Option<usize>,
+        encoding: SlfEncoding,
+    },
+}
+
+impl SlfValidator {
+    /// Reads an archive and returns all problems that were found.
+    ///
+    /// Fails only if reading fails, a truncated archive is a problem.
+    pub fn validate<T>(&self, input: &mut T) -> Result<Vec<SlfProblem>>
+    where
+        T: Read + Seek,
+    {
+        let file_len = input.seek(SeekFrom::End(0))?;
+        let (mut header, strings) = match SlfHeader::raw_from_input(input) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(vec![SlfProblem::InvalidHeader(err.to_string())]);
+            }
+            Err(err) => return Err(err),
+        };
+        let entries_len = u64::try_from(header.num_entries)
+            .unwrap_or_default()
+            .saturating_mul(u64::from(ENTRY_BYTES));
+        if header.num_entries <= 0 || u64::from(HEADER_BYTES) + entries_len > file_len {
+            return Ok(vec![SlfProblem::InvalidNumEntries {
+                num_entries: header.num_entries,
+                file_len,
+            }]);
+        }
+        let raw_entries = header.raw_entries_from_input(input)?;
+        header.detect_encoding(&strings, &raw_entries, self.legacy_encoding);
+
+        // Undecodable strings are reported, the other checks use them with replacement characters
+        let encoding = header.encoding;
+        let mut undecodable = Vec::new();
+        if strings.iter().any(|x| encoding.decode(x).is_err()) {
+            undecodable.push(SlfProblem::UndecodableString {
+                index: None,
+                encoding,
+            });
+        }
+        header.library_name = encoding.decode_lossy(&strings[0]);
+        header.library_path = encoding.decode_lossy(&strings[1]);
+        let entries: Vec<SlfEntry> = raw_entries
+            .into_iter()
+            .enumerate()
+            .map(|(index, (file_path, entry))| {
+                let file_path = encoding.decode(&file_path).unwrap_or_else(|_| {
+                    undecodable.push(SlfProblem::UndecodableString {
+                        index: Some(index),
+                        encoding,
+                    });
+                    encoding.decode_lossy(&file_path)
+                });
+                SlfEntry { file_path, ..entry }
+            })
+            .collect();
+
+        let mut problems = self.validate_entries(&header, &entries, file_len);
+        problems.append(&mut undecodable);
+        Ok(problems)
+    }
+
+    /// Returns all problems of entries that were read from an archive with the given length.
+    pub fn validate_entries(
+        &self,
+        header: &SlfHeader,
+        entries: &[SlfEntry],
+        file_len: u64,
+    ) -> Vec<SlfProblem> {
+        let mut problems = Vec::new();
+        if usize::try_from(header.num_entries).ok() != Some(entries.len()) {
+            problems.push(SlfProblem::InvalidNumEntries {
+                num_entries: header.num_entries,
+                file_len,
+            });
+        }
+        let ok_entries = entries
+            .iter()
+            .filter(|x| x.state == SlfEntryState::Ok)
+            .count();
+        if usize::try_from(header.ok_entries).ok() != Some(ok_entries) {
+            problems.push(SlfProblem::OkEntriesMismatch {
+                expected: header.ok_entries,
+                actual: ok_entries,
+            });
+        }
+
+        let entries_start = file_len.saturating_sub(entries.len() as u64 * u64::from(ENTRY_BYTES));
+        let mut ranges = Vec::new();
+        let mut paths = HashMap::new();
+        for (index, entry) in entries.iter().enumerate() {
+            match entry.state {
+                SlfEntryState::Unknown(state) => {
+                    problems.push(SlfProblem::UnknownState { index, state });
+                }
+                SlfEntryState::Ok | SlfEntryState::Old => {
+                    let end = u64::from(entry.offset) + u64::from(entry.length);
+                    if entry.offset < HEADER_BYTES || end > entries_start {
+                        problems.push(SlfProblem::DataOutOfBounds {
+                            index,
+                            offset: entry.offset,
+                            length: entry.length,
+                        });
+                    } else if entry.length > 0 {
+                        ranges.push((u64::from(entry.offset), end, index));
+                    }
+                }
+                _ => {}
+            }
+            if entry.state == SlfEntryState::Ok {
+                let key = entry.file_path.to_ascii_lowercase();
+                if let Some(other) = paths.insert(key, index) {
+                    problems.push(SlfProblem::DuplicatePath { index, other });
+                }
+            }
+            if self.check_order && index > 0 && entry.sort_key() < entries[index - 1].sort_key() {
+                problems.push(SlfProblem::Unsorted { index });
+            }
+        }
+
+        ranges.sort_unstable();
+        let mut furthest: Option<(u64, usize)> = None;
+        for (start, end, index) in ranges {
+            if let Some((furthest_end, other)) = furthest {
+                if start < furthest_end {
+                    problems.push(SlfProblem::OverlappingData { index, other });
+                }
+            }
+            if furthest.is_none_or(|(furthest_end, _)| end > furthest_end) {
+                furthest = Some((end, index));
+            }
+        }
+        problems
+    }
+}
+
+impl Default for SlfValidator {
+    fn default() -> Self {
+        Self {
+            check_order: true,
+            legacy_encoding: None,
+        }
+    }
+}
+
+impl fmt::Display for SlfProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlfProblem::InvalidHeader(err) => write!(f, "invalid header: {}", err),
+            SlfProblem::InvalidNumEntries {
+                num_entries,
+                file_len,
+            } => write!(
+                f,
+                "{} entries do not fit in an archive of {} bytes",
+                num_entries, file_len
+            ),
+            SlfProblem::OkEntriesMismatch { expected, actual } => write!(
+                f,
+                "header has {} entries with state Ok, found {}",
+                expected, actual
+            ),
+            SlfProblem::UnknownState { index, state } => {
+                write!(f, "entry {} has unknown state 0x{:02X}", index, state)
+            }
+            SlfProblem::DataOutOfBounds {
+                index,
+                offset,
+                length,
+            } => write!(
+                f,
+                "data of entry {} at offset {} with length {} is outside of the data area",
+                index, offset, length
+            ),
+            SlfProblem::OverlappingData { index, other } => {
+                write!(f, "data of entry {} overlaps entry {}", index, other)
+            }
+            SlfProblem::DuplicatePath { index, other } => {
+                write!(f, "entry {} has the same path as entry {}", index, other)
+            }
+            SlfProblem::Unsorted { index } => {
+                write!(f, "entry {} is sorted before the previous entry", index)
+            }
+            SlfProblem::UndecodableString {
+                index: None,
+                encoding,
+            } => write!(
+                f,
+                "library name or library path is not valid {:?}",
+                encoding
+            ),
+            SlfProblem::UndecodableString {
+                index: Some(index),
+                encoding,
+            } => write!(
+                f,
+                "file path of entry {} is not valid {:?}",
+                index, encoding
+            ),
+        }
+    }
+}
+
 impl Default for SlfEntryState {
     /// Default value of SlfEntryState
     fn default() -> Self {
@@ -393,14 +1330,75 @@ impl From<u8> for SlfEntryState {
     }
 }
 
+impl From<VanillaVersion> for SlfEncoding {
+    /// Legacy encoding of the archives of a vanilla version.
+    fn from(version: VanillaVersion) -> Self {
+        match version {
+            VanillaVersion::POLISH => SlfEncoding::Windows1250,
+            VanillaVersion::RUSSIAN | VanillaVersion::RUSSIAN_GOLD => SlfEncoding::Windows1251,
+            VanillaVersion::SIMPLIFIED_CHINESE => SlfEncoding::Gbk,
+            VanillaVersion::DUTCH
+            | VanillaVersion::ENGLISH
+            | VanillaVersion::FRENCH
+            | VanillaVersion::GERMAN
+            | VanillaVersion::ITALIAN => SlfEncoding::Windows1252,
+        }
+    }
+}
+
+/// Returns the offset after the header and the data of all entries.
+fn end_of_data(entries: &[SlfEntry]) -> Result<u32> {
+    entries.iter().try_fold(HEADER_BYTES, |end_of_data, entry| {
+        let end_of_entry = entry.offset.checked_add(entry.length).ok_or_else(|| {
+            Error::new(
+                InvalidData,
+                format!(
+                    "entry {:?} ends after the end of the archive",
+                    entry.file_path
+                ),
+            )
+        })?;
+        Ok(end_of_data.max(end_of_entry))
+    })
+}
+
+/// Returns the ranges of consecutive non-ASCII bytes of a string.
+fn non_ascii_runs(string: &[u8]) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut end = 0;
+    while let Some(start) = string[end..].iter().position(|x| !x.is_ascii()) {
+        let start = end + start;
+        end = string[start..]
+            .iter()
+            .position(|x| x.is_ascii())
+            .map_or(string.len(), |x| start + x);
+        runs.push(start..end);
+    }
+    runs
+}
+
+/// Returns true for CJK ideographs, punctuation and fullwidth forms.
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{303F}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{FF00}'..='\u{FFEF}')
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
     use std::io::Cursor;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
 
     use crate::file_formats::slf::{
-        ENTRY_BYTES, HEADER_BYTES, SlfEntry, SlfEntryState, SlfHeader, UNIX_EPOCH_AS_FILETIME,
+        ENTRY_BYTES, HEADER_BYTES, SlfBuilder, SlfEncoding, SlfEntry, SlfEntryState, SlfHeader,
+        SlfProblem, SlfValidator, UNIX_EPOCH_AS_FILETIME, compact_archive,
     };
+    use crate::fs;
+    use crate::unicode::Nfc;
+    use crate::vfs::VfsLayer;
+    use crate::vfs::mem::MemFs;
+    use crate::vfs::slf::SlfFs;
 
     #[inline]
     fn assert_ok<OK, ERR: Debug>(result: Result<OK, ERR>) -> OK {
@@ -418,6 +1416,7 @@ mod tests {
             sort: 0xFFFF,
             version: 0x0200,
             contains_subdirectories: 1,
+            encoding: SlfEncoding::Utf8,
         };
         let test_data = b"file contents\n".to_vec();
         let test_data_len = test_data.len() as u32;
@@ -459,4 +1458,358 @@ mod tests {
             assert_eq!(test_data, data);
         }
     }
+
+    #[test]
+    fn entries_ending_after_the_maximum_offset() {
+        let header = SlfHeader {
+            library_name: "test library".to_string(),
+            library_path: "libdir\\".to_string(),
+            num_entries: 1,
+            ok_entries: 1,
+            sort: 0xFFFF,
+            version: 0x0200,
+            contains_subdirectories: 0,
+            encoding: SlfEncoding::Utf8,
+        };
+        let entries = vec![SlfEntry {
+            file_path: "file.ext".to_string(),
+            offset: u32::MAX - 1,
+            length: 2,
+            state: SlfEntryState::Ok,
+            file_time: UNIX_EPOCH_AS_FILETIME,
+        }];
+        let mut buf: Vec<u8> = Vec::new();
+        let mut f = Cursor::new(&mut buf);
+        let err = header.entries_to_output(&mut f, &entries).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(f.get_ref().is_empty());
+    }
+
+    #[test]
+    fn file_time_conversion() {
+        let time = UNIX_EPOCH + Duration::new(1_000_000_000, 123_456_700);
+        let file_time = SlfEntry::file_time_from_system_time(time);
+        assert_eq!(file_time, UNIX_EPOCH_AS_FILETIME + 10_000_000_001_234_567);
+        let entry = SlfEntry {
+            file_time,
+            ..SlfEntry::default()
+        };
+        assert_eq!(entry.to_system_time(), Some(time));
+        assert_eq!(
+            SlfEntry::file_time_from_system_time(UNIX_EPOCH),
+            UNIX_EPOCH_AS_FILETIME
+        );
+    }
+
+    #[test]
+    fn build_archive() {
+        let temp_dir = assert_ok(fs::TempDir::new());
+        assert_ok(fs::create_dir_all(temp_dir.path().join("Maps")));
+        assert_ok(fs::write(temp_dir.path().join("Maps/b9.dat"), b"b9"));
+        let mem_fs = MemFs::new("mem");
+        assert_ok(mem_fs.insert(&Nfc::caseless_path("tilesets/0/a.sti"), b"sti".to_vec()));
+        let layer: Arc<dyn VfsLayer> = Arc::new(mem_fs);
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+
+        let mut builder = SlfBuilder::new("MAPS.SLF", "/mods/test/");
+        assert_eq!(assert_ok(builder.add_dir(temp_dir.path())), 1);
+        assert_eq!(
+            assert_ok(builder.add_layer_dir(&layer, &Nfc::caseless_path("tilesets"))),
+            1
+        );
+        assert_ok(builder.add_data("A.txt", b"text".to_vec(), Some(time)));
+        assert!(builder.add_data("a.TXT", vec![], None).is_err());
+        assert!(builder.add_data("../a.txt", vec![], None).is_err());
+        let mut f = Cursor::new(Vec::new());
+        let written = assert_ok(builder.write(&mut f));
+
+        let header = assert_ok(SlfHeader::from_input(&mut f));
+        assert_eq!(header.library_name, "MAPS.SLF");
+        assert_eq!(header.library_path, "mods\\test\\");
+        assert_eq!((header.num_entries, header.ok_entries), (3, 3));
+        assert_eq!(header.contains_subdirectories, 1);
+        let entries = assert_ok(header.entries_from_input(&mut f));
+        assert_eq!(entries, written);
+        let paths: Vec<_> = entries.iter().map(|x| x.file_path.as_str()).collect();
+        assert_eq!(paths, vec!["0\\a.sti", "A.txt", "Maps\\b9.dat"]);
+        assert_eq!(entries[0].offset, HEADER_BYTES);
+        assert_eq!(entries[1].to_system_time(), Some(time));
+        assert_ne!(entries[2].file_time, 0);
+        let data: Vec<_> = entries
+            .iter()
+            .map(|x| assert_ok(x.data_from_input(&mut f)))
+            .collect();
+        assert_eq!(
+            data,
+            vec![b"sti".to_vec(), b"text".to_vec(), b"b9".to_vec()]
+        );
+
+        assert!(
+            SlfBuilder::new("EMPTY.SLF", "")
+                .write(&mut Cursor::new(Vec::new()))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn build_archive_sort_order() {
+        let mut builder = SlfBuilder::new("TEST.SLF", "");
+        assert_ok(builder.add_data("bc.txt", b"1".to_vec(), None));
+        assert_ok(builder.add_data("B_C.txt", b"2".to_vec(), None));
+        assert_ok(builder.add_data("b\\c.txt", b"3".to_vec(), None));
+        let written = assert_ok(builder.write(&mut Cursor::new(Vec::new())));
+
+        // like _stricmp, '\\' < '_' < 'c' and not 'C' < '\\' < '_'
+        let paths: Vec<_> = written.iter().map(|x| x.file_path.as_str()).collect();
+        assert_eq!(paths, vec!["b\\c.txt", "B_C.txt", "bc.txt"]);
+        assert!(
+            written
+                .windows(2)
+                .all(|x| x[0].sort_key() < x[1].sort_key())
+        );
+    }
+
+    #[test]
+    fn append_and_compact() {
+        let mut builder = SlfBuilder::new("TEST.SLF", "");
+        assert_ok(builder.add_data("a.txt", b"old a".to_vec(), None));
+        assert_ok(builder.add_data("b.txt", b"b".to_vec(), None));
+        let mut f = Cursor::new(Vec::new());
+        assert_ok(builder.write(&mut f));
+
+        let mut update = SlfBuilder::new("", "");
+        assert_ok(update.add_data("A.TXT", b"new a".to_vec(), None));
+        assert_ok(update.add_data("dir/c.txt", b"c".to_vec(), None));
+        let appended = assert_ok(update.append_to(&mut f));
+
+        let header = assert_ok(SlfHeader::from_input(&mut f));
+        assert_eq!(header.library_name, "TEST.SLF");
+        assert_eq!((header.num_entries, header.ok_entries), (4, 3));
+        assert_eq!(header.contains_subdirectories, 1);
+        let entries = assert_ok(header.entries_from_input(&mut f));
+        assert_eq!(entries, appended);
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|x| {
+                (
+                    x.file_path.as_str(),
+                    x.state,
+                    assert_ok(x.data_from_input(&mut f)),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.txt", SlfEntryState::Old, b"old a".to_vec()),
+                ("A.TXT", SlfEntryState::Ok, b"new a".to_vec()),
+                ("b.txt", SlfEntryState::Ok, b"b".to_vec()),
+                ("dir\\c.txt", SlfEntryState::Ok, b"c".to_vec()),
+            ]
+        );
+
+        let mut compacted = Cursor::new(Vec::new());
+        let compacted_entries = assert_ok(compact_archive(&mut f, &mut compacted));
+        let header = assert_ok(SlfHeader::from_input(&mut compacted));
+        assert_eq!((header.num_entries, header.ok_entries), (3, 3));
+        let entries = assert_ok(header.entries_from_input(&mut compacted));
+        assert_eq!(entries, compacted_entries);
+        assert_eq!(
+            entries.iter().map(|x| x.state).collect::<Vec<_>>(),
+            vec![SlfEntryState::Ok; 3]
+        );
+        assert_eq!(
+            assert_ok(entries[0].data_from_input(&mut compacted)),
+            b"new a".to_vec()
+        );
+        assert_eq!(
+            compacted.get_ref().len(),
+            (HEADER_BYTES + 7 + 3 * ENTRY_BYTES) as usize
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let mut builder = SlfBuilder::new("TEST.SLF", "");
+        assert_ok(builder.add_data("b_c.txt", b"b".to_vec(), None));
+        assert_ok(builder.add_data("bc.txt", b"b".to_vec(), None));
+        assert_ok(builder.add_data("b\\c.txt", b"b".to_vec(), None));
+        let mut f = Cursor::new(Vec::new());
+        assert_ok(builder.write(&mut f));
+        assert_eq!(assert_ok(SlfValidator::default().validate(&mut f)), vec![]);
+
+        let entry = |file_path: &str, offset, length, state| SlfEntry {
+            file_path: file_path.to_string(),
+            offset,
+            length,
+            state,
+            file_time: 0,
+        };
+        let entries = vec![
+            entry("b.txt", HEADER_BYTES, 10, SlfEntryState::Ok),
+            entry("a.txt", HEADER_BYTES + 5, 10, SlfEntryState::Ok),
+            entry("A.TXT", HEADER_BYTES + 15, 5, SlfEntryState::Ok),
+            entry("c.txt", 100, 5, SlfEntryState::Ok),
+            entry("d.txt", 0, 0, SlfEntryState::Unknown(0x42)),
+        ];
+        let header = SlfHeader {
+            num_entries: 5,
+            ok_entries: 5,
+            ..SlfHeader::default()
+        };
+        let mut f = Cursor::new(Vec::new());
+        assert_ok(header.to_output(&mut f));
+        assert_ok(header.entries_to_output(&mut f, &entries));
+        assert_eq!(
+            assert_ok(SlfValidator::default().validate(&mut f)),
+            vec![
+                SlfProblem::OkEntriesMismatch {
+                    expected: 5,
+                    actual: 4
+                },
+                SlfProblem::Unsorted { index: 1 },
+                SlfProblem::DuplicatePath { index: 2, other: 1 },
+                SlfProblem::DataOutOfBounds {
+                    index: 3,
+                    offset: 100,
+                    length: 5
+                },
+                SlfProblem::UnknownState {
+                    index: 4,
+                    state: 0x42
+                },
+                SlfProblem::OverlappingData { index: 1, other: 0 },
+            ]
+        );
+        let validator = SlfValidator {
+            check_order: false,
+            ..SlfValidator::default()
+        };
+        assert!(
+            !assert_ok(validator.validate(&mut f)).contains(&SlfProblem::Unsorted { index: 1 })
+        );
+
+        // truncated archives
+        let data = f.into_inner();
+        let problems = assert_ok(
+            SlfValidator::default().validate(&mut Cursor::new(&data[..HEADER_BYTES as usize])),
+        );
+        assert_eq!(
+            problems,
+            vec![SlfProblem::InvalidNumEntries {
+                num_entries: 5,
+                file_len: u64::from(HEADER_BYTES)
+            }]
+        );
+        let problems = assert_ok(SlfValidator::default().validate(&mut Cursor::new(&data[..10])));
+        assert!(matches!(problems[..], [SlfProblem::InvalidHeader(_)]));
+    }
+
+    #[test]
+    fn legacy_encoding() {
+        let mut builder =
+            SlfBuilder::new_with_encoding("КАРТЫ.SLF", "Карты", SlfEncoding::Windows1251);
+        assert_ok(builder.add_data("Сектор/A9.dat", b"a9".to_vec(), None));
+        assert!(builder.add_data("地图.dat", vec![], None).is_err());
+        let mut f = Cursor::new(Vec::new());
+        assert_ok(builder.write(&mut f));
+        assert_eq!(&f.get_ref()[..10], b"\xCA\xC0\xD0\xD2\xDB.SLF\0");
+
+        // detected
+        let header = assert_ok(SlfHeader::from_input(&mut f));
+        assert_eq!(header.encoding, SlfEncoding::Windows1251);
+        assert_eq!(header.library_name, "КАРТЫ.SLF");
+        assert_eq!(header.library_path, "Карты\\");
+        let entries = assert_ok(header.entries_from_input(&mut f));
+        assert_eq!(entries[0].file_path, "Сектор\\A9.dat");
+
+        // configured, only used when the strings are not valid UTF-8
+        let header = assert_ok(SlfHeader::from_input_with_encoding(
+            &mut f,
+            Some(SlfEncoding::Windows1250),
+        ));
+        assert_eq!(header.encoding, SlfEncoding::Windows1250);
+        assert_eq!(header.library_name, "ĘŔĐŇŰ.SLF");
+        let mut builder = SlfBuilder::new("КАРТЫ.SLF", "");
+        assert_ok(builder.add_data("a.dat", vec![], None));
+        let mut utf8 = Cursor::new(Vec::new());
+        assert_ok(builder.write(&mut utf8));
+        let header = assert_ok(SlfHeader::from_input_with_encoding(
+            &mut utf8,
+            Some(SlfEncoding::Windows1251),
+        ));
+        assert_eq!(header.encoding, SlfEncoding::Utf8);
+        assert_eq!(header.library_name, "КАРТЫ.SLF");
+
+        // appended files use the encoding of the archive
+        let mut builder = SlfBuilder::new("", "");
+        assert_ok(builder.add_data("Сектор/B9.dat", b"b9".to_vec(), None));
+        assert_ok(builder.append_to(&mut f));
+        let header = assert_ok(SlfHeader::from_input(&mut f));
+        assert_eq!(header.encoding, SlfEncoding::Windows1251);
+        let entries = assert_ok(header.entries_from_input(&mut f));
+        assert_eq!(entries[1].file_path, "Сектор\\B9.dat");
+
+        // mounted with caseless paths
+        let mem_fs = MemFs::new("mem");
+        assert_ok(mem_fs.insert(&Nfc::caseless_path("maps.slf"), f.into_inner()));
+        let slf_file = assert_ok(mem_fs.open(&Nfc::caseless_path("maps.slf")));
+        let slf_fs = assert_ok(SlfFs::new(slf_file));
+        let mut data = Vec::new();
+        let mut file = assert_ok(slf_fs.open(&Nfc::caseless_path("КАРТЫ/сектор/b9.DAT")));
+        assert_ok(file.read_to_end(&mut data));
+        assert_eq!(data, b"b9");
+
+        let polish: &[u8] = b"Cz\xEA\x9C\xE6\\mapa.dat";
+        let russian: &[u8] = b"\xCA\xE0\xF0\xF2\xE0\\a9.dat";
+        let chinese: &[u8] = b"\xB5\xD8\xCD\xBC.dat";
+        let german: &[u8] = b"Gr\xFC\xDFe\\Stra\xDFe.dat";
+        assert_eq!(SlfEncoding::detect([polish]), SlfEncoding::Windows1250);
+        assert_eq!(SlfEncoding::detect([german]), SlfEncoding::Windows1252);
+        assert_eq!(
+            SlfEncoding::detect([german, polish]),
+            SlfEncoding::Windows1250
+        );
+        assert_eq!(SlfEncoding::detect([russian]), SlfEncoding::Windows1251);
+        assert_eq!(SlfEncoding::detect([chinese]), SlfEncoding::Gbk);
+
+        // undecodable strings are problems
+        let mut builder = SlfBuilder::new_with_encoding("КАРТЫ.SLF", "", SlfEncoding::Windows1251);
+        assert_ok(builder.add_data("a.dat", vec![], None));
+        assert_ok(builder.add_data("Карта.dat", vec![], None));
+        let mut f = Cursor::new(Vec::new());
+        assert_ok(builder.write(&mut f));
+        let validator = SlfValidator {
+            legacy_encoding: Some(SlfEncoding::Gbk),
+            ..SlfValidator::default()
+        };
+        assert_eq!(
+            assert_ok(validator.validate(&mut f)),
+            vec![
+                SlfProblem::UndecodableString {
+                    index: None,
+                    encoding: SlfEncoding::Gbk
+                },
+                SlfProblem::UndecodableString {
+                    index: Some(1),
+                    encoding: SlfEncoding::Gbk
+                },
+            ]
+        );
+        assert!(assert_ok(SlfValidator::default().validate(&mut f)).is_empty());
+        assert_eq!(
+            SlfEncoding::detect(["Сектор".as_bytes()]),
+            SlfEncoding::Utf8
+        );
+        assert_eq!(assert_ok(SlfEncoding::Gbk.decode(chinese)), "地图.dat");
+        assert_eq!(
+            assert_ok(SlfEncoding::Windows1250.decode(polish)),
+            "Część\\mapa.dat"
+        );
+        assert_eq!(
+            assert_ok(SlfEncoding::Windows1252.decode(german)),
+            "Grüße\\Straße.dat"
+        );
+        assert!(SlfEncoding::Utf8.decode(russian).is_err());
+    }
 }
@@ -44,11 +44,11 @@ impl StciRgb888 {
 }
 
 const fn max_value_565_shift_right(mask: u32, shift: u32) -> u32 {
-    u32::MAX & (mask >> shift)
+    mask >> shift
 }
 
 const fn max_value_565_shift_left(mask: u32, shift: u32) -> u32 {
-    u32::MAX & (mask << shift)
+    mask << shift
 }
 
 impl From<StciRgb565> for StciRgb888 {
@@ -65,6 +65,17 @@ impl From<StciRgb565> for StciRgb888 {
     }
 }
 
+impl From<StciRgb888> for StciRgb565 {
+    /// This conversion rounds to the nearest Rgb565 color.
+    /// It is the inverse of the conversion to Rgb888, so converting there and back keeps the color.
+    fn from(value: StciRgb888) -> Self {
+        let r = (u16::from(value.0) * 31 + 127) / 255;
+        let g = (u16::from(value.1) * 63 + 127) / 255;
+        let b = (u16::from(value.2) * 31 + 127) / 255;
+        StciRgb565((r << 11) | (g << 5) | b)
+    }
+}
+
 /// Rgb color representation with 5 bits red, 6 bits green, 5 bits blue
 ///
 /// This is used in rgb STCI images as pixel data
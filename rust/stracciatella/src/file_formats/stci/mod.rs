@@ -70,9 +70,12 @@
 //!
 //! # Indexed Data Section Structure
 //!
-//! The data section for indexed images contains all pixel data for each sub image in consecutive order.
-//! The data is ETRLE compressed for each sub image. When decompressed it can be used to index into the
-//! palette of the STCI image.
+//! The data section for indexed images contains all pixel data for each sub image, its size is the stored size
+//! in the header. The data is ETRLE compressed for each sub image. When decompressed it can be used to index into
+//! the palette of the STCI image.
+//!
+//! The assets shipped with Jagged Alliance 2 store the data in the order of the sub images, but the data offset
+//! and length of a sub image can point anywhere in the data section. Sub images with identical data can share it.
 //!
 //! # RGB Data Section Structure
 //!
@@ -92,10 +95,16 @@
 //! - 1 byte unsigned int for the number of frames for animation
 //! - 1 byte unsigned int for flags on the sub image
 //! - 6 unused bytes
+//!
+//! # PNG Conversion
+//!
+//! RGB images are converted to truecolor PNG images, indexed images to palette PNG images with a
+//! JSON sidecar for the metadata, see the `png` module.
 
 use super::{StracciatellaReadExt, StracciatellaWriteExt};
 use bitflags::bitflags;
 use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
 use std::io::{
     Cursor, Error,
     ErrorKind::{InvalidData, InvalidInput, UnexpectedEof},
@@ -105,6 +114,7 @@ use std::io::{
 mod color;
 pub mod etrle;
 pub mod indexed;
+pub mod png;
 pub mod rgb;
 
 pub use color::*;
@@ -112,6 +122,7 @@ use indexed::*;
 use rgb::*;
 
 pub use indexed::{StciAppData, StciPalette, StciSubImage};
+pub use png::{StciPngAppData, StciPngSidecar, StciPngSubImage};
 
 /// Representation of the size part of the STCI header.
 ///
@@ -392,6 +403,15 @@ impl StciHeader {
     }
 }
 
+/// Options for writing STCI images
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StciWriteOptions {
+    /// Store the data of sub images with identical compressed data only once.
+    ///
+    /// The sub image headers of those sub images point to the same data.
+    pub deduplicate_sub_images: bool,
+}
+
 /// Complete representation of an STCI image
 #[derive(Debug, PartialEq)]
 pub enum Stci {
@@ -424,31 +444,52 @@ where
 
 fn decode_sub_images<T>(
     subimage_headers: Vec<StciSubImageHeader>,
+    data_size: u32,
     decode_app_data: bool,
     input: &mut T,
 ) -> Result<Vec<StciSubImage>>
 where
     T: Read,
 {
+    // The data section is read as a whole like the game does, the sub images
+    // can be stored in any order and can share their data
+    let mut data_section = Vec::new();
+    input
+        .take(u64::from(data_size))
+        .read_to_end(&mut data_section)?;
+    if data_section.len() != data_size as usize {
+        return Err(Error::new(
+            UnexpectedEof,
+            format!(
+                "expected to read {} bytes for the data section, got {}",
+                data_size,
+                data_section.len(),
+            ),
+        ));
+    }
+
     let number_of_subimages = subimage_headers.len();
     let mut sub_images = Vec::with_capacity(number_of_subimages);
-    let mut current_index: u32 = 0;
-    for header in subimage_headers.iter() {
-        // We expect the images to be stored in the same order as the headers.
-        // This seems to be true for all STCI shipped with Jagged Alliance 2
-        if header.data_offset != current_index {
-            return Err(Error::new(
-                InvalidInput,
-                "only supporting continoous offsets",
-            ));
-        }
+    for (index, header) in subimage_headers.iter().enumerate() {
+        let start = header.data_offset as usize;
+        let compressed_data = start
+            .checked_add(header.data_length as usize)
+            .and_then(|end| data_section.get(start..end))
+            .ok_or_else(|| {
+                Error::new(
+                    InvalidData,
+                    format!(
+                        "data of sub image {} at offset {} with length {} is outside of the data section",
+                        index, header.data_offset, header.data_length,
+                    ),
+                )
+            })?;
 
-        let data_length = header.data_length as usize;
         let expected_decompressed_length =
             header.dimensions.0 as usize * header.dimensions.1 as usize;
         let mut data = Vec::with_capacity(expected_decompressed_length);
         let mut output = Cursor::new(&mut data);
-        let mut input = input.take(data_length as u64);
+        let mut input = compressed_data;
         etrle::etrle_decompress(&mut input, &mut output)?;
 
         // Check whether we decompressed exactly the number of bytes we need
@@ -468,8 +509,6 @@ where
             app_data: None,
             data,
         });
-
-        current_index += header.data_length;
     }
     // App data is optional
     if decode_app_data {
@@ -545,8 +584,12 @@ impl Stci {
                     input,
                     format_specific_header.number_of_images as usize,
                 )?;
-                let sub_images =
-                    decode_sub_images(sub_image_headers, header.app_data_size > 0, input)?;
+                let sub_images = decode_sub_images(
+                    sub_image_headers,
+                    header.size.stored,
+                    header.app_data_size > 0,
+                    input,
+                )?;
                 Stci::Indexed {
                     palette: Box::new(palette),
                     sub_images,
@@ -558,6 +601,18 @@ impl Stci {
     /// Write a STCI image to output.
     #[allow(dead_code)]
     pub fn to_output<T>(&self, output: &mut T) -> Result<()>
+    where
+        T: Write,
+    {
+        self.to_output_with_options(output, &StciWriteOptions::default())
+    }
+
+    /// Write a STCI image to output with options.
+    pub fn to_output_with_options<T>(
+        &self,
+        output: &mut T,
+        options: &StciWriteOptions,
+    ) -> Result<()>
     where
         T: Write,
     {
@@ -621,10 +676,25 @@ impl Stci {
                         u32::from(sub_image.dimensions.0) * u32::from(sub_image.dimensions.1)
                     })
                     .sum();
-                let stored_size: u32 = compressed_sub_image_bytes
-                    .iter()
-                    .map(|v| v.len() as u32)
-                    .sum();
+
+                // Offsets of the data of each sub image and the data that is stored
+                let mut data_offsets = Vec::with_capacity(number_of_images);
+                let mut stored_data: Vec<&[u8]> = Vec::with_capacity(number_of_images);
+                let mut offset_by_data: HashMap<&[u8], u32> = HashMap::new();
+                let mut stored_size: u32 = 0;
+                for compressed_data in &compressed_sub_image_bytes {
+                    let compressed_data = compressed_data.as_slice();
+                    if options.deduplicate_sub_images {
+                        if let Some(offset) = offset_by_data.get(compressed_data) {
+                            data_offsets.push(*offset);
+                            continue;
+                        }
+                        offset_by_data.insert(compressed_data, stored_size);
+                    }
+                    data_offsets.push(stored_size);
+                    stored_data.push(compressed_data);
+                    stored_size += compressed_data.len() as u32;
+                }
                 let header = StciHeader::Indexed {
                     header: StciCommonHeader {
                         size: StciSize {
@@ -642,24 +712,19 @@ impl Stci {
                 header.to_output(output)?;
                 palette.to_output(output)?;
 
-                let mut current_offset: u32 = 0;
                 for (index, sub_image) in sub_images.iter().enumerate() {
-                    let compressed_data = &compressed_sub_image_bytes[index];
-                    let compressed_data_len = compressed_data.len() as u32;
                     let sub_image_header = StciSubImageHeader {
-                        data_offset: current_offset,
-                        data_length: compressed_data_len,
+                        data_offset: data_offsets[index],
+                        data_length: compressed_sub_image_bytes[index].len() as u32,
                         dimensions: sub_image.dimensions,
                         offset: sub_image.offset,
                     };
 
                     sub_image_header.to_output(output)?;
-
-                    current_offset += compressed_data_len;
                 }
 
-                for sub_image_bytes in compressed_sub_image_bytes {
-                    output.write_all(&sub_image_bytes)?;
+                for sub_image_bytes in stored_data {
+                    output.write_all(sub_image_bytes)?;
                 }
 
                 if app_data_size > 0 {
@@ -908,4 +973,113 @@ mod tests {
         );
         assert_eq!(read_stci, stci)
     }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        etrle::etrle_compress(&mut &data[..], &mut Cursor::new(&mut compressed))
+            .expect("should be possible to compress data");
+        compressed
+    }
+
+    fn sub_image(dimensions: (u16, u16), data: Vec<u8>) -> StciSubImage {
+        StciSubImage {
+            offset: (0, 0),
+            dimensions,
+            data,
+            app_data: None,
+        }
+    }
+
+    #[test]
+    fn test_stci_indexed_deduplicated() {
+        let stci = Stci::Indexed {
+            palette: Box::new(StciPalette::default()),
+            sub_images: vec![
+                sub_image((2, 1), vec![1, 2]),
+                sub_image((1, 1), vec![3]),
+                sub_image((2, 1), vec![1, 2]),
+            ],
+        };
+        let mut v = Vec::new();
+        stci.to_output(&mut v)
+            .expect("should be possible to write stci");
+        let mut deduplicated = Vec::new();
+        let options = StciWriteOptions {
+            deduplicate_sub_images: true,
+        };
+        stci.to_output_with_options(&mut deduplicated, &options)
+            .expect("should be possible to write stci");
+        assert_eq!(deduplicated.len(), v.len() - compress(&[1, 2]).len());
+
+        let mut c = Cursor::new(&deduplicated[64 + 768..]);
+        let headers = decode_sub_image_headers(&mut c, 3)
+            .expect("should be possible to read sub image headers");
+        assert_eq!(headers[0].data_offset, headers[2].data_offset);
+        assert_ne!(headers[0].data_offset, headers[1].data_offset);
+
+        let read_stci = Stci::from_input(&mut deduplicated.as_slice())
+            .expect("should be possible to read stci");
+        assert_eq!(read_stci, stci)
+    }
+
+    #[test]
+    fn test_stci_indexed_reordered_data() {
+        let first = compress(&[1, 2]);
+        let second = compress(&[3]);
+        let write_stci = |data_offsets: (u32, u32)| {
+            let mut v = Vec::new();
+            v.extend_from_slice(b"STCI");
+            let header = StciHeader::Indexed {
+                header: StciCommonHeader {
+                    size: StciSize {
+                        original: 3,
+                        stored: (first.len() + second.len()) as u32,
+                    },
+                    ..StciCommonHeader::indexed()
+                },
+                format_specific_header: StciHeaderIndexed {
+                    number_of_images: 2,
+                    ..StciHeaderIndexed::default()
+                },
+            };
+            header
+                .to_output(&mut v)
+                .expect("should be possible to write header");
+            StciPalette::default()
+                .to_output(&mut v)
+                .expect("should be possible to write palette");
+            for (data_offset, data, dimensions) in [
+                (data_offsets.0, &first, (2, 1)),
+                (data_offsets.1, &second, (1, 1)),
+            ] {
+                let sub_image_header = StciSubImageHeader {
+                    data_offset,
+                    data_length: data.len() as u32,
+                    offset: (0, 0),
+                    dimensions,
+                };
+                sub_image_header
+                    .to_output(&mut v)
+                    .expect("should be possible to write sub image header");
+            }
+            // The data of the second sub image is stored first
+            v.extend_from_slice(&second);
+            v.extend_from_slice(&first);
+            v
+        };
+
+        let v = write_stci((second.len() as u32, 0));
+        let read_stci =
+            Stci::from_input(&mut v.as_slice()).expect("should be possible to read stci");
+        assert_eq!(
+            read_stci,
+            Stci::Indexed {
+                palette: Box::new(StciPalette::default()),
+                sub_images: vec![sub_image((2, 1), vec![1, 2]), sub_image((1, 1), vec![3])],
+            }
+        );
+
+        let v = write_stci((second.len() as u32 + 1, 0));
+        assert!(Stci::from_input(&mut v.as_slice()).is_err());
+    }
 }
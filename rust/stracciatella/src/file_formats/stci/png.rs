@@ -0,0 +1,560 @@
+//! This module contains the conversion of STCI images to and from PNG images
+//!
+//! RGB STCI images are converted to truecolor PNG images. The RGB565 colors are converted to RGB888
+//! and back, converting an image there and back keeps all colors.
+//!
+//! Indexed STCI images are converted to one palette PNG image per sub image. The PNG images have the
+//! palette of the STCI image and index 0 is transparent. A JSON sidecar contains the palette, the
+//! offsets, the app data and the PNG file names of the sub images:
+//!
+//! ```json
+//! {
+//!   "palette": ["#000000", "#ff00ff", ...],
+//!   "sub_images": [
+//!     { "file": "door_0.png", "offset": [-12, 4], "app_data": { "number_of_frames": 4, "flags": ["animated_tile"], ... } },
+//!     { "file": null, "offset": [0, 0], "dimensions": [0, 0] }
+//!   ]
+//! }
+//! ```
+//!
+//! Sub images without pixels have no PNG image, their dimensions are in the sidecar.
+//!
+//! When converting back, the pixels of the PNG images are mapped to the palette of the sidecar.
+//! Image editors may reorder or reduce the palette of a PNG image, so colors are looked up
+//! in the palette of the sidecar when the index differs. Transparent colors become index 0.
+
+use super::indexed::StciAppDataFlags;
+use super::{Stci, StciAppData, StciPalette, StciRgb565, StciRgb888, StciSubImage};
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{
+    BufReader, BufWriter, Error,
+    ErrorKind::{InvalidData, InvalidInput},
+    Read, Result, Write,
+};
+use std::path::{Path, PathBuf};
+
+/// Names of the app data flags in the sidecar
+const APP_DATA_FLAG_NAMES: [(StciAppDataFlags, &str); 6] = [
+    (StciAppDataFlags::FULL_TILE, "full_tile"),
+    (StciAppDataFlags::ANIMATED_TILE, "animated_tile"),
+    (StciAppDataFlags::DYNAMIC_TILE, "dynamic_tile"),
+    (StciAppDataFlags::INTERACTIVE_TILE, "interactive_tile"),
+    (StciAppDataFlags::IGNORES_HEIGHT, "ignores_height"),
+    (StciAppDataFlags::USES_LAND_Z, "uses_land_z"),
+];
+
+/// JSON sidecar of an indexed STCI image that was converted to PNG images
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StciPngSidecar {
+    /// Palette colors as `#rrggbb`
+    pub palette: Vec<String>,
+    /// Sub images in the order of the STCI image
+    pub sub_images: Vec<StciPngSubImage>,
+}
+
+/// Sub image in the JSON sidecar
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StciPngSubImage {
+    /// PNG file name relative to the sidecar, `None` for sub images without pixels
+    pub file: Option<String>,
+    /// Offset when the sub image is rendered
+    pub offset: (i16, i16),
+    /// Dimensions of sub images without pixels, the others have the dimensions of the PNG image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<(u16, u16)>,
+    /// Tile and animation metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_data: Option<StciPngAppData>,
+}
+
+/// App data of a sub image in the JSON sidecar, see `StciAppData`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StciPngAppData {
+    pub wall_orientation: u8,
+    pub number_of_tiles: u8,
+    pub tile_location_index: u16,
+    pub current_frame: u8,
+    pub number_of_frames: u8,
+    /// Names of the flags, e.g. `"animated_tile"`
+    pub flags: Vec<String>,
+}
+
+impl StciPngSidecar {
+    /// Reads the palette of the sidecar.
+    pub fn to_palette(&self) -> Result<StciPalette> {
+        if self.palette.len() != 256 {
+            return Err(Error::new(
+                InvalidData,
+                format!("expected 256 palette colors, got {}", self.palette.len()),
+            ));
+        }
+        let mut palette = StciPalette::default();
+        for (color, hex) in palette.colors.iter_mut().zip(&self.palette) {
+            *color = parse_color(hex)?;
+        }
+        Ok(palette)
+    }
+}
+
+impl From<&StciAppData> for StciPngAppData {
+    fn from(app_data: &StciAppData) -> Self {
+        let flags = APP_DATA_FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| app_data.flags.contains(*flag))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        Self {
+            wall_orientation: app_data.wall_orientation,
+            number_of_tiles: app_data.number_of_tiles,
+            tile_location_index: app_data.tile_location_index,
+            current_frame: app_data.current_frame,
+            number_of_frames: app_data.number_of_frames,
+            flags,
+        }
+    }
+}
+
+impl TryFrom<&StciPngAppData> for StciAppData {
+    type Error = Error;
+
+    fn try_from(app_data: &StciPngAppData) -> Result<Self> {
+        let mut flags = StciAppDataFlags::empty();
+        for name in &app_data.flags {
+            let (flag, _) = APP_DATA_FLAG_NAMES
+                .iter()
+                .find(|(_, x)| x == name)
+                .ok_or_else(|| {
+                    Error::new(InvalidData, format!("unknown app data flag {:?}", name))
+                })?;
+            flags.insert(*flag);
+        }
+        Ok(Self {
+            wall_orientation: app_data.wall_orientation,
+            number_of_tiles: app_data.number_of_tiles,
+            tile_location_index: app_data.tile_location_index,
+            current_frame: app_data.current_frame,
+            number_of_frames: app_data.number_of_frames,
+            flags,
+        })
+    }
+}
+
+impl StciSubImage {
+    /// Write the sub image as palette PNG image with index 0 transparent to output.
+    ///
+    /// Offset and app data are not part of the PNG image.
+    pub fn to_png<T>(&self, palette: &StciPalette, output: T) -> Result<()>
+    where
+        T: Write,
+    {
+        let (width, height) = self.dimensions;
+        let mut encoder = Encoder::new(output, u32::from(width), u32::from(height));
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        let colors: Vec<u8> = palette
+            .colors
+            .iter()
+            .flat_map(|x| [x.0, x.1, x.2])
+            .collect();
+        encoder.set_palette(colors);
+        // Colors without an alpha value are opaque
+        encoder.set_trns(vec![0u8]);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Read a sub image from a palette PNG image in input.
+    ///
+    /// The pixels are mapped to the palette, offset and app data are empty.
+    pub fn from_png<T>(palette: &StciPalette, input: T) -> Result<Self>
+    where
+        T: Read,
+    {
+        let mut decoder = Decoder::new(input);
+        decoder.set_transformations(Transformations::IDENTITY);
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0u8; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut data)?;
+        if frame.color_type != ColorType::Indexed || frame.bit_depth != BitDepth::Eight {
+            return Err(Error::new(
+                InvalidData,
+                format!(
+                    "expected 8 bit palette PNG image, got {:?} with {:?} bit",
+                    frame.color_type, frame.bit_depth
+                ),
+            ));
+        }
+        let dimensions = png_dimensions(frame.width, frame.height)?;
+        data.truncate(frame.buffer_size());
+
+        let info = reader.info();
+        let png_palette = info.palette.as_deref().unwrap_or_default();
+        let trns = info.trns.as_deref().unwrap_or_default();
+        let indexes: Vec<Option<u8>> = png_palette
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(index, rgb)| {
+                let color = StciRgb888(rgb[0], rgb[1], rgb[2]);
+                if trns.get(index) == Some(&0) {
+                    Some(0)
+                } else if palette.colors.get(index) == Some(&color) {
+                    Some(index as u8)
+                } else {
+                    palette
+                        .colors
+                        .iter()
+                        .position(|x| *x == color)
+                        .map(|x| x as u8)
+                }
+            })
+            .collect();
+        for pixel in data.iter_mut() {
+            *pixel = indexes
+                .get(usize::from(*pixel))
+                .copied()
+                .flatten()
+                .ok_or_else(|| {
+                    Error::new(
+                        InvalidData,
+                        format!("color {} of the PNG image is not in the palette", pixel),
+                    )
+                })?;
+        }
+
+        Ok(Self {
+            offset: (0, 0),
+            dimensions,
+            app_data: None,
+            data,
+        })
+    }
+}
+
+impl Stci {
+    /// Write an RGB STCI image as truecolor PNG image to output.
+    ///
+    /// Indexed images have multiple sub images, use `to_png_files` for them.
+    pub fn to_png<T>(&self, output: T) -> Result<()>
+    where
+        T: Write,
+    {
+        let Stci::Rgb {
+            width,
+            height,
+            data,
+        } = self
+        else {
+            return Err(Error::new(
+                InvalidInput,
+                "indexed STCI images are converted to multiple PNG images",
+            ));
+        };
+        let mut encoder = Encoder::new(output, u32::from(*width), u32::from(*height));
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        let pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|x| {
+                let color = StciRgb888::from(*x);
+                [color.0, color.1, color.2]
+            })
+            .collect();
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Read an RGB STCI image from a PNG image in input.
+    ///
+    /// The alpha channel is ignored and colors are rounded to RGB565.
+    pub fn from_png<T>(input: T) -> Result<Self>
+    where
+        T: Read,
+    {
+        let mut decoder = Decoder::new(input);
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut pixels = vec![0u8; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut pixels)?;
+        let (width, height) = png_dimensions(frame.width, frame.height)?;
+        pixels.truncate(frame.buffer_size());
+
+        let to_rgb565 = |r, g, b| StciRgb565::from(StciRgb888(r, g, b));
+        let data = match frame.color_type {
+            ColorType::Rgb => pixels
+                .chunks_exact(3)
+                .map(|x| to_rgb565(x[0], x[1], x[2]))
+                .collect(),
+            ColorType::Rgba => pixels
+                .chunks_exact(4)
+                .map(|x| to_rgb565(x[0], x[1], x[2]))
+                .collect(),
+            ColorType::Grayscale => pixels.iter().map(|x| to_rgb565(*x, *x, *x)).collect(),
+            ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .map(|x| to_rgb565(x[0], x[0], x[0]))
+                .collect(),
+            ColorType::Indexed => {
+                return Err(Error::new(InvalidData, "expected expanded PNG colors"));
+            }
+        };
+
+        Ok(Stci::Rgb {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Write the image as PNG files.
+    ///
+    /// RGB images are written to `path`. The sub images of indexed images are written to
+    /// `{stem}_{index}.png` next to `path` and the sidecar to `path` with the extension `json`.
+    /// Returns the written files.
+    pub fn to_png_files(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let (palette, sub_images) = match self {
+            Stci::Rgb { .. } => {
+                let mut output = BufWriter::new(fs::File::create(path)?);
+                self.to_png(&mut output)?;
+                output.flush()?;
+                return Ok(vec![path.to_owned()]);
+            }
+            Stci::Indexed {
+                palette,
+                sub_images,
+            } => (palette, sub_images),
+        };
+        let stem = path
+            .file_stem()
+            .map(|x| x.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::new(InvalidInput, format!("{:?} has no file name", path)))?;
+        let mut written = Vec::new();
+        let mut sidecar_sub_images = Vec::with_capacity(sub_images.len());
+        for (index, sub_image) in sub_images.iter().enumerate() {
+            let (width, height) = sub_image.dimensions;
+            let has_pixels = width > 0 && height > 0;
+            let file = has_pixels.then(|| format!("{}_{}.png", stem, index));
+            if let Some(file) = &file {
+                let png_path = path.with_file_name(file);
+                let mut output = BufWriter::new(fs::File::create(&png_path)?);
+                sub_image.to_png(palette, &mut output)?;
+                output.flush()?;
+                written.push(png_path);
+            }
+            sidecar_sub_images.push(StciPngSubImage {
+                file,
+                offset: sub_image.offset,
+                dimensions: (!has_pixels).then_some(sub_image.dimensions),
+                app_data: sub_image.app_data.as_ref().map(StciPngAppData::from),
+            });
+        }
+        let sidecar = StciPngSidecar {
+            palette: palette
+                .colors
+                .iter()
+                .map(|x| format!("#{:02x}{:02x}{:02x}", x.0, x.1, x.2))
+                .collect(),
+            sub_images: sidecar_sub_images,
+        };
+        let sidecar_path = path.with_extension("json");
+        let mut output = BufWriter::new(fs::File::create(&sidecar_path)?);
+        serde_json::to_writer_pretty(&mut output, &sidecar)?;
+        output.flush()?;
+        written.push(sidecar_path);
+        Ok(written)
+    }
+
+    /// Read an image that was written with `to_png_files`.
+    ///
+    /// The image is indexed if `path` with the extension `json` exists, otherwise it is RGB.
+    pub fn from_png_files(path: &Path) -> Result<Self> {
+        let sidecar_path = path.with_extension("json");
+        if !sidecar_path.exists() {
+            return Self::from_png(BufReader::new(fs::File::open(path)?));
+        }
+        let sidecar: StciPngSidecar =
+            serde_json::from_reader(BufReader::new(fs::File::open(&sidecar_path)?))?;
+        let palette = sidecar.to_palette()?;
+        let mut sub_images = Vec::with_capacity(sidecar.sub_images.len());
+        for sidecar_sub_image in &sidecar.sub_images {
+            let mut sub_image = match (&sidecar_sub_image.file, sidecar_sub_image.dimensions) {
+                (Some(file), _) => {
+                    let png_path = sidecar_path.with_file_name(file);
+                    let input = BufReader::new(fs::File::open(&png_path)?);
+                    StciSubImage::from_png(&palette, input).map_err(|e| {
+                        Error::new(e.kind(), format!("{}: {}", png_path.display(), e))
+                    })?
+                }
+                (None, Some(dimensions)) => StciSubImage {
+                    offset: (0, 0),
+                    dimensions,
+                    app_data: None,
+                    data: vec![0; usize::from(dimensions.0) * usize::from(dimensions.1)],
+                },
+                (None, None) => {
+                    return Err(Error::new(
+                        InvalidData,
+                        "sub images need either a file or dimensions",
+                    ));
+                }
+            };
+            sub_image.offset = sidecar_sub_image.offset;
+            sub_image.app_data = sidecar_sub_image
+                .app_data
+                .as_ref()
+                .map(StciAppData::try_from)
+                .transpose()?;
+            sub_images.push(sub_image);
+        }
+        Ok(Stci::Indexed {
+            palette: Box::new(palette),
+            sub_images,
+        })
+    }
+}
+
+/// Parses a `#rrggbb` color
+fn parse_color(hex: &str) -> Result<StciRgb888> {
+    let invalid = || {
+        Error::new(
+            InvalidData,
+            format!("expected #rrggbb color, got {:?}", hex),
+        )
+    };
+    let digits = hex
+        .strip_prefix('#')
+        .filter(|x| x.len() == 6)
+        .ok_or_else(invalid)?;
+    let value = u32::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    Ok(StciRgb888(
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ))
+}
+
+/// Checks that the dimensions of a PNG image fit in an STCI image
+fn png_dimensions(width: u32, height: u32) -> Result<(u16, u16)> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(Error::new(
+            InvalidData,
+            format!("PNG image of {}x{} is too large", width, height),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::TempDir;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_rgb565_round_trip() {
+        for value in 0..=u16::MAX {
+            let color = StciRgb565(value);
+            assert_eq!(StciRgb565::from(StciRgb888::from(color)), color);
+        }
+    }
+
+    #[test]
+    fn test_rgb_png() {
+        let stci = Stci::Rgb {
+            width: 3,
+            height: 2,
+            data: [0, 0xF800, 0x7E0, 0x1F, 0x1234, 0xFFFF]
+                .into_iter()
+                .map(StciRgb565)
+                .collect(),
+        };
+        let mut png = Vec::new();
+        stci.to_png(&mut png)
+            .expect("should be possible to write png");
+        let read_stci = Stci::from_png(png.as_slice()).expect("should be possible to read png");
+        assert_eq!(read_stci, stci)
+    }
+
+    #[test]
+    fn test_indexed_png_files() {
+        let mut palette = StciPalette::default();
+        for (index, color) in palette.colors.iter_mut().enumerate() {
+            *color = StciRgb888(index as u8, 255 - index as u8, 7);
+        }
+        let stci = Stci::Indexed {
+            palette: Box::new(palette.clone()),
+            sub_images: vec![
+                StciSubImage {
+                    offset: (-12, 4),
+                    dimensions: (3, 2),
+                    data: vec![0, 1, 2, 255, 0, 3],
+                    app_data: Some(StciAppData {
+                        wall_orientation: 1,
+                        number_of_tiles: 2,
+                        tile_location_index: 3,
+                        current_frame: 0,
+                        number_of_frames: 4,
+                        flags: StciAppDataFlags::ANIMATED_TILE | StciAppDataFlags::USES_LAND_Z,
+                    }),
+                },
+                StciSubImage {
+                    offset: (0, 0),
+                    dimensions: (0, 0),
+                    data: vec![],
+                    app_data: Some(StciAppData {
+                        wall_orientation: 0,
+                        number_of_tiles: 0,
+                        tile_location_index: 0,
+                        current_frame: 0,
+                        number_of_frames: 0,
+                        flags: StciAppDataFlags::empty(),
+                    }),
+                },
+            ],
+        };
+        let temp_dir = TempDir::new().expect("temp dir");
+        let path = temp_dir.path().join("door.png");
+        let written = stci
+            .to_png_files(&path)
+            .expect("should be possible to write png files");
+        assert_eq!(
+            written,
+            vec![
+                temp_dir.path().join("door_0.png"),
+                temp_dir.path().join("door.json")
+            ]
+        );
+        let read_stci = Stci::from_png_files(&path).expect("should be possible to read png files");
+        assert_eq!(read_stci, stci);
+
+        // An editor reordered the palette and dropped the transparency
+        let mut encoder = Encoder::new(fs::File::create(&written[0]).expect("png file"), 3, 2);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_palette(vec![2, 253, 7, 0, 255, 7, 1, 254, 7, 255, 0, 7, 3, 252, 7]);
+        let mut writer = encoder.write_header().expect("png header");
+        writer
+            .write_image_data(&[1, 2, 0, 3, 1, 4])
+            .expect("png data");
+        writer.finish().expect("png finish");
+        let read_stci = Stci::from_png_files(&path).expect("should be possible to read png files");
+        assert_eq!(read_stci, stci);
+
+        let sub_image = StciSubImage {
+            offset: (0, 0),
+            dimensions: (1, 1),
+            data: vec![1],
+            app_data: None,
+        };
+        let mut png = Vec::new();
+        sub_image
+            .to_png(&palette, &mut png)
+            .expect("should be possible to write png");
+        assert!(StciSubImage::from_png(&StciPalette::default(), Cursor::new(png)).is_err());
+    }
+}
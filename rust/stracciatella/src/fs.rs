@@ -19,7 +19,9 @@ pub use std::fs::OpenOptions;
 pub use std::fs::create_dir;
 pub use std::fs::create_dir_all;
 pub use std::fs::metadata;
+pub use std::fs::read;
 pub use std::fs::read_dir;
+pub use std::fs::remove_dir;
 pub use std::fs::set_permissions;
 pub use std::fs::write;
 
@@ -243,6 +245,28 @@ pub fn free_space(path: &Path) -> io::Result<u64> {
     Err(io::Error::other("not implemented"))
 }
 
+/// Reads from a file at an offset without using or changing the position of the file.
+///
+/// Concurrent reads of the same file do not need to be synchronized.
+/// Fails with `ErrorKind::Unsupported` on targets without positional reads.
+#[allow(unreachable_code, unused_variables)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+
+        return file.read_at(buf, offset);
+    }
+    #[cfg(windows)]
+    {
+        // seek_read moves the position of the file, which is never used for positional reads
+        use std::os::windows::fs::FileExt;
+
+        return file.seek_read(buf, offset);
+    }
+    Err(io::ErrorKind::Unsupported.into())
+}
+
 /// Cleans a filename from special characters, so it can be used safely for the filesystem
 /// Note that the filename should not contain the extension
 pub fn clean_basename<T: AsRef<Path>>(basename: T) -> PathBuf {
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::io;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 mod mod_manifest;
@@ -8,11 +9,17 @@ use mod_manifest::ModManifestJson;
 use regex::Regex;
 
 use crate::config::EngineOptions;
+use crate::unicode::Nfc;
+use crate::vfs::VfsLayer;
+use crate::vfs::dir::DirFsFile;
+use crate::vfs::zip::ZipFs;
 
 /// Path to a mod
 #[derive(Debug, Clone)]
 pub enum ModPath {
     Path(PathBuf),
+    /// Path inside of a zip archive (archive, path inside the archive)
+    ZipPath(PathBuf, PathBuf),
     #[cfg(target_os = "android")]
     AndroidAssetPath(PathBuf),
 }
@@ -22,6 +29,7 @@ impl ModPath {
     pub fn join<P: AsRef<Path>>(&self, p: P) -> ModPath {
         match self {
             ModPath::Path(s) => ModPath::Path(s.join(p)),
+            ModPath::ZipPath(a, s) => ModPath::ZipPath(a.clone(), s.join(p)),
             #[cfg(target_os = "android")]
             ModPath::AndroidAssetPath(s) => ModPath::AndroidAssetPath(s.join(p)),
         }
@@ -30,11 +38,11 @@ impl ModPath {
     /// Extracts the mod id from the mod path
     pub fn id(&self) -> std::io::Result<String> {
         let dir_name = match self {
-            ModPath::Path(s) => s,
+            ModPath::Path(s) => s.file_name(),
+            ModPath::ZipPath(a, _) => a.file_stem(),
             #[cfg(target_os = "android")]
-            ModPath::AndroidAssetPath(s) => s,
+            ModPath::AndroidAssetPath(s) => s.file_name(),
         }
-        .file_name()
         .ok_or_else(|| {
             io::Error::new(
                 io::ErrorKind::InvalidData,
@@ -101,6 +109,33 @@ impl Mod {
         }
     }
 
+    /// Create a mod instance from a zip archive on disk
+    ///
+    /// The archive must contain a `manifest.json` at its root.
+    pub fn from_zip(path: &Path) -> io::Result<Self> {
+        let zip_path = ModPath::ZipPath(path.to_owned(), PathBuf::new());
+        let id = zip_path.id()?;
+        let zip_file = DirFsFile::open(path)?;
+        let zip_fs = ZipFs::new(Box::new(zip_file))?;
+        let mut s = String::new();
+        zip_fs
+            .open(&Nfc::caseless_path("manifest.json"))
+            .and_then(|mut f| f.read_to_string(&mut s))
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Mod manifest not found in `{:?}`: {}", path, e),
+                )
+            })?;
+        match crate::json::de::from_string::<ModManifestJson>(&s) {
+            Ok(json) => Ok(Mod::new_with_mod_manifest(zip_path, id, json)),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Mod manifest in `{:?}` could not be read: {}", path, e),
+            )),
+        }
+    }
+
     /// Create a mod instance from an android assets path
     #[cfg(target_os = "android")]
     pub fn from_android_assets(path: &Path) -> io::Result<Self> {
@@ -280,10 +315,19 @@ impl ModManager {
         for dir in &dirs {
             if let Ok(paths) = dir.read_dir() {
                 for entry in paths {
-                    if let Ok(false) = entry.as_ref().map(|e| e.path().is_dir()) {
+                    let path = entry.map(|e| e.path());
+                    let is_zip = matches!(&path, Ok(p) if is_zip_file(p));
+                    if let Ok(false) = path.as_ref().map(|p| p.is_dir() || is_zip) {
                         continue;
                     }
-                    match entry.and_then(|dir_entry| Mod::from_path(&dir_entry.path())) {
+                    let mod_result = path.and_then(|p| {
+                        if is_zip {
+                            Mod::from_zip(&p)
+                        } else {
+                            Mod::from_path(&p)
+                        }
+                    });
+                    match mod_result {
                         Ok(m) => {
                             log::debug!("Found mod `{}` at `{:?}`", m.id(), m.path());
                             available_mods.insert(m.id().to_owned(), m);
@@ -311,13 +355,25 @@ impl ModManager {
     }
 }
 
+/// Returns true if the path is a file with a `.zip` extension
+fn is_zip_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .map(|x| x.eq_ignore_ascii_case("zip"))
+            .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use tempfile::{TempDir, tempdir};
 
+    use std::io::Write;
+    use std::path::Path;
+
     use crate::{
         config::EngineOptions,
-        mods::{ModManagerInitError, mod_manifest::ModManifestJson},
+        mods::{ModManagerInitError, ModPath, mod_manifest::ModManifestJson},
     };
 
     use super::ModManager;
@@ -440,6 +496,59 @@ mod tests {
         assert_eq!(m.description(), "test description");
     }
 
+    #[test]
+    fn zip_mod_with_manifest_should_load() {
+        let (engine_options, _temp_dir) = create_test_engine_options();
+
+        std::fs::create_dir_all(engine_options.stracciatella_home.join("mods"))
+            .expect("create dir `mods`");
+        create_zip(
+            &engine_options
+                .stracciatella_home
+                .join("mods/test-mod-1.zip"),
+            &[
+                ("Manifest.json", r#"{ "name": "m1", "version": "1.0.0" }"#),
+                ("data/foo.txt", "foo"),
+            ],
+        );
+
+        let mod_manager = ModManager::new(&engine_options).unwrap();
+        let m = mod_manager.get_mod_by_id("test-mod-1").unwrap();
+
+        assert_eq!(m.name(), "m1");
+        assert_eq!(m.version(), "1.0.0");
+        assert!(matches!(m.path(), ModPath::ZipPath(_, _)));
+    }
+
+    #[test]
+    fn zip_mod_without_manifest_should_be_ignored() {
+        let (engine_options, _temp_dir) = create_test_engine_options();
+
+        std::fs::create_dir_all(engine_options.stracciatella_home.join("mods"))
+            .expect("create dir `mods`");
+        create_zip(
+            &engine_options
+                .stracciatella_home
+                .join("mods/test-mod-1.zip"),
+            &[("data/foo.txt", "foo")],
+        );
+
+        let mod_manager = ModManager::new_unchecked(&engine_options);
+
+        assert!(mod_manager.get_mod_by_id("test-mod-1").is_none());
+    }
+
+    fn create_zip(path: &Path, files: &[(&str, &str)]) {
+        let file = std::fs::File::create(path).expect("create zip");
+        let mut zip = zip::ZipWriter::new(file);
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .expect("start zip file");
+            zip.write_all(content.as_bytes()).expect("write zip file");
+        }
+        zip.finish().expect("finish zip");
+    }
+
     fn create_test_engine_options() -> (EngineOptions, TempDir) {
         let temp_dir = tempdir().expect("temp_dir");
         let mut engine_options = EngineOptions::default();
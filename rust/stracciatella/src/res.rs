@@ -321,8 +321,7 @@ impl ResourcePackBuilder {
     ) -> Result<Vec<Resource>, ResourceError> {
         slf.set_property("archive_slf", true);
         let mut input = io::Cursor::new(&data);
-        let header = SlfHeader::from_input(&mut input)?;
-        let entries = header.entries_from_input(&mut input)?;
+        let (header, entries) = SlfHeader::with_entries_from_input(&mut input, None)?;
         let resources: Result<Vec<Resource>, ResourceError> = entries
             .par_iter()
             .filter(|entry| entry.state == SlfEntryState::Ok)
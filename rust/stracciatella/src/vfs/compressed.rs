@@ -0,0 +1,620 @@
+//! This module contains a virtual filesystem that decompresses the files of another layer.
+//!
+//! A request for `foo.dat` is resolved to `foo.dat.gz` (gzip) or `foo.dat.zst` (zstd)
+//! when the layer does not contain `foo.dat`. Directory listings contain the
+//! uncompressed names of the compressed files that are resolved this way.
+//!
+//! The decompressed length of a file is taken from the gzip trailer or from the
+//! zstd frame headers when they store it. Otherwise the file is decompressed
+//! once to get the length, which is cached while the file does not change.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use flate2::read::MultiGzDecoder;
+use ruzstd::frame::{ReadFrameHeaderError, read_frame_header};
+use ruzstd::{FrameDecoder, StreamingDecoder};
+
+use crate::math::checked_add_u64_i64;
+use crate::unicode::Nfc;
+use crate::vfs::watch::VfsChangeCallback;
+use crate::vfs::{VfsCaseCollision, VfsFile, VfsLayer, VfsMetadata};
+
+/// Compression formats and the extensions of the compressed files, in the order they are tried.
+const COMPRESSIONS: [(Compression, &str); 2] =
+    [(Compression::Gzip, ".gz"), (Compression::Zstd, ".zst")];
+
+/// Length of the smallest gzip file, a header, an empty deflate block and the trailer.
+const GZIP_MIN_LEN: u64 = 20;
+
+/// Compression format of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, with one or more members
+    Gzip,
+    /// zstd, with one or more frames
+    Zstd,
+}
+
+/// Decompressed lengths by compressed path, with the generation of the layer and the compressed length.
+type LengthCache = Arc<Mutex<HashMap<Nfc, (u64, u64, u64)>>>;
+
+/// A virtual filesystem that provides the compressed files of another VFS layer decompressed.
+///
+/// Writes go to the inner layer unchanged.
+#[derive(Clone, Debug)]
+pub struct CompressedFs {
+    /// The inner layer.
+    pub layer: Arc<dyn VfsLayer>,
+    /// Known decompressed lengths of the compressed files.
+    lengths: LengthCache,
+}
+
+/// A decompressed virtual file.
+///
+/// The data is decompressed while reading. Seeking backwards restarts the decompression.
+pub struct CompressedFsFile {
+    /// Display info.
+    pub file_path: Nfc,
+    /// Layer that contains the compressed file.
+    layer: Arc<dyn VfsLayer>,
+    /// Path of the compressed file in the layer.
+    compressed_path: Nfc,
+    /// Compression format.
+    compression: Compression,
+    /// Decompressor of the compressed file.
+    decoder: Box<dyn Read + Send + Sync>,
+    /// Position in the decompressed data.
+    position: u64,
+    /// Number of bytes that were decompressed, less than the position after seeking past the end.
+    decoded: u64,
+    /// Length of the decompressed data, when it is known.
+    len: OnceLock<u64>,
+    /// Known decompressed lengths of the layer, see `CompressedFs`.
+    lengths: LengthCache,
+    /// Generation of the layer and length of the compressed file when it was opened.
+    source_state: (u64, u64),
+}
+
+impl CompressedFs {
+    /// Creates a new virtual filesystem that decompresses the files of a layer.
+    pub fn new(layer: Arc<dyn VfsLayer>) -> Arc<CompressedFs> {
+        Arc::new(CompressedFs {
+            layer,
+            lengths: LengthCache::default(),
+        })
+    }
+
+    /// Opens a compressed file of the inner layer
+    fn open_compressed(
+        &self,
+        file_path: &Nfc,
+        compressed_path: Nfc,
+        compression: Compression,
+    ) -> io::Result<CompressedFsFile> {
+        CompressedFsFile::open(
+            self.layer.clone(),
+            self.lengths.clone(),
+            file_path,
+            compressed_path,
+            compression,
+        )
+    }
+
+    /// Returns the path in the inner layer and the compression of the file that provides a path
+    fn resolve(&self, file_path: &Nfc) -> io::Result<Option<(Nfc, Option<Compression>)>> {
+        if self.layer.exists(file_path)? {
+            return Ok(Some((file_path.clone(), None)));
+        }
+        for (compression, extension) in COMPRESSIONS {
+            let compressed_path = Nfc::from(format!("{}{}", file_path, extension));
+            if self.layer.exists(&compressed_path)? {
+                return Ok(Some((compressed_path, Some(compression))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl VfsLayer for CompressedFs {
+    fn open(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        match self.resolve(file_path)? {
+            Some((path, None)) => self.layer.open(&path),
+            Some((path, Some(compression))) => Ok(Box::new(self.open_compressed(
+                file_path,
+                path,
+                compression,
+            )?)),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn exists(&self, file_path: &Nfc) -> io::Result<bool> {
+        Ok(self.resolve(file_path)?.is_some())
+    }
+
+    fn read_dir(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        Ok(provided_paths(self.layer.read_dir(file_path)?))
+    }
+
+    fn read_dir_recursive(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        Ok(provided_paths(self.layer.read_dir_recursive(file_path)?))
+    }
+
+    fn read_empty_dirs(&self) -> io::Result<BTreeSet<Nfc>> {
+        self.layer.read_empty_dirs()
+    }
+
+    /// Returns the metadata, the length of compressed files is the decompressed length
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        match self.resolve(file_path)? {
+            Some((path, None)) => self.layer.metadata(&path),
+            Some((path, Some(compression))) => {
+                let metadata = self.layer.metadata(&path)?;
+                let file = self.open_compressed(file_path, path, compression)?;
+                Ok(VfsMetadata::file(self, file.len()?, metadata.modified))
+            }
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.layer.generation()
+    }
+
+    /// Watches the inner layer, changes of compressed files are reported with the path they provide
+    fn watch(&self, on_change: VfsChangeCallback) -> io::Result<bool> {
+        let layer = self.layer.clone();
+        self.layer.watch(Arc::new(move |path: Nfc| {
+            let provided = provided_path(&path, |x| layer.exists(x));
+            on_change(provided.unwrap_or(path))
+        }))
+    }
+
+    fn case_collisions(&self) -> io::Result<Vec<VfsCaseCollision>> {
+        self.layer
+            .case_collisions()?
+            .into_iter()
+            .map(|x| {
+                Ok(VfsCaseCollision {
+                    path: provided_path(&x.path, |x| self.layer.exists(x))?,
+                    ..x
+                })
+            })
+            .collect()
+    }
+
+    fn is_writable(&self) -> bool {
+        self.layer.is_writable()
+    }
+
+    fn create(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.layer.create(file_path)
+    }
+
+    fn open_writable(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.layer.open_writable(file_path)
+    }
+
+    fn create_dir(&self, file_path: &Nfc) -> io::Result<()> {
+        self.layer.create_dir(file_path)
+    }
+
+    fn remove(&self, file_path: &Nfc) -> io::Result<()> {
+        self.layer.remove(file_path)
+    }
+
+    fn rename(&self, from: &Nfc, to: &Nfc) -> io::Result<()> {
+        self.layer.rename(from, to)
+    }
+}
+
+impl CompressedFsFile {
+    /// Opens a compressed file of a layer
+    ///
+    /// The decompressed length is taken from lengths if it is known, or from the file if it stores it.
+    fn open(
+        layer: Arc<dyn VfsLayer>,
+        lengths: LengthCache,
+        file_path: &Nfc,
+        compressed_path: Nfc,
+        compression: Compression,
+    ) -> io::Result<CompressedFsFile> {
+        let mut file = layer.open(&compressed_path)?;
+        let source_state = (layer.generation(), file.len()?);
+        let len = OnceLock::new();
+        if let Some(&(generation, compressed_len, known_len)) = lengths
+            .lock()
+            .map_err(|err| {
+                io::Error::other(format!("CompressedFs: Error locking lengths: `{}`", err))
+            })?
+            .get(&compressed_path)
+        {
+            if (generation, compressed_len) == source_state {
+                let _ = len.set(known_len);
+            }
+        }
+        if len.get().is_none() {
+            if let Some(stored_len) = stored_len(&mut file, compression).ok().flatten() {
+                let _ = len.set(stored_len);
+            }
+            file.seek(SeekFrom::Start(0))?;
+        }
+        let decoder = decoder(file, compression)?;
+        Ok(CompressedFsFile {
+            file_path: file_path.clone(),
+            layer,
+            compressed_path,
+            compression,
+            decoder,
+            position: 0,
+            decoded: 0,
+            len,
+            lengths,
+            source_state,
+        })
+    }
+
+    /// Remembers the decompressed length in the file and in the lengths of the layer
+    fn set_len(&self, len: u64) -> u64 {
+        if let Ok(mut lengths) = self.lengths.lock() {
+            let (generation, compressed_len) = self.source_state;
+            lengths.insert(
+                self.compressed_path.clone(),
+                (generation, compressed_len, len),
+            );
+        }
+        *self.len.get_or_init(|| len)
+    }
+
+    /// Restarts the decompression from the start of the file
+    fn restart(&mut self) -> io::Result<()> {
+        let file = self.layer.open(&self.compressed_path)?;
+        self.decoder = decoder(file, self.compression)?;
+        self.position = 0;
+        self.decoded = 0;
+        Ok(())
+    }
+
+    /// Remembers the decompressed length when the end of the data was reached
+    ///
+    /// It replaces a stored length that is wrong.
+    fn reached_end(&mut self) {
+        if self.len.get() != Some(&self.decoded) {
+            self.len.take();
+            self.set_len(self.decoded);
+        }
+    }
+}
+
+impl VfsFile for CompressedFsFile {
+    /// Gets the length of the decompressed data.
+    ///
+    /// The file is decompressed separately to get the length, unless the length is known.
+    fn len(&self) -> io::Result<u64> {
+        if let Some(len) = self.len.get() {
+            return Ok(*len);
+        }
+        let file = self.layer.open(&self.compressed_path)?;
+        let len = io::copy(&mut decoder(file, self.compression)?, &mut io::sink())?;
+        Ok(self.set_len(len))
+    }
+}
+
+impl fmt::Debug for CompressedFsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressedFsFile")
+            .field("file_path", &self.file_path)
+            .field("compressed_path", &self.compressed_path)
+            .field("compression", &self.compression)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl fmt::Display for CompressedFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CompressedFs {{ {} }}", self.layer)
+    }
+}
+
+impl fmt::Display for CompressedFsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CompressedFsFile {{ {:?} in {} }}",
+            self.compressed_path, self.layer
+        )
+    }
+}
+
+impl io::Read for CompressedFsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.decoder.read(buf)?;
+        self.position += n as u64;
+        self.decoded += n as u64;
+        if n == 0 && !buf.is_empty() {
+            self.reached_end();
+        }
+        Ok(n)
+    }
+}
+
+impl io::Seek for CompressedFsFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => checked_add_u64_i64(self.position, offset),
+            SeekFrom::End(offset) => checked_add_u64_i64(self.len()?, offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        if target < self.decoded {
+            self.restart()?;
+        }
+        if target > self.decoded {
+            let skip = target - self.decoded;
+            let skipped = io::copy(&mut (&mut self.decoder).take(skip), &mut io::sink())?;
+            self.decoded += skipped;
+            if skipped < skip {
+                self.reached_end();
+            }
+        }
+        // Seeking past the end is allowed, reads return no data
+        self.position = target;
+        Ok(target)
+    }
+}
+
+impl io::Write for CompressedFsFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "stracciatella::vfs::compressed::CompressedFsFile is read-only",
+        ))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "stracciatella::vfs::compressed::CompressedFsFile is read-only",
+        ))
+    }
+}
+
+/// Returns the path that a file of the inner layer provides
+///
+/// A compressed file provides its uncompressed path, unless a file that is tried before it
+/// for that path exists, see `CompressedFs::resolve`. Other files provide their own path.
+fn provided_path(path: &Nfc, exists: impl Fn(&Nfc) -> io::Result<bool>) -> io::Result<Nfc> {
+    for (index, (_, extension)) in COMPRESSIONS.iter().enumerate() {
+        let Some(uncompressed) = path.strip_suffix(extension).filter(|x| !x.is_empty()) else {
+            continue;
+        };
+        let uncompressed = Nfc::from(uncompressed);
+        if exists(&uncompressed)? {
+            return Ok(path.clone());
+        }
+        for (_, other_extension) in &COMPRESSIONS[..index] {
+            if exists(&Nfc::from(format!("{}{}", uncompressed, other_extension)))? {
+                return Ok(path.clone());
+            }
+        }
+        return Ok(uncompressed);
+    }
+    Ok(path.clone())
+}
+
+/// Returns the paths that the files of a listing of the inner layer provide
+fn provided_paths(paths: BTreeSet<Nfc>) -> BTreeSet<Nfc> {
+    paths
+        .iter()
+        .map(|path| provided_path(path, |x| Ok(paths.contains(x))).expect("listing lookup"))
+        .collect()
+}
+
+/// Returns the decompressed length that a compressed file stores, if it stores one
+///
+/// gzip stores the length modulo 2^32 of the last member, so files with several members
+/// report the wrong length until they are read to the end. zstd frames store their length
+/// unless the compressor did not know it.
+fn stored_len(file: &mut Box<dyn VfsFile>, compression: Compression) -> io::Result<Option<u64>> {
+    match compression {
+        Compression::Gzip => {
+            if file.len()? < GZIP_MIN_LEN {
+                return Ok(None);
+            }
+            file.seek(SeekFrom::End(-4))?;
+            let mut isize = [0; 4];
+            file.read_exact(&mut isize)?;
+            Ok(Some(u64::from(u32::from_le_bytes(isize))))
+        }
+        Compression::Zstd => zstd_content_size(file),
+    }
+}
+
+/// Returns the sum of the content sizes of the zstd frames of a file
+///
+/// The blocks of the frames are skipped without decompressing them.
+/// Returns None if a frame does not store its content size.
+fn zstd_content_size(file: &mut Box<dyn VfsFile>) -> io::Result<Option<u64>> {
+    let file_len = file.len()?;
+    let mut position = 0;
+    let mut content_size = 0u64;
+    while position < file_len {
+        file.seek(SeekFrom::Start(position))?;
+        let (frame, header_len) = match read_frame_header(&mut *file) {
+            Ok(frame) => frame,
+            Err(ReadFrameHeaderError::SkipFrame { length, .. }) => {
+                position += 8 + u64::from(length);
+                continue;
+            }
+            Err(_) => return Ok(None),
+        };
+        let descriptor = &frame.header.descriptor;
+        if !descriptor.frame_content_size_bytes().is_ok_and(|x| x > 0) {
+            return Ok(None);
+        }
+        content_size = content_size.saturating_add(frame.header.frame_content_size());
+        position += u64::from(header_len);
+        loop {
+            let mut block_header = [0; 4];
+            file.seek(SeekFrom::Start(position))?;
+            file.read_exact(&mut block_header[..3])?;
+            let block_header = u32::from_le_bytes(block_header);
+            let is_last = block_header & 1 == 1;
+            let is_rle = (block_header >> 1) & 3 == 1;
+            // RLE blocks store a single byte
+            position += 3 + if is_rle {
+                1
+            } else {
+                u64::from(block_header >> 3)
+            };
+            if is_last {
+                break;
+            }
+        }
+        if descriptor.content_checksum_flag() {
+            position += 4;
+        }
+    }
+    Ok(Some(content_size))
+}
+
+/// Creates a decompressor that reads from a compressed file
+fn decoder(
+    file: Box<dyn VfsFile>,
+    compression: Compression,
+) -> io::Result<Box<dyn Read + Send + Sync>> {
+    Ok(match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(ZstdDecoder::new(file)?),
+    })
+}
+
+/// Decompressor of zstd files with multiple frames
+struct ZstdDecoder {
+    /// Decompressor of the current frame, None after an error.
+    decoder: Option<StreamingDecoder<Box<dyn VfsFile>, FrameDecoder>>,
+}
+
+impl ZstdDecoder {
+    fn new(file: Box<dyn VfsFile>) -> io::Result<ZstdDecoder> {
+        let decoder = StreamingDecoder::new(file).map_err(zstd_error)?;
+        Ok(ZstdDecoder {
+            decoder: Some(decoder),
+        })
+    }
+}
+
+impl io::Read for ZstdDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let decoder = self.decoder.as_mut().ok_or_else(|| zstd_error("failed"))?;
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            // End of frame, continue with the next frame if there is one
+            let source = decoder.get_mut();
+            if source.stream_position()? >= source.len()? {
+                return Ok(0);
+            }
+            let (source, frame_decoder) = self.decoder.take().expect("decoder").into_parts();
+            self.decoder = Some(
+                StreamingDecoder::new_with_decoder(source, frame_decoder).map_err(zstd_error)?,
+            );
+        }
+    }
+}
+
+/// Converts errors of the zstd decoder to io errors
+fn zstd_error<E: fmt::Display>(err: E) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("zstd decompression failed: {}", err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::sync::Arc;
+
+    use flate2::write::GzEncoder;
+
+    use super::{CompressedFs, Compression};
+    use crate::unicode::Nfc;
+    use crate::vfs::mem::MemFs;
+    use crate::vfs::{VfsFile, VfsLayer};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn cached_lengths() {
+        let mem_fs = MemFs::new("mem");
+        let path = Nfc::caseless_path("a.dat");
+        let compressed_path = Nfc::caseless_path("a.dat.gz");
+        mem_fs.insert(&compressed_path, gzip(&[1; 1000])).unwrap();
+        let compressed_fs = CompressedFs::new(Arc::new(mem_fs.clone()));
+
+        assert_eq!(compressed_fs.metadata(&path).unwrap().len, 1000);
+        // the length of newly opened files is known without decompressing them
+        let file = compressed_fs
+            .open_compressed(&path, compressed_path.clone(), Compression::Gzip)
+            .unwrap();
+        assert_eq!(file.len.get(), Some(&1000));
+        assert_eq!(file.len().unwrap(), 1000);
+
+        // changed files are decompressed again
+        mem_fs.insert(&compressed_path, gzip(&[2; 10])).unwrap();
+        let file = compressed_fs
+            .open_compressed(&path, compressed_path.clone(), Compression::Gzip)
+            .unwrap();
+        assert_eq!(file.len.get(), Some(&10));
+        assert_eq!(compressed_fs.metadata(&path).unwrap().len, 10);
+        let mut data = Vec::new();
+        compressed_fs
+            .open(&path)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, vec![2; 10]);
+    }
+
+    #[test]
+    fn multi_member_gzip_lengths() {
+        let mem_fs = MemFs::new("mem");
+        let path = Nfc::caseless_path("a.dat");
+        let compressed_path = Nfc::caseless_path("a.dat.gz");
+        let mut data = gzip(&[1; 1000]);
+        data.extend(gzip(&[2; 10]));
+        mem_fs.insert(&compressed_path, data).unwrap();
+        let compressed_fs = CompressedFs::new(Arc::new(mem_fs));
+
+        // the stored length only covers the last member
+        let mut file = compressed_fs.open(&path).unwrap();
+        assert_eq!(file.len().unwrap(), 10);
+        // the length is corrected when the end is reached
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 1010);
+        assert_eq!(file.len().unwrap(), 1010);
+        assert_eq!(compressed_fs.metadata(&path).unwrap().len, 1010);
+        let mut file = compressed_fs.open(&path).unwrap();
+        assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 1000);
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data, vec![2; 10]);
+    }
+}
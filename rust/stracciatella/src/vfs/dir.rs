@@ -2,7 +2,8 @@
 #![allow(dead_code)]
 
 use lru::LruCache;
-use std::collections::BTreeSet;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsString;
 use std::fmt;
 use std::io;
@@ -11,22 +12,33 @@ use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::fs;
-use crate::fs::File;
+use crate::fs::{File, OpenOptions};
 use crate::unicode::Nfc;
-use crate::vfs::{VfsFile, VfsLayer};
+use crate::vfs::watch::{VfsChangeCallback, logical_path};
+use crate::vfs::{VfsCaseCollision, VfsFile, VfsLayer, VfsMetadata, read_only_error};
 
 /// The size of the cache used for canonicalization
 const CANONICALIZATION_CACHE_SIZE: usize = 256;
 
+/// Directory listings of the paths that were listed during path canonicalization
+type CanonicalizationCache = LruCache<PathBuf, Vec<(Nfc, OsString)>>;
+
 /// A case-insensitive virtual filesystem backed by a filesystem directory.
 #[derive(Debug)]
 pub struct DirFs {
     /// Path to the directory.
     pub dir_path: PathBuf,
+    /// Whether files can be created, modified and removed.
+    pub writable: bool,
     /// Cache that is used for canonicalization. It will contain an entry for each path that is listed during path canonicalization
-    canonicalization_cache: Mutex<LruCache<PathBuf, Vec<(Nfc, OsString)>>>,
+    canonicalization_cache: Arc<Mutex<CanonicalizationCache>>,
+    /// Watcher for changes in the directory, if watching was started
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    /// Number of known changes of the directory contents
+    generation: Arc<AtomicU64>,
 }
 
 /// A virtual file.
@@ -41,17 +53,86 @@ pub struct DirFsFile {
 }
 
 impl DirFs {
-    /// Creates a new virtual filesystem.
+    /// Creates a new read-only virtual filesystem.
     pub fn new(path: &Path) -> io::Result<Arc<DirFs>> {
+        Self::new_with_writable(path, false)
+    }
+
+    /// Creates a new writable virtual filesystem.
+    pub fn new_writable(path: &Path) -> io::Result<Arc<DirFs>> {
+        Self::new_with_writable(path, true)
+    }
+
+    fn new_with_writable(path: &Path, writable: bool) -> io::Result<Arc<DirFs>> {
         fs::read_dir(path)?;
         Ok(Arc::new(DirFs {
             dir_path: path.to_owned(),
-            canonicalization_cache: Mutex::new(LruCache::new(
+            writable,
+            canonicalization_cache: Arc::new(Mutex::new(LruCache::new(
                 NonZeroUsize::new(CANONICALIZATION_CACHE_SIZE).unwrap(),
-            )),
+            ))),
+            watcher: Mutex::new(None),
+            generation: Arc::new(AtomicU64::new(0)),
         }))
     }
 
+    /// Fails if the virtual filesystem is read-only
+    fn ensure_writable(&self, file_path: &Nfc) -> io::Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(read_only_error(self, file_path))
+        }
+    }
+
+    /// Clears the canonicalization cache, needs to be called whenever the directory contents change
+    fn clear_canonicalization_cache(&self) -> io::Result<()> {
+        let mut canonicalization_cache = self.canonicalization_cache.lock().map_err(|err| {
+            io::Error::other(format!(
+                "DirFs: Error locking canonicalization cache: `{}`",
+                err
+            ))
+        })?;
+        canonicalization_cache.clear();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Maps a path to the filesystem path that should be used for writing
+    ///
+    /// Existing path components are matched case insensitively, missing components are used as is.
+    fn resolve_for_write(&self, file_path: &str) -> io::Result<PathBuf> {
+        let file_path = file_path.trim_matches('/');
+        if file_path.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot write the root directory",
+            ));
+        }
+        if file_path
+            .split('/')
+            .any(|x| x.is_empty() || x == "." || x == "..")
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "special path components are not supported",
+            ));
+        }
+        Ok(fs::resolve_existing_components(
+            Path::new(file_path),
+            Some(&self.dir_path),
+            true,
+        ))
+    }
+
+    /// Maps a path to the first existing candidate
+    fn find_existing(&self, file_path: &str) -> io::Result<PathBuf> {
+        self.canonicalize(file_path.trim_end_matches('/'))?
+            .into_iter()
+            .find(|x| x != &self.dir_path)
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
     /// Maps a path to all candidates that might match the path case insensitively
     ///
     /// The returned paths are already containing the dir path
@@ -128,6 +209,21 @@ impl DirFs {
     }
 }
 
+impl DirFsFile {
+    /// Opens a filesystem file for reading, without a DirFs.
+    pub fn open(path: &Path) -> io::Result<DirFsFile> {
+        let file_name = path
+            .file_name()
+            .map(|x| x.to_string_lossy())
+            .unwrap_or_default();
+        Ok(DirFsFile {
+            file_path: Nfc::caseless_path(&file_name),
+            dir_path: path.parent().map(Path::to_owned).unwrap_or_default(),
+            file: File::open(path)?,
+        })
+    }
+}
+
 impl VfsLayer for DirFs {
     fn open(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
         let candidates = self.canonicalize(file_path)?;
@@ -147,6 +243,23 @@ impl VfsLayer for DirFs {
         Ok(!candidates.is_empty())
     }
 
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        let candidates = self.canonicalize(file_path.trim_end_matches('/'))?;
+        let path = candidates
+            .first()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let metadata = fs::metadata(path)?;
+        if metadata.is_dir() {
+            Ok(VfsMetadata::dir(self, metadata.modified().ok()))
+        } else {
+            Ok(VfsMetadata::file(
+                self,
+                metadata.len(),
+                metadata.modified().ok(),
+            ))
+        }
+    }
+
     fn read_dir(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
         let file_path = file_path.trim_end_matches('/');
         let candidates = self.canonicalize(file_path)?;
@@ -175,6 +288,168 @@ impl VfsLayer for DirFs {
 
         Ok(result)
     }
+
+    /// Walks the directory and lists the directories without files
+    ///
+    /// Symbolic links to directories are not followed.
+    fn read_empty_dirs(&self) -> io::Result<BTreeSet<Nfc>> {
+        let mut result = BTreeSet::new();
+        walk_empty_dirs(&self.dir_path, "", &mut result)?;
+        Ok(result)
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Walks the directory and lists the names that fold to the same logical path
+    ///
+    /// Symbolic links to directories are not followed.
+    fn case_collisions(&self) -> io::Result<Vec<VfsCaseCollision>> {
+        let mut candidates_by_path: BTreeMap<Nfc, Vec<PathBuf>> = BTreeMap::new();
+        let mut dirs = vec![(PathBuf::new(), String::new())];
+        while let Some((dir, logical_dir)) = dirs.pop() {
+            for entry in fs::read_dir(self.dir_path.join(&dir))? {
+                let entry = entry?;
+                let file_name = entry.file_name();
+                let Some(name) = file_name.to_str() else {
+                    continue;
+                };
+                let path = dir.join(name);
+                let logical_path = if logical_dir.is_empty() {
+                    Nfc::caseless(name)
+                } else {
+                    Nfc::caseless(&format!("{}/{}", logical_dir, name))
+                };
+                if entry.file_type()?.is_dir() {
+                    dirs.push((path.clone(), logical_path.to_string()));
+                }
+                candidates_by_path
+                    .entry(logical_path)
+                    .or_default()
+                    .push(path);
+            }
+        }
+        Ok(candidates_by_path
+            .into_iter()
+            .filter(|(_, candidates)| candidates.len() > 1)
+            .map(|(path, mut candidates)| {
+                candidates.sort();
+                VfsCaseCollision { path, candidates }
+            })
+            .collect())
+    }
+
+    /// Watches the directory recursively
+    ///
+    /// Every change clears the canonicalization cache.
+    fn watch(&self, on_change: VfsChangeCallback) -> io::Result<bool> {
+        let dir_path = self.dir_path.clone();
+        let canonicalization_cache = self.canonicalization_cache.clone();
+        let generation = self.generation.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        log::warn!("DirFs: Error watching {:?}: {}", dir_path, err);
+                        return;
+                    }
+                };
+                if event.kind.is_access() {
+                    return;
+                }
+                if let Ok(mut canonicalization_cache) = canonicalization_cache.lock() {
+                    canonicalization_cache.clear();
+                }
+                generation.fetch_add(1, Ordering::SeqCst);
+                for path in &event.paths {
+                    if let Some(path) = logical_path(&dir_path, path) {
+                        on_change(path);
+                    }
+                }
+            })
+            .map_err(|err| io::Error::other(format!("DirFs: Error creating watcher: {}", err)))?;
+        watcher
+            .watch(&self.dir_path, RecursiveMode::Recursive)
+            .map_err(|err| {
+                io::Error::other(format!(
+                    "DirFs: Error watching {:?}: {}",
+                    self.dir_path, err
+                ))
+            })?;
+        *self.watcher.lock().map_err(|err| {
+            io::Error::other(format!("DirFs: Error locking watcher: `{}`", err))
+        })? = Some(watcher);
+        Ok(true)
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn create(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.ensure_writable(file_path)?;
+        let path = self.resolve_for_write(file_path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        self.clear_canonicalization_cache()?;
+        Ok(Box::new(DirFsFile {
+            file_path: file_path.to_owned(),
+            dir_path: self.dir_path.to_owned(),
+            file,
+        }))
+    }
+
+    fn open_writable(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.ensure_writable(file_path)?;
+        let candidates = self.canonicalize(file_path)?;
+        if let Some(path) = candidates.iter().find(|x| x.is_file()) {
+            Ok(Box::new(DirFsFile {
+                file_path: file_path.to_owned(),
+                dir_path: self.dir_path.to_owned(),
+                file: OpenOptions::new().read(true).write(true).open(path)?,
+            }))
+        } else {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn create_dir(&self, file_path: &Nfc) -> io::Result<()> {
+        self.ensure_writable(file_path)?;
+        let path = self.resolve_for_write(file_path)?;
+        fs::create_dir_all(path)?;
+        self.clear_canonicalization_cache()
+    }
+
+    fn remove(&self, file_path: &Nfc) -> io::Result<()> {
+        self.ensure_writable(file_path)?;
+        let path = self.find_existing(file_path)?;
+        if path.is_dir() {
+            fs::remove_dir(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+        self.clear_canonicalization_cache()
+    }
+
+    fn rename(&self, from: &Nfc, to: &Nfc) -> io::Result<()> {
+        self.ensure_writable(from)?;
+        let from_path = self.find_existing(from)?;
+        let to_path = self.resolve_for_write(to)?;
+        if let Some(parent) = to_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&from_path, &to_path)?;
+        self.clear_canonicalization_cache()
+    }
 }
 
 impl VfsFile for DirFsFile {
@@ -182,6 +457,10 @@ impl VfsFile for DirFsFile {
     fn len(&self) -> io::Result<u64> {
         self.file.metadata().map(|x| x.len())
     }
+
+    fn as_file(&self) -> Option<&File> {
+        Some(&self.file)
+    }
 }
 
 impl fmt::Display for DirFs {
@@ -220,3 +499,32 @@ impl io::Write for DirFsFile {
         self.file.flush()
     }
 }
+
+/// Adds the logical paths of the directories without files in dir to result
+///
+/// Returns true if dir contains files.
+fn walk_empty_dirs(dir: &Path, logical_dir: &str, result: &mut BTreeSet<Nfc>) -> io::Result<bool> {
+    let mut has_files = false;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            has_files = true;
+            continue;
+        }
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+        let logical_path = if logical_dir.is_empty() {
+            Nfc::caseless(name)
+        } else {
+            Nfc::caseless(&format!("{}/{}", logical_dir, name))
+        };
+        if walk_empty_dirs(&entry.path(), &logical_path, result)? {
+            has_files = true;
+        } else {
+            result.insert(logical_path);
+        }
+    }
+    Ok(has_files)
+}
@@ -0,0 +1,121 @@
+//! This module contains the export of the virtual filesystem.
+//!
+//! An export materializes the files of the VFS as the game sees them,
+//! either as a directory tree or as a single SLF archive.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::file_formats::slf::SlfBuilder;
+use crate::fs;
+use crate::json;
+use crate::unicode::Nfc;
+use crate::vfs::patch::{patch_paths, patched_path};
+use crate::vfs::walk::VfsEntry;
+use crate::vfs::{Vfs, VfsLayer};
+
+impl Vfs {
+    /// Exports all files in a directory and its subdirectories to a filesystem directory
+    ///
+    /// Files keep their logical path relative to the target directory.
+    /// Existing files are overwritten, other files in the target directory are kept.
+    /// If `apply_patches` is true, json files are written with their patches and merge patches applied
+    /// and the applied patches are not written.
+    /// Returns the number of exported files.
+    pub fn export_to_dir(
+        &self,
+        dir_path: &Nfc,
+        target: &Path,
+        apply_patches: bool,
+    ) -> io::Result<usize> {
+        let entries = self.export_entries(dir_path, apply_patches)?;
+        for (entry, patched) in &entries {
+            let target_path = entry
+                .path
+                .split('/')
+                .fold(target.to_owned(), |path, component| path.join(component));
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut output = fs::File::create(&target_path)?;
+            match patched {
+                Some(data) => output.write_all(data)?,
+                None => {
+                    let mut file = self.open_in_layer(entry.layer_index, &entry.path)?;
+                    io::copy(&mut file, &mut output)?;
+                }
+            }
+        }
+        Ok(entries.len())
+    }
+
+    /// Exports all files in a directory and its subdirectories to a new SLF archive
+    ///
+    /// The library path of the archive is the directory, so mounting the archive
+    /// provides the same logical paths.
+    /// If `apply_patches` is true, json files are written with their patches and merge patches applied
+    /// and the applied patches are not written.
+    /// Returns the number of exported files.
+    pub fn export_to_slf(
+        &self,
+        dir_path: &Nfc,
+        target: &Path,
+        apply_patches: bool,
+    ) -> io::Result<usize> {
+        let entries = self.export_entries(dir_path, apply_patches)?;
+        let dir_path = dir_path.trim_matches('/');
+        let library_name = target
+            .file_name()
+            .map(|x| x.to_string_lossy().to_uppercase())
+            .unwrap_or_default();
+        let mut builder = SlfBuilder::new(&library_name, dir_path);
+        for (entry, patched) in entries {
+            let file_path = entry.path[dir_path.len()..].trim_start_matches('/');
+            let layer: Arc<dyn VfsLayer> = self.entries[entry.layer_index].clone();
+            match patched {
+                Some(data) => {
+                    let modified = layer.metadata(&entry.path).ok().and_then(|x| x.modified);
+                    builder.add_data(file_path, data, modified)?;
+                }
+                None => builder.add_layer_file(file_path, &layer, &entry.path)?,
+            }
+        }
+        Ok(builder.write_to_path(target)?.len())
+    }
+
+    /// Returns the files to export together with their patched data
+    fn export_entries(
+        &self,
+        dir_path: &Nfc,
+        apply_patches: bool,
+    ) -> io::Result<Vec<(VfsEntry, Option<Vec<u8>>)>> {
+        let entries = self.walk(dir_path)?;
+        if !apply_patches {
+            return Ok(entries.into_iter().map(|x| (x, None)).collect());
+        }
+
+        let paths: BTreeSet<&Nfc> = entries.iter().map(|x| &x.path).collect();
+        let has_patch =
+            |path: &Nfc| patch_paths(path).is_some_and(|x| x.iter().any(|x| paths.contains(x)));
+        let mut result = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            if patched_path(&entry.path).is_some_and(|x| paths.contains(&x)) {
+                // Applied to the json file
+                continue;
+            }
+            let patched = if has_patch(&entry.path) {
+                let value = self.read_patched_json(&entry.path)?;
+                let json = json::ser::to_string(&value)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Some(json.into_bytes())
+            } else {
+                None
+            };
+            result.push((entry.clone(), patched));
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,142 @@
+//! This module contains the path index of the virtual filesystem.
+//!
+//! The index knows the paths of every layer, so lookups do not have to ask
+//! every layer. Layers are indexed again when they change.
+//!
+//! Paths that are hidden by whiteouts are not returned for the layers they are hidden in.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+
+use crate::unicode::Nfc;
+use crate::vfs::VfsLayer;
+use crate::vfs::whiteout::{Whiteouts, is_whiteout};
+
+/// Index of the files and directories of a list of VFS layers.
+#[derive(Debug, Default)]
+pub struct VfsIndex {
+    /// Paths of each layer, ordered from highest to lowest priority.
+    layers: Vec<LayerIndex>,
+    /// Whiteouts of all layers.
+    whiteouts: Whiteouts,
+}
+
+/// Index of the files and directories of a single VFS layer.
+#[derive(Debug)]
+struct LayerIndex {
+    /// Identity and generation of the layer when it was indexed.
+    state: (usize, u64),
+    /// Files, empty directories and their parent directories, whiteouts are not included.
+    paths: HashSet<Nfc>,
+    /// Whiteouts of the layer.
+    whiteouts: Vec<Nfc>,
+}
+
+impl VfsIndex {
+    /// Builds the index of a list of layers ordered from highest to lowest priority.
+    pub fn build(entries: &[Arc<dyn VfsLayer + Send + Sync>]) -> io::Result<VfsIndex> {
+        VfsIndex::default().update(entries)
+    }
+
+    /// Updates the index to a list of layers ordered from highest to lowest priority.
+    ///
+    /// Only layers that changed or were added since they were indexed are read.
+    pub fn update(self, entries: &[Arc<dyn VfsLayer + Send + Sync>]) -> io::Result<VfsIndex> {
+        let mut previous: HashMap<(usize, u64), LayerIndex> =
+            self.layers.into_iter().map(|x| (x.state, x)).collect();
+        let layers = entries
+            .iter()
+            .map(|layer| match previous.remove(&layer_state(layer)) {
+                Some(layer_index) => Ok(layer_index),
+                None => LayerIndex::build(layer),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut whiteouts = Whiteouts::default();
+        for (layer_index, layer) in layers.iter().enumerate() {
+            for whiteout in &layer.whiteouts {
+                whiteouts.add(whiteout, layer_index);
+            }
+        }
+        Ok(VfsIndex { layers, whiteouts })
+    }
+
+    /// Returns true if the layers did not change since the index was built.
+    pub fn is_current(&self, entries: &[Arc<dyn VfsLayer + Send + Sync>]) -> bool {
+        self.layers.len() == entries.len()
+            && self
+                .layers
+                .iter()
+                .zip(entries)
+                .all(|(x, layer)| x.state == layer_state(layer))
+    }
+
+    /// Returns the indexes of the layers that provide a path, ordered from highest to lowest priority.
+    pub fn get(&self, path: &Nfc) -> Vec<usize> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            // The root directory exists in all layers
+            return (0..self.layers.len()).collect();
+        }
+        let path = Nfc::from(path);
+        let end = self
+            .whiteouts
+            .hiding_layer(&path)
+            .map_or(self.layers.len(), |x| x + 1);
+        self.layers[..end]
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.paths.contains(&path))
+            .map(|(layer_index, _)| layer_index)
+            .collect()
+    }
+
+    /// Returns the highest priority layer with a whiteout of the path or of one of its parent directories.
+    pub fn whiteout_layer(&self, path: &Nfc) -> Option<usize> {
+        self.whiteouts.hiding_layer(path)
+    }
+}
+
+impl LayerIndex {
+    /// Reads the files and empty directories of a layer.
+    fn build(layer: &Arc<dyn VfsLayer + Send + Sync>) -> io::Result<LayerIndex> {
+        let state = layer_state(layer);
+        let files = match layer.read_dir_recursive(&Nfc::caseless_path("")) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Default::default(),
+            result => result?,
+        };
+        let mut paths = HashSet::new();
+        let mut whiteouts = Vec::new();
+        // Empty directories are indexed like files
+        for file in files.into_iter().chain(layer.read_empty_dirs()?) {
+            if is_whiteout(&file) {
+                whiteouts.push(file);
+                continue;
+            }
+            let mut parent = file.as_str();
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                if !paths.insert(Nfc::from(dir)) {
+                    // The parents of a known directory are known too
+                    break;
+                }
+                parent = dir;
+            }
+            paths.insert(file);
+        }
+        Ok(LayerIndex {
+            state,
+            paths,
+            whiteouts,
+        })
+    }
+}
+
+/// Returns the identity and generation of a layer.
+fn layer_state(layer: &Arc<dyn VfsLayer + Send + Sync>) -> (usize, u64) {
+    (Arc::as_ptr(layer) as *const () as usize, layer.generation())
+}
+
+/// Returns the identity and generation of each layer.
+pub fn layer_states(entries: &[Arc<dyn VfsLayer + Send + Sync>]) -> Vec<(usize, u64)> {
+    entries.iter().map(layer_state).collect()
+}
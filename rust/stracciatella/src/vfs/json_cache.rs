@@ -0,0 +1,157 @@
+//! This module contains the cache of patched json documents of the virtual filesystem.
+//!
+//! The cache is cleared when the layers change, see `VfsLayer::generation`,
+//! and when files that are open for writing through the `Vfs` are written or closed.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+use crate::fs::File;
+use crate::unicode::Nfc;
+use crate::vfs::VfsFile;
+
+/// Cache of the values returned by `Vfs::read_patched_json`.
+#[derive(Debug, Default)]
+pub struct JsonCache {
+    /// Identity and generation of each layer when the cached values were read.
+    layers: Vec<(usize, u64)>,
+    /// Patched values by logical path.
+    values: HashMap<Nfc, Value>,
+    /// Number of lookups that found a value.
+    hits: u64,
+    /// Number of lookups that did not find a value.
+    misses: u64,
+}
+
+/// A file that is open for writing through the `Vfs`.
+///
+/// Writing to the file or closing it clears the json cache, so documents that were read
+/// while the file was open are not cached with stale content.
+#[derive(Debug)]
+pub struct JsonCacheClearingFile {
+    /// File of the writable layer.
+    file: Box<dyn VfsFile>,
+    /// Cache that is cleared.
+    json_cache: Arc<Mutex<JsonCache>>,
+}
+
+/// Statistics of the json cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JsonCacheStats {
+    /// Number of reads that used a cached value.
+    pub hits: u64,
+    /// Number of reads that had to read and patch the json file.
+    pub misses: u64,
+    /// Number of cached values.
+    pub entries: usize,
+}
+
+impl JsonCache {
+    /// Returns the cached value of a path
+    ///
+    /// The cache is cleared first if the layers changed.
+    pub fn get(&mut self, layers: &[(usize, u64)], path: &Nfc) -> Option<Value> {
+        if self.layers != layers {
+            self.values.clear();
+            self.layers = layers.to_vec();
+        }
+        let value = self.values.get(path).cloned();
+        if value.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        value
+    }
+
+    /// Caches the value of a path that was read with the layers
+    ///
+    /// The value is not cached if the layers changed in the meantime.
+    pub fn insert(&mut self, layers: &[(usize, u64)], path: Nfc, value: Value) {
+        if self.layers == layers {
+            self.values.insert(path, value);
+        }
+    }
+
+    /// Removes all cached values, the statistics are kept
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    /// Returns the statistics of the cache
+    pub fn stats(&self) -> JsonCacheStats {
+        JsonCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.values.len(),
+        }
+    }
+}
+
+impl JsonCacheClearingFile {
+    /// Wraps a file that is open for writing.
+    pub fn new(file: Box<dyn VfsFile>, json_cache: Arc<Mutex<JsonCache>>) -> JsonCacheClearingFile {
+        JsonCacheClearingFile { file, json_cache }
+    }
+
+    /// Removes all values from the json cache
+    fn clear_json_cache(&self) -> io::Result<()> {
+        self.json_cache
+            .lock()
+            .map_err(|err| io::Error::other(format!("Vfs: Error locking json cache: `{}`", err)))?
+            .clear();
+        Ok(())
+    }
+}
+
+impl VfsFile for JsonCacheClearingFile {
+    fn len(&self) -> io::Result<u64> {
+        self.file.len()
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        self.file.as_file()
+    }
+}
+
+impl fmt::Display for JsonCacheClearingFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.file, f)
+    }
+}
+
+impl io::Read for JsonCacheClearingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl io::Seek for JsonCacheClearingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl io::Write for JsonCacheClearingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = self.file.write(buf)?;
+        self.clear_json_cache()?;
+        Ok(bytes)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for JsonCacheClearingFile {
+    fn drop(&mut self) {
+        if let Err(err) = self.clear_json_cache() {
+            log::warn!("{}: {}", self.file, err);
+        }
+    }
+}
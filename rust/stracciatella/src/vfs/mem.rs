@@ -0,0 +1,437 @@
+//! This module contains a virtual filesystem that keeps the files in memory.
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::io::SeekFrom;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::math::checked_add_u64_i64;
+use crate::unicode::Nfc;
+use crate::vfs::{VfsFile, VfsLayer, VfsMetadata};
+
+/// A writable case-insensitive virtual filesystem that keeps the files in memory.
+///
+/// Clones share the same files, so a clone can be mounted in a `Vfs` and still be modified.
+#[derive(Clone, Debug, Default)]
+pub struct MemFs {
+    /// Display info.
+    pub name: String,
+    /// Files and directories.
+    inner: Arc<RwLock<MemFsInner>>,
+}
+
+/// Files and directories of a MemFs.
+#[derive(Debug, Default)]
+struct MemFsInner {
+    /// Data of the files by path.
+    files: BTreeMap<Nfc, Arc<RwLock<Vec<u8>>>>,
+    /// Explicitly created directories, parent directories of files are implicit.
+    dirs: BTreeSet<Nfc>,
+    /// Number of times the files and directories were locked for writing.
+    generation: u64,
+}
+
+/// A virtual file.
+#[derive(Debug)]
+pub struct MemFsFile {
+    /// Display info.
+    pub file_path: Nfc,
+    /// Display info.
+    pub name: String,
+    /// Data of the file, shared with the MemFs.
+    data: Arc<RwLock<Vec<u8>>>,
+    /// Whether the file was opened for writing.
+    writable: bool,
+    /// Current position.
+    position: u64,
+}
+
+impl MemFs {
+    /// Creates a new empty virtual filesystem.
+    pub fn new(name: &str) -> MemFs {
+        MemFs {
+            name: name.to_owned(),
+            inner: Default::default(),
+        }
+    }
+
+    /// Adds a file to the virtual filesystem, replacing an existing file.
+    pub fn insert(&self, file_path: &Nfc, data: Vec<u8>) -> io::Result<()> {
+        let file_path = normalize(file_path)?;
+        self.write()?.insert(file_path, data)?;
+        Ok(())
+    }
+
+    fn read(&self) -> io::Result<RwLockReadGuard<'_, MemFsInner>> {
+        self.inner
+            .read()
+            .map_err(|err| io::Error::other(format!("MemFs: Error locking files: `{}`", err)))
+    }
+
+    fn write(&self) -> io::Result<RwLockWriteGuard<'_, MemFsInner>> {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|err| io::Error::other(format!("MemFs: Error locking files: `{}`", err)))?;
+        inner.generation += 1;
+        Ok(inner)
+    }
+
+    fn open_file(&self, file_path: &Nfc, writable: bool) -> io::Result<Box<dyn VfsFile>> {
+        let inner = self.read()?;
+        match inner.files.get(&Nfc::from(file_path.trim_end_matches('/'))) {
+            Some(data) => Ok(Box::new(MemFsFile {
+                file_path: file_path.to_owned(),
+                name: self.name.to_owned(),
+                data: data.clone(),
+                writable,
+                position: 0,
+            })),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+impl MemFsInner {
+    /// Adds a file, replacing an existing file, and returns its data
+    fn insert(&mut self, file_path: Nfc, data: Vec<u8>) -> io::Result<Arc<RwLock<Vec<u8>>>> {
+        if self.is_dir(&file_path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is a directory", file_path),
+            ));
+        }
+        self.ensure_parents_are_dirs(&file_path)?;
+        let data = Arc::new(RwLock::new(data));
+        self.files.insert(file_path, data.clone());
+        Ok(data)
+    }
+
+    /// Fails if one of the parent directories of a path is a file
+    fn ensure_parents_are_dirs(&self, path: &str) -> io::Result<()> {
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            if self.files.contains_key(&Nfc::from(dir)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir),
+                ));
+            }
+            parent = dir;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the path is a directory
+    fn is_dir(&self, path: &str) -> bool {
+        if path.is_empty() || self.dirs.contains(&Nfc::from(path)) {
+            return true;
+        }
+        let prefix = format!("{}/", path);
+        self.files
+            .range(Nfc::from(prefix.as_str())..)
+            .next()
+            .map(|(x, _)| x.starts_with(&prefix))
+            .unwrap_or(false)
+    }
+
+    /// Adds the parent directories of a path
+    fn add_parent_dirs(&mut self, path: &str) {
+        let mut parent = path;
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            self.dirs.insert(Nfc::from(dir));
+            parent = dir;
+        }
+    }
+}
+
+impl VfsLayer for MemFs {
+    fn open(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.open_file(file_path, false)
+    }
+
+    fn exists(&self, file_path: &Nfc) -> io::Result<bool> {
+        let file_path = Nfc::from(file_path.trim_matches('/'));
+        let inner = self.read()?;
+        Ok(inner.files.contains_key(&file_path) || inner.is_dir(&file_path))
+    }
+
+    fn read_dir_recursive(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let path = file_path.trim_matches('/');
+        let inner = self.read()?;
+        if !inner.is_dir(path) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        Ok(inner
+            .files
+            .keys()
+            .filter(|x| x.starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn read_empty_dirs(&self) -> io::Result<BTreeSet<Nfc>> {
+        let inner = self.read()?;
+        let has_files = |dir: &Nfc| {
+            let prefix = format!("{}/", dir);
+            inner
+                .files
+                .range(Nfc::from(prefix.as_str())..)
+                .next()
+                .is_some_and(|(x, _)| x.starts_with(&prefix))
+        };
+        Ok(inner
+            .dirs
+            .iter()
+            .filter(|x| !has_files(x))
+            .cloned()
+            .collect())
+    }
+
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        let file_path = Nfc::from(file_path.trim_matches('/'));
+        let inner = self.read()?;
+        if let Some(data) = inner.files.get(&file_path) {
+            let len = data.read().expect("data").len() as u64;
+            Ok(VfsMetadata::file(self, len, None))
+        } else if inner.is_dir(&file_path) {
+            Ok(VfsMetadata::dir(self, None))
+        } else {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn read_dir(&self, path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let path = path.trim_matches('/');
+        let inner = self.read()?;
+        if !inner.is_dir(path) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        Ok(inner
+            .files
+            .keys()
+            .chain(inner.dirs.iter())
+            .filter_map(|x| x.strip_prefix(prefix.as_str()))
+            .filter_map(|x| x.split('/').next())
+            .map(Nfc::from)
+            .collect())
+    }
+
+    fn generation(&self) -> u64 {
+        self.read()
+            .map(|inner| inner.generation)
+            .unwrap_or_default()
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn create(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        let file_path = normalize(file_path)?;
+        let mut inner = self.write()?;
+        let data = inner.insert(file_path.clone(), vec![])?;
+        inner.add_parent_dirs(&file_path);
+        Ok(Box::new(MemFsFile {
+            file_path,
+            name: self.name.to_owned(),
+            data,
+            writable: true,
+            position: 0,
+        }))
+    }
+
+    fn open_writable(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.open_file(file_path, true)
+    }
+
+    fn create_dir(&self, file_path: &Nfc) -> io::Result<()> {
+        let file_path = normalize(file_path)?;
+        let mut inner = self.write()?;
+        if inner.files.contains_key(&file_path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is a file", file_path),
+            ));
+        }
+        inner.ensure_parents_are_dirs(&file_path)?;
+        inner.add_parent_dirs(&file_path);
+        inner.dirs.insert(file_path);
+        Ok(())
+    }
+
+    fn remove(&self, file_path: &Nfc) -> io::Result<()> {
+        let file_path = normalize(file_path)?;
+        let mut inner = self.write()?;
+        if inner.files.remove(&file_path).is_some() {
+            return Ok(());
+        }
+        if !inner.is_dir(&file_path) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let prefix = format!("{}/", file_path);
+        let has_children = inner
+            .files
+            .keys()
+            .chain(inner.dirs.iter())
+            .any(|x| x.starts_with(&prefix));
+        if has_children {
+            return Err(io::Error::other(format!(
+                "directory {} is not empty",
+                file_path
+            )));
+        }
+        inner.dirs.remove(&file_path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Nfc, to: &Nfc) -> io::Result<()> {
+        let from = normalize(from)?;
+        let to = normalize(to)?;
+        let mut inner = self.write()?;
+        inner.ensure_parents_are_dirs(&to)?;
+        if let Some(data) = inner.files.remove(&from) {
+            inner.add_parent_dirs(&to);
+            inner.files.insert(to, data);
+            return Ok(());
+        }
+        if !inner.is_dir(&from) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let from_prefix = format!("{}/", from);
+        let to_prefix = format!("{}/", to);
+        let rename = |x: &Nfc| Nfc::from(format!("{}{}", to_prefix, &x[from_prefix.len()..]));
+        let files: Vec<_> = inner
+            .files
+            .keys()
+            .filter(|x| x.starts_with(&from_prefix))
+            .cloned()
+            .collect();
+        for file in files {
+            let data = inner.files.remove(&file).expect("file was just listed");
+            inner.files.insert(rename(&file), data);
+        }
+        let dirs: Vec<_> = inner
+            .dirs
+            .iter()
+            .filter(|x| x.starts_with(&from_prefix))
+            .cloned()
+            .collect();
+        for dir in dirs {
+            inner.dirs.remove(&dir);
+            inner.dirs.insert(rename(&dir));
+        }
+        inner.dirs.remove(&from);
+        inner.add_parent_dirs(&to);
+        inner.dirs.insert(to);
+        Ok(())
+    }
+}
+
+impl VfsFile for MemFsFile {
+    /// Gets the length of the file.
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.data.read().expect("data").len() as u64)
+    }
+}
+
+impl fmt::Display for MemFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MemFs {{ {:?} }}", self.name)
+    }
+}
+
+impl fmt::Display for MemFsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MemFsFile {{ {:?} in {:?} }}", self.file_path, self.name)
+    }
+}
+
+impl io::Read for MemFsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.read().expect("data");
+        let start = usize::try_from(self.position)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let bytes = buf.len().min(data.len() - start);
+        buf[..bytes].copy_from_slice(&data[start..start + bytes]);
+        self.position += bytes as u64;
+        Ok(bytes)
+    }
+}
+
+impl io::Seek for MemFsFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position_option = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => checked_add_u64_i64(self.position, n),
+            SeekFrom::End(n) => checked_add_u64_i64(self.len()?, n),
+        };
+        match position_option {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            // underflow or overflow
+            None => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+}
+
+impl io::Write for MemFsFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "stracciatella::vfs::mem::MemFsFile was opened read-only",
+            ));
+        }
+        let position = usize::try_from(self.position)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut data = self.data.write().expect("data");
+        if data.len() < position {
+            data.resize(position, 0);
+        }
+        let overlap = buf.len().min(data.len() - position);
+        data[position..position + overlap].copy_from_slice(&buf[..overlap]);
+        data.extend_from_slice(&buf[overlap..]);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Removes leading and trailing separators and rejects special path components
+fn normalize(file_path: &Nfc) -> io::Result<Nfc> {
+    let file_path = file_path.trim_matches('/');
+    if file_path.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot write the root directory",
+        ));
+    }
+    if file_path
+        .split('/')
+        .any(|x| x.is_empty() || x == "." || x == "..")
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "special path components are not supported",
+        ));
+    }
+    Ok(Nfc::from(file_path))
+}
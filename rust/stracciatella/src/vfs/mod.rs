@@ -2,32 +2,59 @@
 //!
 //! The paths are case insensitive.
 //! It does not support path components `.` and `..`.
+//! Layers can hide paths of lower priority layers with whiteouts, see [`whiteout`].
 #![allow(dead_code)]
 
 #[cfg(target_os = "android")]
 pub mod android;
+pub mod compressed;
 pub mod dir;
+pub mod export;
+pub mod index;
+pub mod json_cache;
+pub mod mem;
+pub mod patch;
+pub mod prefix;
+pub mod provenance;
 pub mod slf;
+pub mod trace;
+pub mod walk;
+pub mod watch;
+pub mod whiteout;
+pub mod zip;
 
 use std::collections::BTreeSet;
 use std::fmt;
 use std::io;
 use std::io::ErrorKind;
+use std::io::{Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
 
 use json_patch::Patch;
 use log::{info, warn};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 
 use crate::EngineOptions;
+use crate::file_formats::slf::SlfEncoding;
 use crate::fs;
 use crate::json;
 use crate::mods::ModManager;
 use crate::mods::ModPath;
 use crate::unicode::Nfc;
-use crate::vfs::dir::DirFs;
+use crate::vfs::compressed::CompressedFs;
+use crate::vfs::dir::{DirFs, DirFsFile};
+use crate::vfs::index::{VfsIndex, layer_states};
+use crate::vfs::json_cache::{JsonCache, JsonCacheClearingFile, JsonCacheStats};
+use crate::vfs::prefix::PrefixFs;
 use crate::vfs::slf::SlfFs;
+use crate::vfs::trace::{VfsAccess, VfsAccessKind, VfsTrace};
+use crate::vfs::watch::{VfsChangeCallback, VfsWatchState};
+use crate::vfs::whiteout::{Whiteouts, ancestors, is_whiteout, whiteout_path};
+use crate::vfs::zip::ZipFs;
 
 pub trait VfsFile:
     io::Read + io::Seek + io::Write + fmt::Debug + fmt::Display + Send + Sync
@@ -39,6 +66,67 @@ pub trait VfsFile:
     fn is_empty(&self) -> io::Result<bool> {
         Ok(self.len()? == 0)
     }
+
+    /// Returns the filesystem file if the virtual file is backed by one
+    ///
+    /// The whole filesystem file is the content of the virtual file.
+    fn as_file(&self) -> Option<&fs::File> {
+        None
+    }
+}
+
+/// Metadata of a file or directory in the VFS
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VfsMetadata {
+    /// Length of the file in bytes, 0 for directories
+    pub len: u64,
+    /// Time of the last modification, if it is known
+    pub modified: Option<SystemTime>,
+    /// True if the path is a directory
+    pub is_dir: bool,
+    /// Display info of the VFS Layer that provides the path
+    pub layer: String,
+    /// Index of the VFS layer that provides the path, if it was queried through a Vfs
+    pub layer_index: Option<usize>,
+}
+
+impl VfsMetadata {
+    /// Creates the metadata of a file
+    pub fn file<T: fmt::Display + ?Sized>(
+        layer: &T,
+        len: u64,
+        modified: Option<SystemTime>,
+    ) -> VfsMetadata {
+        VfsMetadata {
+            len,
+            modified,
+            is_dir: false,
+            layer: layer.to_string(),
+            layer_index: None,
+        }
+    }
+
+    /// Creates the metadata of a directory
+    pub fn dir<T: fmt::Display + ?Sized>(layer: &T, modified: Option<SystemTime>) -> VfsMetadata {
+        VfsMetadata {
+            len: 0,
+            modified,
+            is_dir: true,
+            layer: layer.to_string(),
+            layer_index: None,
+        }
+    }
+}
+
+/// A logical path that matches several paths of a VFS Layer case insensitively
+///
+/// Only one of the paths is used, so the contents depend on the filesystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VfsCaseCollision {
+    /// Logical path
+    pub path: Nfc,
+    /// Matching paths in the VFS Layer, in the order they are tried
+    pub candidates: Vec<PathBuf>,
 }
 
 pub trait VfsLayer: fmt::Debug + fmt::Display + Send + Sync {
@@ -63,6 +151,102 @@ pub trait VfsLayer: fmt::Debug + fmt::Display + Send + Sync {
             .filter(|path| path.ends_with(extension.as_str()))
             .collect())
     }
+
+    /// Lists all files in a directory and its subdirectories in the VFS Layer
+    ///
+    /// The returned paths start with the directory path, directories are not included.
+    fn read_dir_recursive(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let dir = file_path.trim_matches('/');
+        let mut result = BTreeSet::new();
+        for name in self.read_dir(&Nfc::from(dir))? {
+            let path = if dir.is_empty() {
+                name
+            } else {
+                Nfc::from(format!("{}/{}", dir, name))
+            };
+            if self.metadata(&path)?.is_dir {
+                result.append(&mut self.read_dir_recursive(&path)?);
+            } else {
+                result.insert(path);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Lists all directories of the VFS Layer that contain no files, also not in their subdirectories
+    ///
+    /// Layers that cannot contain empty directories return an empty list.
+    fn read_empty_dirs(&self) -> io::Result<BTreeSet<Nfc>> {
+        Ok(BTreeSet::new())
+    }
+
+    /// Returns the metadata of a file or directory in the VFS Layer
+    ///
+    /// The default implementation opens the file to get its length.
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        match self.open(file_path) {
+            Ok(file) => Ok(VfsMetadata::file(self, file.len()?, None)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                if self.exists(file_path)? {
+                    Ok(VfsMetadata::dir(self, None))
+                } else {
+                    Err(err)
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns a number that changes whenever paths are added to or removed from the VFS Layer
+    ///
+    /// Layers that cannot change return 0.
+    fn generation(&self) -> u64 {
+        0
+    }
+
+    /// Starts watching the VFS Layer for changes
+    ///
+    /// The callback is called with the logical path of every change.
+    /// Returns false if the VFS Layer does not support watching.
+    fn watch(&self, _on_change: VfsChangeCallback) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Lists the logical paths that match several paths of the VFS Layer case insensitively
+    ///
+    /// Layers that cannot contain such paths return an empty list.
+    fn case_collisions(&self) -> io::Result<Vec<VfsCaseCollision>> {
+        Ok(vec![])
+    }
+
+    /// Returns true if files can be created, modified and removed in the VFS Layer
+    fn is_writable(&self) -> bool {
+        false
+    }
+    /// Creates a file in the VFS Layer and opens it for reading and writing
+    ///
+    /// An existing file is truncated. Missing parent directories are created.
+    fn create(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        Err(read_only_error(self, file_path))
+    }
+    /// Opens an existing file in the VFS Layer for reading and writing
+    fn open_writable(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        Err(read_only_error(self, file_path))
+    }
+    /// Creates a directory and all missing parent directories in the VFS Layer
+    fn create_dir(&self, file_path: &Nfc) -> io::Result<()> {
+        Err(read_only_error(self, file_path))
+    }
+    /// Removes a file or an empty directory from the VFS Layer
+    fn remove(&self, file_path: &Nfc) -> io::Result<()> {
+        Err(read_only_error(self, file_path))
+    }
+    /// Renames a file or directory in the VFS Layer
+    ///
+    /// An existing file at the destination is replaced.
+    fn rename(&self, from: &Nfc, _to: &Nfc) -> io::Result<()> {
+        Err(read_only_error(self, from))
+    }
 }
 
 /// A virtual filesystem that mounts other filesystems.
@@ -70,6 +254,27 @@ pub trait VfsLayer: fmt::Debug + fmt::Display + Send + Sync {
 pub struct Vfs {
     /// List of VFS layers ordered from highest to lowest priority.
     pub entries: Vec<Arc<dyn VfsLayer + Send + Sync>>,
+    /// Subscribers and pending changes of watched layers.
+    watch_state: Arc<VfsWatchState>,
+    /// Optional index of the paths in all layers.
+    index: RwLock<Option<VfsIndex>>,
+    /// Cache of patched json documents, shared with the files that are open for writing.
+    json_cache: Arc<Mutex<JsonCache>>,
+    /// Optional trace of the accessed paths.
+    trace: Mutex<Option<VfsTrace>>,
+    /// Whether `init` mounts SLF files in subdirectories and inside of other archives.
+    pub nested_slf_files: bool,
+    /// Whether `init` builds a path index for lookups, see `build_index`.
+    pub path_index: bool,
+    /// Whether the directories added by `add_dir*` and `init` are checked for ambiguous paths.
+    ///
+    /// Checking walks the whole layer, see `case_collisions_by_layer` for an explicit check.
+    pub check_case_collisions: bool,
+    /// Legacy encoding of SLF files with strings that are not valid UTF-8.
+    ///
+    /// `init` sets it to the encoding of the vanilla version when it is `None`, so the encoding is
+    /// only detected from the strings of each archive for layers that are added without `init`.
+    pub slf_encoding: Option<SlfEncoding>,
 }
 
 /// A virtual filesystem that mounts other filesystems.
@@ -91,6 +296,25 @@ impl Vfs {
         Vfs::default()
     }
 
+    /// Adds an existing filesystem layer.
+    /// The added layer will have lowest priority.
+    pub fn add_layer(&mut self, layer: Arc<dyn VfsLayer>) {
+        self.entries.push(layer);
+    }
+
+    /// Inserts an existing filesystem layer at a specific priority.
+    /// Index 0 is the highest priority, the number of layers is the lowest priority.
+    pub fn insert_layer(&mut self, index: usize, layer: Arc<dyn VfsLayer>) -> io::Result<()> {
+        if index > self.entries.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "layer index out of range",
+            ));
+        }
+        self.entries.insert(index, layer);
+        Ok(())
+    }
+
     /// Adds a filesystem layer backed by a filesystem directory.
     /// The added layer will have lowest priority.
     pub fn add_dir(&mut self, path: &Path) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
@@ -98,6 +322,84 @@ impl Vfs {
             path: path.to_owned(),
             error,
         })?;
+        self.warn_case_collisions(&*dir_fs);
+        self.entries.push(dir_fs.clone());
+        Ok(dir_fs)
+    }
+
+    /// Adds a filesystem layer backed by a filesystem directory with compressed files.
+    /// Files compressed with gzip (`.gz`) or zstd (`.zst`) are provided decompressed without the extension.
+    /// `init` uses it for mods, the home, externalized and vanilla data dirs and android assets are not decompressed.
+    /// The added layer will have lowest priority.
+    pub fn add_compressed_dir(&mut self, path: &Path) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
+        let dir_fs = DirFs::new(path).map_err(|error| VfsInitError {
+            path: path.to_owned(),
+            error,
+        })?;
+        let compressed_fs = CompressedFs::new(dir_fs);
+        self.warn_case_collisions(&*compressed_fs);
+        self.entries.push(compressed_fs.clone());
+        Ok(compressed_fs)
+    }
+
+    /// Adds a filesystem layer backed by a directory inside of a ZIP file with compressed files.
+    /// Files compressed with gzip (`.gz`) or zstd (`.zst`) are provided decompressed without the extension.
+    /// Pass an empty root to use the root of the archive.
+    /// The added layer will have lowest priority.
+    pub fn add_compressed_zip(
+        &mut self,
+        file: Box<dyn VfsFile>,
+        root: &Nfc,
+    ) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
+        let path = PathBuf::from(format!("{}", file));
+        let zip_fs =
+            ZipFs::new_with_root(file, root).map_err(|error| VfsInitError { path, error })?;
+        let compressed_fs = CompressedFs::new(zip_fs);
+        self.warn_case_collisions(&*compressed_fs);
+        self.entries.push(compressed_fs.clone());
+        Ok(compressed_fs)
+    }
+
+    /// Adds a filesystem layer backed by a filesystem directory that is mounted at a prefix.
+    /// The added layer will have lowest priority.
+    pub fn add_dir_at(
+        &mut self,
+        path: &Path,
+        prefix: &Nfc,
+    ) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
+        let prefix_fs = DirFs::new(path)
+            .and_then(|dir_fs| PrefixFs::new(dir_fs, prefix))
+            .map_err(|error| VfsInitError {
+                path: path.to_owned(),
+                error,
+            })?;
+        self.warn_case_collisions(&*prefix_fs);
+        self.entries.push(prefix_fs.clone());
+        Ok(prefix_fs)
+    }
+
+    /// Adds an existing filesystem layer with one of its directories mounted at a prefix.
+    /// Pass an empty root to mount the root of the layer.
+    /// The added layer will have lowest priority.
+    pub fn add_layer_at(
+        &mut self,
+        layer: Arc<dyn VfsLayer>,
+        root: &Nfc,
+        prefix: &Nfc,
+    ) -> io::Result<Arc<dyn VfsLayer>> {
+        let prefix_fs = PrefixFs::new_with_root(layer, root, prefix)?;
+        self.entries.push(prefix_fs.clone());
+        Ok(prefix_fs)
+    }
+
+    /// Adds a writable filesystem layer backed by a filesystem directory.
+    /// The added layer will have lowest priority.
+    pub fn add_writable_dir(&mut self, path: &Path) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
+        let dir_fs = DirFs::new_writable(path).map_err(|error| VfsInitError {
+            path: path.to_owned(),
+            error,
+        })?;
+        self.warn_case_collisions(&*dir_fs);
         self.entries.push(dir_fs.clone());
         Ok(dir_fs)
     }
@@ -105,12 +407,39 @@ impl Vfs {
     /// Adds a filesystem layer backed by a SLF file.
     /// The added layer will have lowest priority.
     pub fn add_slf(&mut self, file: Box<dyn VfsFile>) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
+        self.add_slf_in_dir(file, &Nfc::caseless_path(""))
+    }
+
+    /// Adds a filesystem layer backed by a SLF file that is inside of a directory.
+    /// The library path of the SLF file is relative to the directory.
+    /// The added layer will have lowest priority.
+    pub fn add_slf_in_dir(
+        &mut self,
+        file: Box<dyn VfsFile>,
+        dir_path: &Nfc,
+    ) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
         let path = PathBuf::from(format!("{}", file));
-        let slf_fs = SlfFs::new(file).map_err(|error| VfsInitError { path, error })?;
+        let slf_fs = SlfFs::new_in_dir_with_encoding(file, dir_path, self.slf_encoding)
+            .map_err(|error| VfsInitError { path, error })?;
         self.entries.push(slf_fs.clone());
         Ok(slf_fs)
     }
 
+    /// Adds a filesystem layer backed by a directory inside of a ZIP file.
+    /// Pass an empty root to use the root of the archive.
+    /// The added layer will have lowest priority.
+    pub fn add_zip(
+        &mut self,
+        file: Box<dyn VfsFile>,
+        root: &Nfc,
+    ) -> Result<Arc<dyn VfsLayer>, VfsInitError> {
+        let path = PathBuf::from(format!("{}", file));
+        let zip_fs =
+            ZipFs::new_with_root(file, root).map_err(|error| VfsInitError { path, error })?;
+        self.entries.push(zip_fs.clone());
+        Ok(zip_fs)
+    }
+
     /// Adds a filesystem layer backed by android assets.
     /// The added layer will have lowest priority.
     #[cfg(target_os = "android")]
@@ -152,6 +481,55 @@ impl Vfs {
         Ok(())
     }
 
+    /// Adds layers for all SLF files in the passed in layer and its subdirectories.
+    /// SLF files inside of the added SLF files are added too.
+    /// The added layers will have lowest priority.
+    pub fn add_nested_slf_files_from(
+        &mut self,
+        layer: Arc<dyn VfsLayer>,
+        required: bool,
+    ) -> Result<(), VfsInitError> {
+        // Layers without files, e.g. SLF files with only Old or Deleted entries, are NotFound
+        let files = match layer.read_dir_recursive(&Nfc::caseless_path("")) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            result => result.map_err(|error| VfsInitError {
+                path: PathBuf::from(format!("Error listing SLF files in {}", layer)),
+                error,
+            })?,
+        };
+        let slf_paths: Vec<_> = files.into_iter().filter(|x| x.ends_with(".slf")).collect();
+        if required && slf_paths.is_empty() {
+            return Err(VfsInitError {
+                path: PathBuf::from(format!("*.slf in {}", layer)),
+                error: ErrorKind::NotFound.into(),
+            });
+        }
+        for path in &slf_paths {
+            let file = layer.open(path).map_err(|error| VfsInitError {
+                path: PathBuf::from(format!("{} in {}", path, layer)),
+                error,
+            })?;
+            let dir_path = path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+            let slf_layer = self.add_slf_in_dir(file, &Nfc::from(dir_path))?;
+            self.add_nested_slf_files_from(slf_layer, false)?;
+        }
+        Ok(())
+    }
+
+    /// Adds layers for the SLF files in the passed in layer.
+    /// Nested SLF files are only added if `nested_slf_files` is set.
+    fn add_slf_files_of_layer(
+        &mut self,
+        layer: Arc<dyn VfsLayer>,
+        required: bool,
+    ) -> Result<(), VfsInitError> {
+        if self.nested_slf_files {
+            self.add_nested_slf_files_from(layer, required)
+        } else {
+            self.add_slf_files_from(layer, required)
+        }
+    }
+
     /// Adds the editor.slf layer to VFS
     fn add_editor_slf_layer(
         &mut self,
@@ -183,6 +561,9 @@ impl Vfs {
         mod_manager: &ModManager,
     ) -> Result<(), VfsInitError> {
         let vanilla_game_dir = engine_options.vanilla_game_dir.clone();
+        if self.slf_encoding.is_none() {
+            self.slf_encoding = Some(engine_options.resource_version.into());
+        }
         let vanilla_data_dir =
             fs::resolve_existing_components(Path::new(DATA_DIR), Some(&vanilla_game_dir), true);
 
@@ -197,16 +578,22 @@ impl Vfs {
             )
         }
 
-        // First is home data dir (does not need to exist)
+        // First is home data dir (writable, created when missing)
         let home_data_dir = fs::resolve_existing_components(
             &PathBuf::from(DATA_DIR),
             Some(&engine_options.stracciatella_home),
             true,
         );
+        if !home_data_dir.exists() && engine_options.stracciatella_home.exists() {
+            fs::create_dir_all(&home_data_dir).map_err(|error| VfsInitError {
+                path: home_data_dir.clone(),
+                error,
+            })?;
+        }
         if home_data_dir.exists() {
-            let layer = self.add_dir(&home_data_dir)?;
+            let layer = self.add_writable_dir(&home_data_dir)?;
             // home data dir can include slf files
-            self.add_slf_files_from(layer, false)?;
+            self.add_slf_files_of_layer(layer, false)?;
         }
 
         // Add mod directories
@@ -223,8 +610,19 @@ impl Vfs {
             match mod_path {
                 ModPath::Path(p) => {
                     let p = fs::resolve_existing_components(&p, None, true);
-                    let layer = self.add_dir(&p)?;
-                    self.add_slf_files_from(layer, false)?;
+                    let layer = self.add_compressed_dir(&p)?;
+                    self.add_slf_files_of_layer(layer, false)?;
+                }
+                ModPath::ZipPath(archive, p) => {
+                    let file = DirFsFile::open(&archive).map_err(|error| VfsInitError {
+                        path: archive.clone(),
+                        error,
+                    })?;
+                    let layer = self.add_compressed_zip(
+                        Box::new(file),
+                        &Nfc::caseless_path(&p.to_string_lossy()),
+                    )?;
+                    self.add_slf_files_of_layer(layer, false)?;
                 }
                 #[cfg(target_os = "android")]
                 ModPath::AndroidAssetPath(p) => {
@@ -233,7 +631,7 @@ impl Vfs {
                         error: e,
                     })?;
                     self.entries.push(layer.clone());
-                    self.add_slf_files_from(layer, false)?;
+                    self.add_slf_files_of_layer(layer, false)?;
                 }
             }
         }
@@ -256,7 +654,7 @@ impl Vfs {
         let data_dir_layer = self.add_dir(&vanilla_data_dir)?;
 
         // Next are SLF files in vanilla data dir
-        self.add_slf_files_from(data_dir_layer, true)?;
+        self.add_slf_files_of_layer(data_dir_layer, true)?;
 
         // Last is fallback editor.slf if it exists (does not need to exist)
         if engine_options.run_editor {
@@ -268,9 +666,220 @@ impl Vfs {
             info!("VFS layer {}: {}", index + 1, v);
         }
 
+        if self.path_index {
+            if let Err(err) = self.build_index() {
+                warn!("Could not build the VFS path index: {}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Logs a warning for every logical path that is ambiguous in a newly mounted VFS Layer
+    ///
+    /// Does nothing unless `check_case_collisions` is set.
+    fn warn_case_collisions(&self, layer: &dyn VfsLayer) {
+        if !self.check_case_collisions {
+            return;
+        }
+        match layer.case_collisions() {
+            Ok(collisions) => {
+                for collision in collisions {
+                    warn!(
+                        "Ambiguous path {} in {}, it matches {:?}",
+                        collision.path, layer, collision.candidates
+                    );
+                }
+            }
+            Err(err) => warn!("Could not check {} for ambiguous paths: {}", layer, err),
+        }
+    }
+
+    /// Builds an index of the paths in all layers and uses it for lookups
+    ///
+    /// Layers that changed are indexed again on the next lookup. If that fails, the index is
+    /// dropped and lookups ask the layers again.
+    /// Changes of directories that are neither writable through the VFS nor watched are not detected,
+    /// files that are added to them later are not found.
+    pub fn build_index(&self) -> io::Result<()> {
+        let index = VfsIndex::build(&self.entries)?;
+        *self.index.write().map_err(lock_error)? = Some(index);
+        Ok(())
+    }
+
+    /// Stops using the path index for lookups
+    pub fn drop_index(&self) -> io::Result<()> {
+        *self.index.write().map_err(lock_error)? = None;
+        Ok(())
+    }
+
+    /// Calls a function with the path index
+    ///
+    /// Returns None if there is no path index. The layers that changed are indexed again first.
+    fn with_index<T>(&self, f: impl Fn(&VfsIndex) -> T) -> io::Result<Option<T>> {
+        {
+            let index = self.index.read().map_err(lock_error)?;
+            match &*index {
+                None => return Ok(None),
+                Some(index) if index.is_current(&self.entries) => return Ok(Some(f(index))),
+                Some(_) => {}
+            }
+        }
+        let mut index = self.index.write().map_err(lock_error)?;
+        let Some(previous) = index.take() else {
+            return Ok(None);
+        };
+        let updated = if previous.is_current(&self.entries) {
+            Ok(previous)
+        } else {
+            log::debug!("updating VFS path index");
+            previous.update(&self.entries)
+        };
+        match updated {
+            Ok(updated) => Ok(Some(f(index.insert(updated)))),
+            Err(err) => {
+                warn!(
+                    "Could not update the VFS path index, it is not used anymore: {}",
+                    err
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the indexes of the layers that provide a path according to the path index
+    ///
+    /// Returns None if there is no path index.
+    fn indexed_layers(&self, path: &Nfc) -> io::Result<Option<Vec<usize>>> {
+        self.with_index(|index| index.get(path))
+    }
+
+    /// Calls a function with the indexes of the layers that might provide a path until it returns a value
+    ///
+    /// Layers that are hidden by a whiteout are skipped. Without a path index, each layer is asked for
+    /// whiteouts of the path after the function was called for it, so layers below the layer that
+    /// provides the path are not asked.
+    fn find_in_layers<T>(
+        &self,
+        path: &Nfc,
+        mut f: impl FnMut(usize) -> io::Result<Option<T>>,
+    ) -> io::Result<Option<T>> {
+        if let Some(layers) = self.indexed_layers(path)? {
+            for layer_index in layers {
+                if let Some(value) = f(layer_index)? {
+                    return Ok(Some(value));
+                }
+            }
+            return Ok(None);
+        }
+        if is_whiteout(path) {
+            return Ok(None);
+        }
+        for layer_index in 0..self.entries.len() {
+            if let Some(value) = f(layer_index)? {
+                return Ok(Some(value));
+            }
+            if self.has_whiteout(layer_index, path)? {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns true if a layer has a whiteout of the path or of one of its parent directories
+    fn has_whiteout(&self, layer_index: usize, path: &Nfc) -> io::Result<bool> {
+        let layer = &self.entries[layer_index];
+        for ancestor in ancestors(path) {
+            if layer.exists(&whiteout_path(ancestor))? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the index of the highest priority layer with a whiteout of the path or of one of its parent directories
+    fn whiteout_layer(&self, path: &Nfc) -> io::Result<Option<usize>> {
+        if let Some(layer_index) = self.with_index(|index| index.whiteout_layer(path))? {
+            return Ok(layer_index);
+        }
+        for layer_index in 0..self.entries.len() {
+            if self.has_whiteout(layer_index, path)? {
+                return Ok(Some(layer_index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Lists all files in a directory and its subdirectories in each layer
+    ///
+    /// Whiteouts and the files they hide are excluded.
+    fn read_dir_recursive_by_layer(&self, dir_path: &Nfc) -> io::Result<Vec<BTreeSet<Nfc>>> {
+        let end = self
+            .whiteout_layer(dir_path)?
+            .map_or(self.entries.len(), |x| x + 1);
+        let mut files_by_layer = Vec::with_capacity(self.entries.len());
+        let mut whiteouts = Whiteouts::default();
+        for (layer_index, layer) in self.entries.iter().enumerate() {
+            let files = if layer_index >= end {
+                BTreeSet::new()
+            } else {
+                match layer.read_dir_recursive(dir_path) {
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+                    result => result?,
+                }
+            };
+            for file in &files {
+                whiteouts.add(file, layer_index);
+            }
+            files_by_layer.push(files);
+        }
+        for (layer_index, files) in files_by_layer.iter_mut().enumerate() {
+            files.retain(|x| !is_whiteout(x) && !whiteouts.hides(x, layer_index));
+        }
+        Ok(files_by_layer)
+    }
+
+    /// Lists the logical paths that match several paths case insensitively in each layer
+    pub fn case_collisions_by_layer(&self) -> io::Result<Vec<Vec<VfsCaseCollision>>> {
+        self.entries.iter().map(|x| x.case_collisions()).collect()
+    }
+
+    /// Returns the index of the highest priority layer that a path exists in
+    fn exists_in_layer(&self, path: &Nfc) -> io::Result<Option<usize>> {
+        if let Some(layer_indexes) = self.indexed_layers(path)? {
+            return Ok(layer_indexes.first().copied());
+        }
+        self.find_in_layers(path, |layer_index| {
+            Ok(self.entries[layer_index]
+                .exists(path)?
+                .then_some(layer_index))
+        })
+    }
+
+    /// Starts watching all layers that support it for changes
+    ///
+    /// Changed logical paths are sent to subscribers and collected for `poll_changes`.
+    pub fn watch(&self) -> io::Result<()> {
+        let watch_state = self.watch_state.clone();
+        let on_change: VfsChangeCallback = Arc::new(move |path| watch_state.notify(path));
+        for layer in &self.entries {
+            if layer.watch(on_change.clone())? {
+                info!("Watching VFS layer {} for changes", layer);
+            }
+        }
         Ok(())
     }
 
+    /// Subscribes to the logical paths that change in watched layers
+    pub fn subscribe(&self) -> Receiver<Nfc> {
+        self.watch_state.subscribe()
+    }
+
+    /// Returns the logical paths that changed in watched layers since the last call
+    pub fn poll_changes(&self) -> BTreeSet<Nfc> {
+        self.watch_state.poll()
+    }
+
     /// Opens a file in a specific VFS layer given by its index
     pub fn open_in_layer(
         &self,
@@ -286,6 +895,8 @@ impl Vfs {
                     self.entries.len() - layer_index
                 )
             }
+            let served_by = res.as_ref().ok().map(|_| layer_index);
+            self.record_access(VfsAccessKind::Open, file_path, served_by);
             res
         } else {
             Err(io::Error::new(
@@ -298,19 +909,185 @@ impl Vfs {
     /// Returns the indexes of the layers that a path exists in
     /// The resulting vector is ordered by the highest priority layer last
     pub fn read_layers(&self, path: &Nfc) -> io::Result<Vec<usize>> {
+        if let Some(layers) = self.indexed_layers(path)? {
+            return Ok(layers);
+        }
         let mut result = vec![];
 
-        for (idx, layer) in self.entries.iter().enumerate() {
-            if layer.exists(path)? {
+        self.find_in_layers(path, |idx| {
+            if self.entries[idx].exists(path)? {
                 result.push(idx);
             }
-        }
+            Ok(None::<()>)
+        })?;
 
         Ok(result)
     }
 
+    /// Returns the index of the highest priority writable layer
+    fn writable_layer_index(&self) -> io::Result<usize> {
+        self.entries
+            .iter()
+            .position(|layer| layer.is_writable())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "there is no writable VFS layer",
+                )
+            })
+    }
+
+    /// Returns the index of the writable layer that a write to path should go to
+    ///
+    /// Fails if a read-only layer with a higher priority provides the path,
+    /// because the written file would not be visible.
+    fn writable_layer_index_for(&self, path: &Nfc) -> io::Result<usize> {
+        let writable_index = self.writable_layer_index()?;
+        for (layer_index, layer) in self.entries[..writable_index].iter().enumerate() {
+            if layer.exists(path)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "{} is shadowed by read-only layer {}",
+                        path,
+                        self.entries.len() - layer_index
+                    ),
+                ));
+            }
+        }
+        Ok(writable_index)
+    }
+
     /// Opens a json file and applies optional patches on higher priority VFS layers
+    ///
+    /// See [`patch`] for the supported patches and the order they are applied in.
+    /// The patched values are cached until the layers change or a file is written through the VFS,
+    /// files that are open for writing clear the cache whenever they are written and when they are closed.
+    /// Files that are modified outside of the VFS are only detected in watched layers.
     pub fn read_patched_json(&self, path: &Nfc) -> io::Result<Value> {
+        let layers = layer_states(&self.entries);
+        let cached = self.lock_json_cache()?.get(&layers, path);
+        if let Some(value) = cached {
+            self.record_cached_json_access(path)?;
+            return Ok(value);
+        }
+        let value = self.read_patched_json_uncached(path)?;
+        self.lock_json_cache()?
+            .insert(&layers, path.clone(), value.clone());
+        Ok(value)
+    }
+
+    /// Returns the statistics of the cache of `read_patched_json`
+    pub fn json_cache_stats(&self) -> io::Result<JsonCacheStats> {
+        Ok(self.lock_json_cache()?.stats())
+    }
+
+    /// Removes all values from the cache of `read_patched_json`
+    pub fn clear_json_cache(&self) -> io::Result<()> {
+        self.lock_json_cache()?.clear();
+        Ok(())
+    }
+
+    /// Locks the cache of `read_patched_json`
+    fn lock_json_cache(&self) -> io::Result<std::sync::MutexGuard<'_, JsonCache>> {
+        self.json_cache
+            .lock()
+            .map_err(|err| io::Error::other(format!("Vfs: Error locking json cache: `{}`", err)))
+    }
+
+    /// Starts recording the accessed paths, see `trace`
+    ///
+    /// If a dump path is given, the trace is written to it when tracing stops or the VFS is dropped.
+    /// A running trace is stopped first.
+    /// Repeated accesses are recorded once, so the trace grows with the number of different accesses.
+    pub fn start_trace(&self, dump_path: Option<&Path>) -> io::Result<()> {
+        let previous = self
+            .lock_trace()?
+            .replace(VfsTrace::new(dump_path.map(Path::to_owned)));
+        if let Some(previous) = previous {
+            previous.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Stops recording the accessed paths and returns the recorded accesses
+    ///
+    /// The trace is written to its dump path, if it has one.
+    pub fn stop_trace(&self) -> io::Result<Vec<VfsAccess>> {
+        let trace = self.lock_trace()?.take();
+        trace.map_or(Ok(Vec::new()), VfsTrace::finish)
+    }
+
+    /// Returns the accesses recorded so far, empty if tracing is not enabled
+    pub fn traced_accesses(&self) -> io::Result<Vec<VfsAccess>> {
+        Ok(self
+            .lock_trace()?
+            .as_ref()
+            .map(|x| x.accesses().to_vec())
+            .unwrap_or_default())
+    }
+
+    /// Writes the accesses recorded so far to a file as JSON
+    pub fn dump_trace(&self, path: &Path) -> io::Result<()> {
+        match self.lock_trace()?.as_ref() {
+            Some(trace) => trace.dump(path),
+            None => Err(io::Error::other("Vfs: tracing is not enabled")),
+        }
+    }
+
+    /// Locks the trace of the accessed paths
+    fn lock_trace(&self) -> io::Result<std::sync::MutexGuard<'_, Option<VfsTrace>>> {
+        self.trace
+            .lock()
+            .map_err(|err| io::Error::other(format!("Vfs: Error locking trace: `{}`", err)))
+    }
+
+    /// Records an access to a path and the layers that served it if tracing is enabled
+    fn record_access(
+        &self,
+        kind: VfsAccessKind,
+        path: &Nfc,
+        layer_indexes: impl IntoIterator<Item = usize>,
+    ) {
+        if let Ok(mut trace) = self.trace.lock() {
+            if let Some(trace) = trace.as_mut() {
+                let layers = layer_indexes
+                    .into_iter()
+                    .map(|x| self.entries[x].to_string())
+                    .collect();
+                trace.record(kind, path, layers);
+            }
+        }
+    }
+
+    /// Records the json file and the patches of a cached json document if tracing is enabled
+    ///
+    /// The files are recorded as opens, like when the document is not cached.
+    fn record_cached_json_access(&self, path: &Nfc) -> io::Result<()> {
+        if self.lock_trace()?.is_none() {
+            return Ok(());
+        }
+        let Some(&highest_prio_file_layer) = self.read_layers(path)?.first() else {
+            return Ok(());
+        };
+        self.record_access(VfsAccessKind::Open, path, [highest_prio_file_layer]);
+        let [patch_path, merge_path] =
+            patch::patch_paths(&Nfc::caseless_path(path)).expect("path with .json extension");
+        let patch_layers = self.read_layers(&patch_path)?;
+        let merge_layers = self.read_layers(&merge_path)?;
+        for layer in (0..=highest_prio_file_layer).rev() {
+            if patch_layers.contains(&layer) {
+                self.record_access(VfsAccessKind::Open, &patch_path, [layer]);
+            }
+            if merge_layers.contains(&layer) {
+                self.record_access(VfsAccessKind::Open, &merge_path, [layer]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a json file and applies optional patches without using the cache
+    fn read_patched_json_uncached(&self, path: &Nfc) -> io::Result<Value> {
         if path
             .as_str()
             .rsplit('.')
@@ -323,90 +1100,105 @@ impl Vfs {
                 "patched json must end in .json extension",
             ));
         }
-        let patch_path =
-            Nfc::caseless_path(&format!("{}.patch.json", &path.as_str()[..path.len() - 5]));
+        let [patch_path, merge_path] =
+            patch::patch_paths(&Nfc::caseless_path(path)).expect("path with .json extension");
         let file_layers = self.read_layers(path)?;
         let highest_prio_file_layer = if let Some(p) = file_layers.first() {
-            Ok(p)
+            Ok(*p)
         } else {
             Err(io::Error::new(io::ErrorKind::NotFound, "entity not found"))
         }?;
-        let mut value: Value = {
-            let mut file = self.open_in_layer(*highest_prio_file_layer, path)?;
-            let mut content = String::new();
-            file.read_to_string(&mut content)?;
-            json::de::from_string(&content).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("failed to deserialize json: {}", e),
-                )
-            })?
-        };
+        let mut value: Value = self.read_json_in_layer(highest_prio_file_layer, path)?;
 
         let patch_layers = self.read_layers(&patch_path)?;
+        let merge_layers = self.read_layers(&merge_path)?;
         // Order patches from lowest to highest priority
-        for patch_layer in patch_layers.iter().rev() {
-            // Ignore patches with lower priority than the highest priority layer
-            if patch_layer > highest_prio_file_layer {
-                continue;
+        for layer in (0..=highest_prio_file_layer).rev() {
+            if patch_layers.contains(&layer) {
+                let patch_value: Patch = self.read_json_in_layer(layer, &patch_path)?;
+                json_patch::patch(&mut value, &patch_value).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("failed to apply patch to json: {}", e),
+                    )
+                })?;
             }
-
-            let patch_value: Patch = {
-                let mut file = self.open_in_layer(*patch_layer, &patch_path)?;
-                let mut content = String::new();
-                file.read_to_string(&mut content)?;
-                json::de::from_string(&content).map_err(|e| {
+            if merge_layers.contains(&layer) {
+                let merge_value: Value = self.read_json_in_layer(layer, &merge_path)?;
+                patch::merge(&mut value, &merge_value).map_err(|e| {
                     io::Error::new(
                         io::ErrorKind::InvalidData,
-                        format!("failed to deserialize json: {}", e),
+                        format!("failed to apply merge patch to json: {}", e),
                     )
-                })?
-            };
-
-            json_patch::patch(&mut value, &patch_value).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("failed to apply patch to json: {}", e),
-                )
-            })?;
+                })?;
+            }
         }
 
         Ok(value)
     }
+
+    /// Reads and deserializes a json file in a specific VFS layer
+    fn read_json_in_layer<T: DeserializeOwned>(
+        &self,
+        layer_index: usize,
+        path: &Nfc,
+    ) -> io::Result<T> {
+        let mut file = self.open_in_layer(layer_index, path)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        json::de::from_string(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to deserialize json: {}", e),
+            )
+        })
+    }
 }
 
 impl VfsLayer for Vfs {
     fn open(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
-        for (layer_index, entry) in self.entries.iter().enumerate() {
-            let file_result = entry.open(file_path);
-            if let Err(err) = &file_result {
-                if err.kind() == io::ErrorKind::NotFound {
-                    continue;
-                }
-            } else {
+        let found = self.find_in_layers(file_path, |layer_index| {
+            match self.entries[layer_index].open(file_path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                result => result.map(|file| Some((layer_index, file))),
+            }
+        });
+        match found {
+            Ok(Some((layer_index, file))) => {
                 log::debug!(
                     "opened file {} in layer {}",
                     file_path,
                     self.entries.len() - layer_index
-                )
+                );
+                self.record_access(VfsAccessKind::Open, file_path, Some(layer_index));
+                Ok(file)
+            }
+            Ok(None) => {
+                self.record_access(VfsAccessKind::Open, file_path, None);
+                Err(io::ErrorKind::NotFound.into())
+            }
+            Err(err) => {
+                self.record_access(VfsAccessKind::Open, file_path, None);
+                Err(err)
             }
-            return file_result;
         }
-        Err(io::ErrorKind::NotFound.into())
     }
 
     fn exists(&self, file_path: &Nfc) -> io::Result<bool> {
-        for entry in &self.entries {
-            if entry.exists(file_path)? {
-                return Ok(true);
-            }
-        }
-        Ok(false)
+        let layer_index = self.exists_in_layer(file_path)?;
+        self.record_access(VfsAccessKind::Exists, file_path, layer_index);
+        Ok(layer_index.is_some())
     }
 
+    /// Lists a directory in all layers that are not hidden by a whiteout
     fn read_dir(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let end = self
+            .whiteout_layer(file_path)?
+            .map_or(self.entries.len(), |x| x + 1);
         let mut entries = BTreeSet::new();
-        for entry in self.entries.iter() {
+        let mut whiteouts = Whiteouts::default();
+        let mut served_by = Vec::new();
+        for (layer_index, entry) in self.entries[..end].iter().enumerate() {
             let layer_result = entry.read_dir(file_path);
             if let Err(err) = &layer_result {
                 if err.kind() == io::ErrorKind::NotFound {
@@ -414,16 +1206,148 @@ impl VfsLayer for Vfs {
                 }
             }
             let layer_result = layer_result?;
+            served_by.push(layer_index);
+            for result in &layer_result {
+                whiteouts.add(result, layer_index);
+            }
             for result in layer_result {
-                entries.insert(result);
+                if !is_whiteout(&result) && !whiteouts.hides(&result, layer_index) {
+                    entries.insert(result);
+                }
             }
         }
+        if entries.is_empty() {
+            served_by.clear();
+        }
+        self.record_access(VfsAccessKind::ReadDir, file_path, served_by);
         if entries.is_empty() {
             Err(io::ErrorKind::NotFound.into())
         } else {
             Ok(entries)
         }
     }
+
+    fn read_dir_recursive(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let mut result = BTreeSet::new();
+        let mut served_by = Vec::new();
+        for (layer_index, mut files) in self
+            .read_dir_recursive_by_layer(file_path)?
+            .into_iter()
+            .enumerate()
+        {
+            if !files.is_empty() {
+                served_by.push(layer_index);
+            }
+            result.append(&mut files);
+        }
+        self.record_access(VfsAccessKind::ReadDirRecursive, file_path, served_by);
+        if result.is_empty() {
+            Err(io::ErrorKind::NotFound.into())
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Returns the metadata from the highest priority layer that provides the path
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        self.find_in_layers(file_path, |layer_index| {
+            match self.entries[layer_index].metadata(file_path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                result => result.map(|metadata| {
+                    Some(VfsMetadata {
+                        layer_index: Some(layer_index),
+                        ..metadata
+                    })
+                }),
+            }
+        })?
+        .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    fn generation(&self) -> u64 {
+        self.entries
+            .iter()
+            .fold(self.entries.len() as u64, |sum, layer| {
+                sum.wrapping_add(layer.generation())
+            })
+    }
+
+    fn is_writable(&self) -> bool {
+        self.entries.iter().any(|layer| layer.is_writable())
+    }
+
+    /// Creates a file in the highest priority writable layer
+    ///
+    /// The file shadows files with the same path in lower priority layers.
+    fn create(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        let layer_index = self.writable_layer_index_for(file_path)?;
+        let file = self.entries[layer_index].create(file_path)?;
+        Ok(Box::new(JsonCacheClearingFile::new(
+            file,
+            self.json_cache.clone(),
+        )))
+    }
+
+    /// Opens a file for reading and writing in the highest priority writable layer
+    ///
+    /// A file that is only provided by lower priority layers is copied to the writable layer first.
+    fn open_writable(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        let layer_index = self.writable_layer_index_for(file_path)?;
+        let writable_layer = &self.entries[layer_index];
+        match writable_layer.open_writable(file_path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            result => {
+                return result.map(|file| -> Box<dyn VfsFile> {
+                    Box::new(JsonCacheClearingFile::new(file, self.json_cache.clone()))
+                });
+            }
+        }
+        for layer in &self.entries[layer_index + 1..] {
+            let mut source = match layer.open(file_path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                result => result?,
+            };
+            let mut file = writable_layer.create(file_path)?;
+            io::copy(&mut source, &mut file)?;
+            file.seek(SeekFrom::Start(0))?;
+            log::debug!("copied file {} to writable layer {}", file_path, layer);
+            return Ok(Box::new(JsonCacheClearingFile::new(
+                file,
+                self.json_cache.clone(),
+            )));
+        }
+        Err(io::ErrorKind::NotFound.into())
+    }
+
+    fn create_dir(&self, file_path: &Nfc) -> io::Result<()> {
+        let layer_index = self.writable_layer_index()?;
+        self.entries[layer_index].create_dir(file_path)
+    }
+
+    /// Removes a file or an empty directory from the highest priority writable layer
+    ///
+    /// Files with the same path in lower priority layers become visible again.
+    fn remove(&self, file_path: &Nfc) -> io::Result<()> {
+        let layer_index = self.writable_layer_index()?;
+        self.entries[layer_index].remove(file_path)?;
+        self.clear_json_cache()
+    }
+
+    /// Renames a file or directory in the highest priority writable layer
+    ///
+    /// Paths that are provided by read-only layers cannot be renamed.
+    fn rename(&self, from: &Nfc, to: &Nfc) -> io::Result<()> {
+        let layer_index = self.writable_layer_index_for(to)?;
+        let writable_layer = &self.entries[layer_index];
+        if !writable_layer.exists(from)? && self.exists(from)? {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is provided by a read-only layer", from),
+            ));
+        }
+        writable_layer.rename(from, to)?;
+        self.clear_json_cache()
+    }
 }
 
 impl fmt::Display for Vfs {
@@ -452,6 +1376,19 @@ impl fmt::Display for VfsInitError {
     }
 }
 
+/// Error for write operations on read-only VFS layers
+fn read_only_error<T: fmt::Display + ?Sized>(layer: &T, file_path: &Nfc) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("{} is read-only, cannot write {}", layer, file_path),
+    )
+}
+
+/// Converts a poisoned lock of the path index to an io error
+fn lock_error<T>(err: std::sync::PoisonError<T>) -> io::Error {
+    io::Error::other(format!("Vfs: Error locking path index: `{}`", err))
+}
+
 fn map_not_found_to_option<T>(result: io::Result<T>) -> io::Result<Option<T>> {
     match result {
         Ok(t) => Ok(Some(t)),
@@ -469,9 +1406,11 @@ fn map_not_found_to_option<T>(result: io::Result<T>) -> io::Result<Option<T>> {
 mod tests {
     use tempfile::{TempDir, tempdir};
 
-    use crate::{config::EngineOptions, mods::ModManager};
+    use std::io::{Read, Write};
+
+    use crate::{config::EngineOptions, mods::ModManager, unicode::Nfc};
 
-    use super::Vfs;
+    use super::{Vfs, VfsLayer};
 
     #[test]
     fn missing_game_data_dir_should_fail() {
@@ -556,6 +1495,43 @@ mod tests {
         vfs.init(&engine_options, &mod_manager).unwrap();
     }
 
+    #[test]
+    fn zip_mod_data_dir_should_be_mounted() {
+        let (mut engine_options, _temp_dir) = create_test_engine_options();
+        engine_options.mods = vec!["test-mod".to_owned()];
+        std::fs::create_dir_all(engine_options.stracciatella_home.join("mods"))
+            .expect("create `mods` dir");
+        let file =
+            std::fs::File::create(engine_options.stracciatella_home.join("mods/test-mod.zip"))
+                .expect("create `test-mod.zip`");
+        let mut zip = zip::ZipWriter::new(file);
+        for (name, content) in [
+            ("manifest.json", r#"{ "name": "test", "version": "1" }"#),
+            ("Data/Foo.txt", "foo"),
+        ] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .expect("start zip file");
+            zip.write_all(content.as_bytes()).expect("write zip file");
+        }
+        zip.finish().expect("finish zip");
+        let mod_manager = ModManager::new_unchecked(&engine_options);
+
+        let data_path = engine_options.vanilla_game_dir.join("data");
+        std::fs::create_dir(&data_path).expect("create `data` dir");
+        std::fs::write(data_path.join("empty.slf"), EMPTY_SLF_BYTES).expect("write `empty.slf`");
+
+        let mut vfs = Vfs::new();
+        vfs.init(&engine_options, &mod_manager).unwrap();
+
+        let mut content = String::new();
+        vfs.open(&Nfc::caseless_path("foo.txt"))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "foo");
+        assert!(!vfs.exists(&Nfc::caseless_path("manifest.json")).unwrap());
+    }
+
     const EMPTY_SLF_BYTES: &[u8] = include_bytes!("test_fixtures/empty.slf");
 
     fn create_test_engine_options() -> (EngineOptions, TempDir) {
@@ -0,0 +1,172 @@
+//! This module contains the json patches that are applied by `Vfs::read_patched_json`.
+//!
+//! A json file `<name>.json` can be patched by files in the same or higher priority layers:
+//!  * `<name>.patch.json` is a JSON Patch ([RFC 6902]) document
+//!  * `<name>.merge.json` is a JSON Merge Patch ([RFC 7386]) document
+//!
+//! The patches are applied from the lowest to the highest priority layer.
+//! In each layer the JSON Patch is applied before the JSON Merge Patch.
+//!
+//! Merge patches can merge arrays of objects by key instead of replacing them.
+//! An object with the `$mergeBy` and `$values` members merges each value into
+//! the array element with the same value of the key, or appends it if there is none.
+//! A value with `"$delete": true` removes the element instead.
+//!
+//! ```json
+//! { "$mergeBy": "internalName", "$values": [ { "internalName": "GLOCK_17", "ubWeight": 7 } ] }
+//! ```
+//!
+//! [RFC 6902]: https://tools.ietf.org/html/rfc6902
+//! [RFC 7386]: https://tools.ietf.org/html/rfc7386
+
+use serde_json::{Map, Value};
+
+use crate::unicode::Nfc;
+
+/// Extension of JSON Patch files
+pub const PATCH_EXTENSION: &str = ".patch.json";
+
+/// Extension of JSON Merge Patch files
+pub const MERGE_EXTENSION: &str = ".merge.json";
+
+/// Member with the key of a keyed array merge
+pub const MERGE_BY_MEMBER: &str = "$mergeBy";
+
+/// Member with the values of a keyed array merge
+pub const VALUES_MEMBER: &str = "$values";
+
+/// Member that removes an element in a keyed array merge
+pub const DELETE_MEMBER: &str = "$delete";
+
+/// Returns the paths of the patches of a json file, in the order they are applied in a layer
+pub fn patch_paths(json_path: &Nfc) -> Option<[Nfc; 2]> {
+    let base = json_path.strip_suffix(".json")?;
+    Some([
+        Nfc::from(format!("{}{}", base, PATCH_EXTENSION)),
+        Nfc::from(format!("{}{}", base, MERGE_EXTENSION)),
+    ])
+}
+
+/// Returns the path of the json file that a patch applies to, or None if the path is not a patch
+pub fn patched_path(patch_path: &str) -> Option<Nfc> {
+    [PATCH_EXTENSION, MERGE_EXTENSION]
+        .iter()
+        .find_map(|x| patch_path.strip_suffix(x))
+        .map(|x| Nfc::from(format!("{}.json", x)))
+}
+
+/// Applies a JSON Merge Patch with keyed array merges to a value
+pub fn merge(target: &mut Value, patch: &Value) -> Result<(), String> {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        patch => {
+            *target = patch.clone();
+            return Ok(());
+        }
+    };
+    if patch.contains_key(MERGE_BY_MEMBER) {
+        return merge_by_key(target, patch);
+    }
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let target = target.as_object_mut().expect("object");
+    for (name, value) in patch {
+        if value.is_null() {
+            target.remove(name);
+        } else {
+            merge(target.entry(name.as_str()).or_insert(Value::Null), value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Merges the values of a keyed array merge into the array elements with the same key
+fn merge_by_key(target: &mut Value, patch: &Map<String, Value>) -> Result<(), String> {
+    let key = patch[MERGE_BY_MEMBER]
+        .as_str()
+        .ok_or_else(|| format!("{} must be a string", MERGE_BY_MEMBER))?;
+    let values = patch
+        .get(VALUES_MEMBER)
+        .and_then(|x| x.as_array())
+        .ok_or_else(|| format!("{} must be an array", VALUES_MEMBER))?;
+    let target = target
+        .as_array_mut()
+        .ok_or_else(|| format!("{} {:?} needs an array to merge into", MERGE_BY_MEMBER, key))?;
+    for value in values {
+        let id = value
+            .get(key)
+            .ok_or_else(|| format!("merged value without key {:?}: {}", key, value))?;
+        let position = target.iter().position(|x| x.get(key) == Some(id));
+        let delete = value.get(DELETE_MEMBER) == Some(&Value::Bool(true));
+        let mut value = value.clone();
+        if let Some(value) = value.as_object_mut() {
+            value.remove(DELETE_MEMBER);
+        }
+        match (position, delete) {
+            (Some(position), true) => {
+                target.remove(position);
+            }
+            (Some(position), false) => merge(&mut target[position], &value)?,
+            (None, true) => {}
+            (None, false) => {
+                let mut element = Value::Null;
+                merge(&mut element, &value)?;
+                target.push(element);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::merge;
+
+    #[test]
+    fn merge_patch() {
+        let mut value = json!({ "a": 1, "b": { "c": 2, "d": 3 }, "e": [1, 2] });
+        merge(
+            &mut value,
+            &json!({ "a": null, "b": { "c": 4 }, "e": [3], "f": { "g": null } }),
+        )
+        .unwrap();
+        assert_eq!(value, json!({ "b": { "c": 4, "d": 3 }, "e": [3], "f": {} }));
+    }
+
+    #[test]
+    fn merge_by_key() {
+        let mut value = json!([
+            { "id": 1, "name": "a", "tags": [ { "k": "x", "v": 1 } ] },
+            { "id": 2, "name": "b" },
+        ]);
+        let patch = json!({
+            "$mergeBy": "id",
+            "$values": [
+                { "id": 1, "name": null, "tags": { "$mergeBy": "k", "$values": [ { "k": "x", "v": 2 } ] } },
+                { "id": 2, "$delete": true },
+                { "id": 3, "name": "c", "extra": null },
+            ]
+        });
+        merge(&mut value, &patch).unwrap();
+        assert_eq!(
+            value,
+            json!([
+                { "id": 1, "tags": [ { "k": "x", "v": 2 } ] },
+                { "id": 3, "name": "c" },
+            ])
+        );
+
+        assert!(merge(&mut json!({}), &patch).is_err());
+        assert!(merge(&mut json!([]), &json!({ "$mergeBy": "id" })).is_err());
+        assert!(
+            merge(
+                &mut json!([]),
+                &json!({ "$mergeBy": "id", "$values": [{}] })
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! This module contains a virtual filesystem that mounts another layer under a path prefix.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::unicode::Nfc;
+use crate::vfs::watch::VfsChangeCallback;
+use crate::vfs::{VfsCaseCollision, VfsFile, VfsLayer, VfsMetadata};
+
+/// A virtual filesystem that provides a directory of another VFS layer under a path prefix.
+///
+/// The parent directories of the prefix exist and only contain the next component of the prefix.
+#[derive(Clone, Debug)]
+pub struct PrefixFs {
+    /// Case-insensitive path where the directory is mounted, empty for the root.
+    pub prefix: Nfc,
+    /// Case-insensitive path of the mounted directory in the inner layer, empty for the root.
+    pub root: Nfc,
+    /// The inner layer.
+    pub layer: Arc<dyn VfsLayer>,
+}
+
+/// Where a path of a `PrefixFs` is.
+enum PrefixPath {
+    /// Inside of the prefix, with the path in the inner layer.
+    Inner(Nfc),
+    /// A parent directory of the prefix.
+    Parent(String),
+    /// Outside of the prefix.
+    Outside,
+}
+
+impl PrefixFs {
+    /// Creates a new virtual filesystem that provides the root of a layer under a prefix.
+    pub fn new(layer: Arc<dyn VfsLayer>, prefix: &Nfc) -> io::Result<Arc<PrefixFs>> {
+        Self::new_with_root(layer, &Nfc::caseless_path(""), prefix)
+    }
+
+    /// Creates a new virtual filesystem that provides a directory of a layer under a prefix.
+    ///
+    /// Paths outside of the root directory are not provided.
+    pub fn new_with_root(
+        layer: Arc<dyn VfsLayer>,
+        root: &Nfc,
+        prefix: &Nfc,
+    ) -> io::Result<Arc<PrefixFs>> {
+        let root = Nfc::from(root.trim_matches('/'));
+        let prefix = Nfc::from(prefix.trim_matches('/'));
+        if !root.is_empty() && !layer.exists(&root)? {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} not found in {}", root, layer),
+            ));
+        }
+        Ok(Arc::new(PrefixFs {
+            prefix,
+            root,
+            layer,
+        }))
+    }
+
+    /// Maps a path of this filesystem
+    fn map(&self, file_path: &Nfc) -> PrefixPath {
+        let path = file_path.trim_matches('/');
+        let relative = if self.prefix.is_empty() {
+            Some(path)
+        } else if path == self.prefix.as_str() {
+            Some("")
+        } else {
+            path.strip_prefix(self.prefix.as_str())
+                .and_then(|x| x.strip_prefix('/'))
+        };
+        match relative {
+            Some(relative) => PrefixPath::Inner(join(&self.root, relative)),
+            None if path.is_empty() => PrefixPath::Parent(String::new()),
+            None => match self.prefix.strip_prefix(path) {
+                Some(rest) if rest.starts_with('/') => PrefixPath::Parent(path.to_owned()),
+                _ => PrefixPath::Outside,
+            },
+        }
+    }
+
+    /// Maps a path of the inner layer to a path of this filesystem
+    ///
+    /// Returns None if the path is outside of the root.
+    fn unmap(&self, inner_path: &str) -> Option<Nfc> {
+        let inner_path = inner_path.trim_matches('/');
+        let relative = if self.root.is_empty() {
+            inner_path
+        } else if inner_path == self.root.as_str() {
+            ""
+        } else {
+            inner_path
+                .strip_prefix(self.root.as_str())?
+                .strip_prefix('/')?
+        };
+        Some(join(&self.prefix, relative))
+    }
+
+    /// Returns the inner path of a path for write operations
+    fn writable_path(&self, file_path: &Nfc) -> io::Result<Nfc> {
+        match self.map(file_path) {
+            PrefixPath::Inner(path) => Ok(path),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is outside of {}", file_path, self),
+            )),
+        }
+    }
+}
+
+impl VfsLayer for PrefixFs {
+    fn open(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        match self.map(file_path) {
+            PrefixPath::Inner(path) => self.layer.open(&path),
+            _ => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn exists(&self, file_path: &Nfc) -> io::Result<bool> {
+        match self.map(file_path) {
+            PrefixPath::Inner(path) => self.layer.exists(&path),
+            PrefixPath::Parent(_) => Ok(true),
+            PrefixPath::Outside => Ok(false),
+        }
+    }
+
+    fn read_dir(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        match self.map(file_path) {
+            PrefixPath::Inner(path) => self.layer.read_dir(&path),
+            PrefixPath::Parent(parent) => {
+                let rest = self.prefix[parent.len()..].trim_start_matches('/');
+                let name = rest.split('/').next().unwrap_or_default();
+                Ok(BTreeSet::from([Nfc::from(name)]))
+            }
+            PrefixPath::Outside => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn read_dir_recursive(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let inner_path = match self.map(file_path) {
+            PrefixPath::Inner(path) => path,
+            PrefixPath::Parent(_) => self.root.clone(),
+            PrefixPath::Outside => return Err(io::ErrorKind::NotFound.into()),
+        };
+        Ok(self
+            .layer
+            .read_dir_recursive(&inner_path)?
+            .iter()
+            .filter_map(|x| self.unmap(x))
+            .collect())
+    }
+
+    fn read_empty_dirs(&self) -> io::Result<BTreeSet<Nfc>> {
+        Ok(self
+            .layer
+            .read_empty_dirs()?
+            .iter()
+            .filter_map(|x| self.unmap(x))
+            .collect())
+    }
+
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        match self.map(file_path) {
+            PrefixPath::Inner(path) => Ok(VfsMetadata {
+                layer: self.to_string(),
+                ..self.layer.metadata(&path)?
+            }),
+            PrefixPath::Parent(_) => Ok(VfsMetadata::dir(self, None)),
+            PrefixPath::Outside => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.layer.generation()
+    }
+
+    /// Watches the inner layer and maps the changed paths
+    fn watch(&self, on_change: VfsChangeCallback) -> io::Result<bool> {
+        let mapper = self.clone();
+        self.layer.watch(Arc::new(move |path: Nfc| {
+            if let Some(path) = mapper.unmap(&path) {
+                on_change(path);
+            }
+        }))
+    }
+
+    /// Returns the collisions inside of the root with their mapped paths
+    fn case_collisions(&self) -> io::Result<Vec<VfsCaseCollision>> {
+        Ok(self
+            .layer
+            .case_collisions()?
+            .into_iter()
+            .filter_map(|x| {
+                Some(VfsCaseCollision {
+                    path: self.unmap(&x.path)?,
+                    ..x
+                })
+            })
+            .collect())
+    }
+
+    fn is_writable(&self) -> bool {
+        self.layer.is_writable()
+    }
+
+    fn create(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.layer.create(&self.writable_path(file_path)?)
+    }
+
+    fn open_writable(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        self.layer.open_writable(&self.writable_path(file_path)?)
+    }
+
+    fn create_dir(&self, file_path: &Nfc) -> io::Result<()> {
+        match self.map(file_path) {
+            PrefixPath::Parent(_) => Ok(()),
+            _ => self.layer.create_dir(&self.writable_path(file_path)?),
+        }
+    }
+
+    fn remove(&self, file_path: &Nfc) -> io::Result<()> {
+        self.layer.remove(&self.writable_path(file_path)?)
+    }
+
+    fn rename(&self, from: &Nfc, to: &Nfc) -> io::Result<()> {
+        self.layer
+            .rename(&self.writable_path(from)?, &self.writable_path(to)?)
+    }
+}
+
+impl fmt::Display for PrefixFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrefixFs {{ {} at {:?} }}", self.layer, self.prefix)
+    }
+}
+
+/// Joins two normalized relative paths
+fn join(base: &str, relative: &str) -> Nfc {
+    match (base.is_empty(), relative.is_empty()) {
+        (true, _) => Nfc::from(relative),
+        (_, true) => Nfc::from(base),
+        _ => Nfc::from(format!("{}/{}", base, relative)),
+    }
+}
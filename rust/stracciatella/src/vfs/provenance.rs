@@ -0,0 +1,89 @@
+//! This module contains the provenance report of the virtual filesystem.
+//!
+//! The report lists which layer provides each logical path and which lower
+//! priority layers are shadowed by it.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::unicode::Nfc;
+use crate::vfs::Vfs;
+use crate::vfs::patch::{patch_paths, patched_path};
+
+/// Provenance of a logical path in the VFS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VfsProvenance {
+    /// Logical path of the file.
+    pub path: Nfc,
+    /// Indexes of the layers that contain the file, ordered from highest to lowest priority.
+    ///
+    /// The first layer provides the file, all other layers are shadowed by it.
+    pub layers: Vec<usize>,
+    /// Indexes of the layers with a patch that is applied to the file, ordered from highest to lowest priority.
+    pub patches: Vec<usize>,
+    /// Indexes of the layers with a patch that is ignored, because the file is provided by a higher priority layer.
+    pub ignored_patches: Vec<usize>,
+}
+
+impl VfsProvenance {
+    /// Returns the index of the layer that provides the file
+    pub fn provider(&self) -> usize {
+        self.layers[0]
+    }
+
+    /// Returns the indexes of the layers that are shadowed by the provider
+    pub fn shadowed(&self) -> &[usize] {
+        &self.layers[1..]
+    }
+
+    /// Returns true if files or patches of lower priority layers are not used
+    pub fn is_shadowing(&self) -> bool {
+        self.layers.len() > 1 || !self.ignored_patches.is_empty()
+    }
+}
+
+impl Vfs {
+    /// Returns the provenance of all files in a directory and its subdirectories
+    ///
+    /// Json patches and merge patches are reported together with the json file they apply to.
+    /// Patches without a json file are reported as plain files.
+    pub fn provenance(&self, dir_path: &Nfc) -> io::Result<Vec<VfsProvenance>> {
+        let mut layers_by_path: BTreeMap<Nfc, Vec<usize>> = BTreeMap::new();
+        let files_by_layer = self.read_dir_recursive_by_layer(dir_path)?;
+        for (layer_index, paths) in files_by_layer.into_iter().enumerate() {
+            for path in paths {
+                layers_by_path.entry(path).or_default().push(layer_index);
+            }
+        }
+
+        let mut result = Vec::new();
+        for (path, layers) in &layers_by_path {
+            if patched_path(path).is_some_and(|x| layers_by_path.contains_key(&x)) {
+                continue;
+            }
+            let mut provenance = VfsProvenance {
+                path: path.clone(),
+                layers: layers.clone(),
+                patches: vec![],
+                ignored_patches: vec![],
+            };
+            if let Some(paths) = patch_paths(path) {
+                let mut patch_layers: Vec<usize> = paths
+                    .iter()
+                    .filter_map(|x| layers_by_path.get(x))
+                    .flatten()
+                    .copied()
+                    .collect();
+                patch_layers.sort_unstable();
+                patch_layers.dedup();
+                let (patches, ignored_patches) = patch_layers
+                    .iter()
+                    .partition(|&&x| x <= provenance.provider());
+                provenance.patches = patches;
+                provenance.ignored_patches = ignored_patches;
+            }
+            result.push(provenance);
+        }
+        Ok(result)
+    }
+}
@@ -6,13 +6,16 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::io;
-use std::io::{Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom};
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use crate::file_formats::slf::{SlfEntryState, SlfHeader};
+use crate::file_formats::slf::{SlfEncoding, SlfEntryState, SlfHeader, SlfValidator};
+use crate::fs;
+use crate::fs::File;
 use crate::math::checked_add_u64_i64;
 use crate::unicode::Nfc;
-use crate::vfs::{VfsFile, VfsLayer};
+use crate::vfs::{VfsFile, VfsLayer, VfsMetadata};
 
 /// A read-only case-insensitive virtual filesystem backed by a SLF file.
 #[derive(Debug)]
@@ -20,7 +23,7 @@ pub struct SlfFs {
     /// Display info.
     pub slf_path: String,
     /// SLF archive open for reading.
-    pub slf_file: Arc<Mutex<Box<dyn VfsFile>>>,
+    pub slf_file: Arc<SlfSource>,
     /// Case-insensitive base path.
     pub prefix: Nfc,
     /// List of entries
@@ -36,6 +39,8 @@ pub struct SlfFsEntry {
     pub offset: u32,
     /// Length of the data.
     pub length: u32,
+    /// Modification time of the file.
+    pub file_time: Option<SystemTime>,
 }
 
 /// A virtual file.
@@ -46,7 +51,7 @@ pub struct SlfFsFile {
     /// Display info.
     pub slf_path: String,
     /// SLF archive open for reading.
-    pub slf_file: Arc<Mutex<Box<dyn VfsFile>>>,
+    pub slf_file: Arc<SlfSource>,
     /// Start of the data.
     pub offset: u32,
     /// Length of the data.
@@ -55,13 +60,78 @@ pub struct SlfFsFile {
     pub position: u64,
 }
 
+/// SLF archive open for reading.
+#[derive(Debug)]
+pub enum SlfSource {
+    /// Filesystem file, read with positional reads that do not need a lock.
+    File(File),
+    /// Generic virtual file, reads seek and read under a lock.
+    VfsFile(Mutex<Box<dyn VfsFile>>),
+}
+
+impl SlfSource {
+    /// Uses positional reads if the virtual file is backed by a filesystem file.
+    pub fn new(vfs_file: Box<dyn VfsFile>) -> SlfSource {
+        if cfg!(any(unix, windows)) {
+            if let Some(file) = vfs_file.as_file().and_then(|x| x.try_clone().ok()) {
+                return SlfSource::File(file);
+            }
+        }
+        SlfSource::VfsFile(Mutex::new(vfs_file))
+    }
+
+    /// Reads data at an offset from the start of the archive.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        match self {
+            SlfSource::File(file) => fs::read_at(file, buf, offset),
+            SlfSource::VfsFile(vfs_file) => {
+                let mut vfs_file = vfs_file.lock().expect("slf_file");
+                vfs_file.seek(SeekFrom::Start(offset))?;
+                vfs_file.read(buf)
+            }
+        }
+    }
+}
+
 impl SlfFs {
     /// Creates a new virtual filesystem.
-    pub fn new(mut slf_file: Box<dyn VfsFile>) -> io::Result<Arc<SlfFs>> {
-        let header = SlfHeader::from_input(&mut slf_file)?;
-        let prefix = Nfc::caseless_path(header.library_path.trim_end_matches('/'));
-        let entries: HashMap<_, _> = header
-            .entries_from_input(&mut slf_file)?
+    pub fn new(slf_file: Box<dyn VfsFile>) -> io::Result<Arc<SlfFs>> {
+        Self::new_in_dir(slf_file, &Nfc::caseless_path(""))
+    }
+
+    /// Creates a new virtual filesystem for a SLF file that is inside of a directory.
+    ///
+    /// The library path of the SLF file is relative to the directory.
+    pub fn new_in_dir(slf_file: Box<dyn VfsFile>, dir_path: &Nfc) -> io::Result<Arc<SlfFs>> {
+        Self::new_in_dir_with_encoding(slf_file, dir_path, None)
+    }
+
+    /// Creates a new virtual filesystem for a SLF file that is inside of a directory.
+    ///
+    /// The legacy encoding is used if the strings of the SLF file are not valid UTF-8,
+    /// it is detected when `None`.
+    pub fn new_in_dir_with_encoding(
+        mut slf_file: Box<dyn VfsFile>,
+        dir_path: &Nfc,
+        legacy_encoding: Option<SlfEncoding>,
+    ) -> io::Result<Arc<SlfFs>> {
+        let (header, entries) = SlfHeader::with_entries_from_input(&mut slf_file, legacy_encoding)?;
+        let library_path = Nfc::caseless_path(header.library_path.trim_end_matches('/'));
+        let dir_path = dir_path.trim_matches('/');
+        let prefix = if dir_path.is_empty() {
+            library_path
+        } else {
+            Nfc::caseless_path(&format!("{}/{}", dir_path, library_path))
+        };
+        // The order of the entries does not matter here
+        let validator = SlfValidator {
+            check_order: false,
+            legacy_encoding,
+        };
+        for problem in validator.validate_entries(&header, &entries, slf_file.len()?) {
+            log::warn!("{}: {}", slf_file, problem);
+        }
+        let entries: HashMap<_, _> = entries
             .into_iter()
             .filter(|x| x.state == SlfEntryState::Ok)
             .map(|x| {
@@ -71,13 +141,14 @@ impl SlfFs {
                     path,
                     offset: x.offset,
                     length: x.length,
+                    file_time: x.to_system_time(),
                 };
                 (full_path, entry)
             })
             .collect();
         Ok(Arc::new(SlfFs {
             slf_path: format!("{}", slf_file),
-            slf_file: Arc::new(Mutex::new(slf_file)),
+            slf_file: Arc::new(SlfSource::new(slf_file)),
             prefix,
             entries,
         }))
@@ -118,6 +189,41 @@ impl VfsLayer for SlfFs {
             > 0)
     }
 
+    fn read_dir_recursive(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let prefix = file_path.trim_matches('/');
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", prefix)
+        };
+        let result: BTreeSet<Nfc> = self
+            .entries
+            .keys()
+            .filter(|x| x.starts_with(&prefix))
+            .cloned()
+            .collect();
+        if result.is_empty() {
+            Err(io::ErrorKind::NotFound.into())
+        } else {
+            Ok(result)
+        }
+    }
+
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        if let Some(entry) = self.entries.get(file_path) {
+            return Ok(VfsMetadata::file(
+                self,
+                u64::from(entry.length),
+                entry.file_time,
+            ));
+        }
+        if self.exists(file_path)? {
+            Ok(VfsMetadata::dir(self, None))
+        } else {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+
     fn read_dir(&self, path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
         // Remove trailing slashes from directories
         let path = path.trim_end_matches('/');
@@ -175,15 +281,15 @@ impl fmt::Display for SlfFsFile {
 
 impl io::Read for SlfFsFile {
     fn read(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
-        let mut slf_file = self.slf_file.lock().expect("slf_file");
         let available = u64::from(self.length).saturating_sub(self.position);
         if let Ok(available) = usize::try_from(available) {
             if buf.len() > available {
                 buf = &mut buf[..available];
             }
         }
-        slf_file.seek(SeekFrom::Start(self.position + u64::from(self.offset)))?;
-        let read_result = slf_file.read(buf);
+        let read_result = self
+            .slf_file
+            .read_at(buf, self.position + u64::from(self.offset));
         if let Ok(bytes) = read_result {
             self.position += u64::try_from(bytes).expect("u64");
         }
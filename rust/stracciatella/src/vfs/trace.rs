@@ -0,0 +1,123 @@
+//! This module contains the access trace of the virtual filesystem.
+//!
+//! When tracing is enabled, the `Vfs` records every logical path that is opened,
+//! checked with `exists` or listed, together with the layers that served it.
+//! Reads of patched json documents are recorded as opens of the json file and its
+//! patches, also when the document is served from the json cache.
+//! Repeated accesses with the same kind, path and layers are recorded once.
+//!
+//! The trace is written as a JSON array of accesses:
+//!
+//! ```json
+//! [ { "kind": "open", "path": "binarydata/ja2set.dat", "layers": ["SlfFs { source: BinaryData.slf }"], "timestamp_ms": 1700000000000 } ]
+//! ```
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use serde::Serialize;
+
+use crate::unicode::Nfc;
+
+/// Kind of access to a logical path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VfsAccessKind {
+    /// The file was opened.
+    Open,
+    /// The path was checked with `exists`.
+    Exists,
+    /// The directory was listed.
+    ReadDir,
+    /// The directory was listed recursively.
+    ReadDirRecursive,
+}
+
+/// A recorded access to a logical path.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VfsAccess {
+    /// Kind of access.
+    pub kind: VfsAccessKind,
+    /// Logical path.
+    pub path: String,
+    /// Layers that served the access, ordered from highest to lowest priority.
+    /// Empty if the path was not found.
+    pub layers: Vec<String>,
+    /// Time of the first access in milliseconds since the UNIX epoch.
+    pub timestamp_ms: u64,
+}
+
+/// Recorded accesses of a `Vfs`.
+///
+/// The accesses are written to the dump path when the trace is finished or dropped.
+#[derive(Debug, Default)]
+pub struct VfsTrace {
+    /// Accesses in the order they first happened.
+    accesses: Vec<VfsAccess>,
+    /// Kind, path and layers of the recorded accesses.
+    recorded: HashSet<(VfsAccessKind, Nfc, Vec<String>)>,
+    /// File that the accesses are written to at the end.
+    dump_path: Option<PathBuf>,
+}
+
+impl VfsTrace {
+    /// Creates a new trace with an optional file that the accesses are written to at the end
+    pub fn new(dump_path: Option<PathBuf>) -> VfsTrace {
+        VfsTrace {
+            accesses: Vec::new(),
+            recorded: HashSet::new(),
+            dump_path,
+        }
+    }
+
+    /// Records an access to a path, unless the same access was recorded before
+    pub fn record(&mut self, kind: VfsAccessKind, path: &Nfc, layers: Vec<String>) {
+        if !self.recorded.insert((kind, path.clone(), layers.clone())) {
+            return;
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_millis() as u64);
+        self.accesses.push(VfsAccess {
+            kind,
+            path: path.to_string(),
+            layers,
+            timestamp_ms,
+        });
+    }
+
+    /// Returns the recorded accesses
+    pub fn accesses(&self) -> &[VfsAccess] {
+        &self.accesses
+    }
+
+    /// Writes the recorded accesses to a file as JSON
+    pub fn dump(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &self.accesses)?;
+        writer.flush()
+    }
+
+    /// Finishes the trace, writes it to the dump path and returns the recorded accesses
+    pub fn finish(mut self) -> io::Result<Vec<VfsAccess>> {
+        if let Some(dump_path) = self.dump_path.take() {
+            self.dump(&dump_path)?;
+        }
+        Ok(std::mem::take(&mut self.accesses))
+    }
+}
+
+impl Drop for VfsTrace {
+    fn drop(&mut self) {
+        if let Some(dump_path) = &self.dump_path {
+            if let Err(err) = self.dump(dump_path) {
+                warn!("Failed to write VFS trace to {:?}: {}", dump_path, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! This module contains recursive queries on the virtual filesystem.
+//!
+//! Glob patterns are case insensitive and support these wildcards:
+//!  * `?` matches any character except `/`
+//!  * `*` matches any number of characters except `/`
+//!  * `**` as a path component matches any number of directories
+
+use std::collections::BTreeMap;
+use std::io;
+
+use regex::Regex;
+
+use crate::unicode::Nfc;
+use crate::vfs::Vfs;
+
+/// A file in the VFS and the layer that provides it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VfsEntry {
+    /// Logical path of the file.
+    pub path: Nfc,
+    /// Index of the highest priority layer that provides the file.
+    pub layer_index: usize,
+}
+
+impl Vfs {
+    /// Lists all files in a directory and its subdirectories, ordered by path
+    pub fn walk(&self, dir_path: &Nfc) -> io::Result<Vec<VfsEntry>> {
+        let mut layer_by_path = BTreeMap::new();
+        let files_by_layer = self.read_dir_recursive_by_layer(dir_path)?;
+        for (layer_index, paths) in files_by_layer.into_iter().enumerate() {
+            for path in paths {
+                layer_by_path.entry(path).or_insert(layer_index);
+            }
+        }
+        if layer_by_path.is_empty() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(layer_by_path
+            .into_iter()
+            .map(|(path, layer_index)| VfsEntry { path, layer_index })
+            .collect())
+    }
+
+    /// Lists all files that match a glob pattern, ordered by path
+    ///
+    /// Returns an empty list if no file matches.
+    pub fn glob(&self, pattern: &str) -> io::Result<Vec<VfsEntry>> {
+        let pattern = Nfc::caseless_path(pattern.trim_matches('/'));
+        let regex = glob_to_regex(&pattern)?;
+        // Only walk the directory that contains all matches
+        let components: Vec<_> = pattern.split('/').collect();
+        let dir_path: Vec<_> = components[..components.len() - 1]
+            .iter()
+            .take_while(|x| !x.contains(['*', '?']))
+            .copied()
+            .collect();
+        let dir_path = Nfc::from(dir_path.join("/"));
+        let entries = match self.walk(&dir_path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            result => result?,
+        };
+        Ok(entries
+            .into_iter()
+            .filter(|x| regex.is_match(&x.path))
+            .collect())
+    }
+}
+
+/// Converts a glob pattern to an anchored regular expression
+fn glob_to_regex(pattern: &str) -> io::Result<Regex> {
+    let mut regex = String::from("^");
+    let components: Vec<_> = pattern.split('/').collect();
+    for (index, component) in components.iter().enumerate() {
+        let is_last = index + 1 == components.len();
+        if *component == "**" {
+            regex.push_str(if is_last { ".*" } else { "(?:[^/]*/)*" });
+            continue;
+        }
+        for c in component.chars() {
+            match c {
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        if !is_last {
+            regex.push('/');
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
@@ -0,0 +1,68 @@
+//! This module contains the change notifications of the virtual filesystem.
+//!
+//! Layers that support watching report changed logical paths through a [`VfsChangeCallback`].
+//! The [`Vfs`] forwards them to its subscribers and collects them for polling.
+//!
+//! [`Vfs`]: ../struct.Vfs.html
+//! [`VfsChangeCallback`]: type.VfsChangeCallback.html
+
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use crate::unicode::Nfc;
+
+/// Callback that is called with the logical path of every changed file or directory.
+///
+/// It is called from a background thread.
+pub type VfsChangeCallback = Arc<dyn Fn(Nfc) + Send + Sync>;
+
+/// Subscribers and pending changes of a watched virtual filesystem.
+#[derive(Debug, Default)]
+pub struct VfsWatchState {
+    /// Senders of all subscriptions, closed subscriptions are removed on the next change.
+    subscribers: Mutex<Vec<Sender<Nfc>>>,
+    /// Changed paths since the last poll.
+    pending: Mutex<BTreeSet<Nfc>>,
+}
+
+impl VfsWatchState {
+    /// Adds a subscription that receives every changed path.
+    pub fn subscribe(&self) -> Receiver<Nfc> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().expect("subscribers").push(sender);
+        receiver
+    }
+
+    /// Returns the changed paths since the last poll.
+    pub fn poll(&self) -> BTreeSet<Nfc> {
+        std::mem::take(&mut *self.pending.lock().expect("pending"))
+    }
+
+    /// Reports a changed path to the subscribers and to the next poll.
+    pub fn notify(&self, path: Nfc) {
+        log::debug!("VFS path changed: {}", path);
+        self.pending.lock().expect("pending").insert(path.clone());
+        self.subscribers
+            .lock()
+            .expect("subscribers")
+            .retain(|sender| sender.send(path.clone()).is_ok());
+    }
+}
+
+/// Maps a filesystem path inside of a base directory to a logical path.
+///
+/// Returns None if the path is outside of the base directory.
+pub fn logical_path(base: &Path, path: &Path) -> Option<Nfc> {
+    let relative = path.strip_prefix(base).ok()?;
+    let components: Option<Vec<_>> = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(s) => Some(s.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    Some(Nfc::caseless_path(&components?.join("/")))
+}
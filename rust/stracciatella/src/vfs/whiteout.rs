@@ -0,0 +1,91 @@
+//! This module contains the whiteouts of the virtual filesystem.
+//!
+//! A whiteout is a marker file named `.wh.<name>` that hides `<name>` in all
+//! lower priority layers, similar to overlayfs. The content of the marker file
+//! is ignored.
+//!
+//! A whiteout of a directory hides the whole directory in lower priority layers.
+//! Files of the directory in the same layer as the whiteout stay visible, so a
+//! layer can replace a directory instead of merging with it.
+//!
+//! Whiteouts themselves are not visible in the VFS.
+
+use std::collections::HashMap;
+
+use crate::unicode::Nfc;
+
+/// Prefix of the file name of a whiteout
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Returns the path of the whiteout that hides a path
+pub fn whiteout_path(path: &str) -> Nfc {
+    let path = path.trim_matches('/');
+    match path.rsplit_once('/') {
+        Some((dir, name)) => Nfc::from(format!("{}/{}{}", dir, WHITEOUT_PREFIX, name)),
+        None => Nfc::from(format!("{}{}", WHITEOUT_PREFIX, path)),
+    }
+}
+
+/// Returns true if the file name of a path marks a whiteout
+pub fn is_whiteout(path: &str) -> bool {
+    whiteout_target(path).is_some()
+}
+
+/// Returns the path that a whiteout hides, or None if the path is not a whiteout
+pub fn whiteout_target(path: &str) -> Option<Nfc> {
+    let path = path.trim_matches('/');
+    match path.rsplit_once('/') {
+        Some((dir, name)) => name
+            .strip_prefix(WHITEOUT_PREFIX)
+            .filter(|x| !x.is_empty())
+            .map(|x| Nfc::from(format!("{}/{}", dir, x))),
+        None => path
+            .strip_prefix(WHITEOUT_PREFIX)
+            .filter(|x| !x.is_empty())
+            .map(Nfc::from),
+    }
+}
+
+/// Iterates over a path and its parent directories, the root directory is not included
+pub fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.trim_matches('/');
+    std::iter::successors(Some(path).filter(|x| !x.is_empty()), |x| {
+        x.rsplit_once('/').map(|(dir, _)| dir)
+    })
+}
+
+/// Whiteouts of a list of layers ordered from highest to lowest priority
+#[derive(Debug, Default)]
+pub struct Whiteouts {
+    /// Index of the highest priority layer with a whiteout of each hidden path
+    hidden: HashMap<Nfc, usize>,
+}
+
+impl Whiteouts {
+    /// Adds a path of a layer, returns true if it is a whiteout
+    pub fn add(&mut self, path: &str, layer_index: usize) -> bool {
+        match whiteout_target(path) {
+            Some(target) => {
+                let hiding_layer = self.hidden.entry(target).or_insert(layer_index);
+                *hiding_layer = (*hiding_layer).min(layer_index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the highest priority layer with a whiteout of the path or of one of its parent directories
+    pub fn hiding_layer(&self, path: &str) -> Option<usize> {
+        if self.hidden.is_empty() {
+            return None;
+        }
+        ancestors(path)
+            .filter_map(|x| self.hidden.get(&Nfc::from(x)).copied())
+            .min()
+    }
+
+    /// Returns true if the path is hidden in a layer by a whiteout of a higher priority layer
+    pub fn hides(&self, path: &str, layer_index: usize) -> bool {
+        self.hiding_layer(path).is_some_and(|x| layer_index > x)
+    }
+}
@@ -0,0 +1,281 @@
+//! This module contains a virtual filesystem backed by a ZIP file.
+#![allow(dead_code)]
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::{Cursor, Read, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+use zip::ZipArchive;
+use zip::result::ZipError;
+
+use crate::unicode::Nfc;
+use crate::vfs::{VfsFile, VfsLayer, VfsMetadata};
+
+/// A read-only case-insensitive virtual filesystem backed by a ZIP file.
+#[derive(Debug)]
+pub struct ZipFs {
+    /// Display info.
+    pub zip_path: String,
+    /// ZIP archive open for reading.
+    pub zip_archive: Mutex<ZipArchive<Box<dyn VfsFile>>>,
+    /// Case-insensitive path inside the archive that is used as the root of the filesystem.
+    pub root: Nfc,
+    /// List of file entries
+    pub entries: HashMap<Nfc, ZipFsEntry>,
+    /// List of directories, including the implicit parent directories of all entries
+    pub dirs: BTreeSet<Nfc>,
+}
+
+/// A file entry.
+#[derive(Debug)]
+pub struct ZipFsEntry {
+    /// Index of the file in the archive.
+    pub index: usize,
+    /// Uncompressed length of the data.
+    pub length: u64,
+}
+
+/// A virtual file.
+///
+/// The data is decompressed when the file is opened.
+#[derive(Debug)]
+pub struct ZipFsFile {
+    /// Display info.
+    pub file_path: Nfc,
+    /// Display info.
+    pub zip_path: String,
+    /// Decompressed data.
+    pub data: Cursor<Vec<u8>>,
+}
+
+impl ZipFs {
+    /// Creates a new virtual filesystem with the root of the archive as root.
+    pub fn new(zip_file: Box<dyn VfsFile>) -> io::Result<Arc<ZipFs>> {
+        Self::new_with_root(zip_file, &Nfc::caseless_path(""))
+    }
+
+    /// Creates a new virtual filesystem with a directory inside the archive as root.
+    ///
+    /// Entries outside of the root directory are ignored.
+    pub fn new_with_root(zip_file: Box<dyn VfsFile>, root: &Nfc) -> io::Result<Arc<ZipFs>> {
+        let zip_path = format!("{}", zip_file);
+        let mut zip_archive = ZipArchive::new(zip_file).map_err(zip_error_to_io_error)?;
+        let root = root.trim_matches('/');
+        let root = if root.is_empty() {
+            Nfc::caseless_path("")
+        } else {
+            Nfc::caseless_path(&format!("{}/", root))
+        };
+
+        let mut entries = HashMap::new();
+        let mut dirs = BTreeSet::new();
+        for index in 0..zip_archive.len() {
+            let zip_file = zip_archive
+                .by_index_raw(index)
+                .map_err(zip_error_to_io_error)?;
+            let path = Nfc::caseless_path(zip_file.name());
+            let Some(path) = path.strip_prefix(root.as_str()) else {
+                continue;
+            };
+            let path = path.trim_matches('/');
+            if path.is_empty() {
+                continue;
+            }
+            let mut parent = path;
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                dirs.insert(Nfc::from(dir));
+                parent = dir;
+            }
+            if zip_file.is_dir() {
+                dirs.insert(Nfc::from(path));
+            } else {
+                entries.insert(
+                    Nfc::from(path),
+                    ZipFsEntry {
+                        index,
+                        length: zip_file.size(),
+                    },
+                );
+            }
+        }
+        if !root.is_empty() && entries.is_empty() && dirs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} not found in {}", root, zip_path),
+            ));
+        }
+
+        Ok(Arc::new(ZipFs {
+            zip_path,
+            zip_archive: Mutex::new(zip_archive),
+            root,
+            entries,
+            dirs,
+        }))
+    }
+}
+
+impl VfsLayer for ZipFs {
+    /// Opens a file in the filesystem.
+    fn open(&self, file_path: &Nfc) -> io::Result<Box<dyn VfsFile>> {
+        let entry = self
+            .entries
+            .get(file_path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let mut zip_archive = self.zip_archive.lock().expect("zip_archive");
+        let mut zip_file = zip_archive
+            .by_index(entry.index)
+            .map_err(zip_error_to_io_error)?;
+        let mut data = Vec::with_capacity(usize::try_from(entry.length).unwrap_or_default());
+        zip_file.read_to_end(&mut data)?;
+        Ok(Box::new(ZipFsFile {
+            file_path: file_path.to_owned(),
+            zip_path: self.zip_path.to_owned(),
+            data: Cursor::new(data),
+        }))
+    }
+
+    fn exists(&self, file_path: &Nfc) -> io::Result<bool> {
+        let file_path = Nfc::from(file_path.trim_matches('/'));
+        if file_path.is_empty() {
+            // Root path always exists in the ZIP
+            return Ok(true);
+        }
+        Ok(self.entries.contains_key(&file_path) || self.dirs.contains(&file_path))
+    }
+
+    fn read_dir_recursive(&self, file_path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let path = file_path.trim_matches('/');
+        if !path.is_empty() && !self.dirs.contains(&Nfc::from(path)) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        Ok(self
+            .entries
+            .keys()
+            .filter(|x| x.starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn read_empty_dirs(&self) -> io::Result<BTreeSet<Nfc>> {
+        let mut dirs_with_files = BTreeSet::new();
+        for path in self.entries.keys() {
+            let mut parent = path.as_str();
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                if !dirs_with_files.insert(dir) {
+                    break;
+                }
+                parent = dir;
+            }
+        }
+        Ok(self
+            .dirs
+            .iter()
+            .filter(|x| !dirs_with_files.contains(x.as_str()))
+            .cloned()
+            .collect())
+    }
+
+    /// Returns the metadata without decompressing the file
+    fn metadata(&self, file_path: &Nfc) -> io::Result<VfsMetadata> {
+        if let Some(entry) = self.entries.get(file_path) {
+            return Ok(VfsMetadata::file(self, entry.length, None));
+        }
+        if self.exists(file_path)? {
+            Ok(VfsMetadata::dir(self, None))
+        } else {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn read_dir(&self, path: &Nfc) -> io::Result<BTreeSet<Nfc>> {
+        let path = Nfc::from(path.trim_matches('/'));
+        if !path.is_empty() && !self.dirs.contains(&path) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        // As the entries already use Nfc::caseless_path, we dont need to use Nfc::caseless_path again
+        Ok(self
+            .entries
+            .keys()
+            .chain(self.dirs.iter())
+            .filter_map(|x| x.strip_prefix(prefix.as_str()))
+            .filter_map(|x| x.split('/').next())
+            .map(Nfc::from)
+            .collect())
+    }
+}
+
+impl VfsFile for ZipFsFile {
+    /// Gets the length of the file.
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.data.get_ref().len() as u64)
+    }
+}
+
+impl fmt::Display for ZipFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ZipFs { source: ")?;
+        write!(f, "{}", self.zip_path)?;
+        if !self.root.is_empty() {
+            write!(f, ", root: {:?}", self.root)?;
+        }
+        f.write_str(" }")
+    }
+}
+
+impl fmt::Display for ZipFsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ZipFsFile { ")?;
+        write!(f, "{:?} in {:?}", self.file_path, self.zip_path)?;
+        f.write_str(" }")
+    }
+}
+
+impl io::Read for ZipFsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl io::Seek for ZipFsFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+impl io::Write for ZipFsFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "stracciatella::vfs::zip::ZipFsFile is read-only",
+        ))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "stracciatella::vfs::zip::ZipFsFile is read-only",
+        ))
+    }
+}
+
+/// Converts errors of the zip crate to io errors
+pub fn zip_error_to_io_error(error: ZipError) -> io::Error {
+    match error {
+        ZipError::Io(error) => error,
+        ZipError::FileNotFound => io::ErrorKind::NotFound.into(),
+        error => io::Error::new(io::ErrorKind::InvalidData, error),
+    }
+}
@@ -183,6 +183,214 @@ mod vfs {
         temp.close().expect("close temp dir");
     }
 
+    #[test]
+    fn read_merge_patched_json() {
+        let (temp, dir, _) = create_temp_dir();
+        create_json_file(
+            &dir.join("layer1/items.json"),
+            &json!([
+                { "internalName": "A", "weight": 1, "price": 10 },
+                { "internalName": "B", "weight": 2 },
+            ]),
+        );
+        // JSON Patch is applied before the merge patch of the same layer
+        create_json_file(
+            &dir.join("layer1/items.patch.json"),
+            &json!([ { "op": "add", "path": "/-", "value": { "internalName": "C" } } ]),
+        );
+        create_json_file(
+            &dir.join("layer2/items.merge.json"),
+            &json!({
+                "$mergeBy": "internalName",
+                "$values": [
+                    { "internalName": "A", "price": null },
+                    { "internalName": "C", "weight": 3 },
+                ]
+            }),
+        );
+        create_json_file(
+            &dir.join("layer2/items.patch.json"),
+            &json!([ { "op": "remove", "path": "/1" } ]),
+        );
+        create_json_file(
+            &dir.join("layer3/items.merge.json"),
+            &json!({
+                "$mergeBy": "internalName",
+                "$values": [ { "internalName": "D", "weight": 4 } ]
+            }),
+        );
+        create_json_file(&dir.join("layer3/invalid.json"), &json!({ "items": {} }));
+        create_json_file(
+            &dir.join("layer3/invalid.merge.json"),
+            &json!({ "items": { "$mergeBy": "id", "$values": [] } }),
+        );
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir(&dir.join("layer3")).expect("layer3");
+        vfs.add_dir(&dir.join("layer2")).expect("layer2");
+        vfs.add_dir(&dir.join("layer1")).expect("layer1");
+
+        assert_eq!(
+            vfs.read_patched_json(&Nfc::caseless_path("items.json"))
+                .expect("read patched json"),
+            json!([
+                { "internalName": "A", "weight": 1 },
+                { "internalName": "C", "weight": 3 },
+                { "internalName": "D", "weight": 4 },
+            ])
+        );
+        assert!(
+            vfs.read_patched_json(&Nfc::caseless_path("invalid.json"))
+                .expect_err("error")
+                .to_string()
+                .contains("failed to apply merge patch to json")
+        );
+        let report = vfs.provenance(&Nfc::caseless_path("")).expect("provenance");
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[1].patches, vec![0, 1, 2]);
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn json_cache() {
+        let (temp, dir, _) = create_temp_dir();
+        create_json_file(&dir.join("data/items.json"), &json!({ "a": 1 }));
+        fs::create_dir(dir.join("home")).expect("create home");
+        let mem_fs = MemFs::new("mem");
+
+        let mut vfs = Vfs::new();
+        vfs.add_writable_dir(&dir.join("home")).expect("home");
+        vfs.add_layer(Arc::new(mem_fs.clone()));
+        vfs.add_dir(&dir.join("data")).expect("data");
+        let items = Nfc::caseless_path("items.json");
+        let read = |vfs: &Vfs| vfs.read_patched_json(&items).expect("read patched json");
+
+        assert_eq!(read(&vfs), json!({ "a": 1 }));
+        assert_eq!(read(&vfs), json!({ "a": 1 }));
+        let stats = vfs.json_cache_stats().expect("stats");
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        // changed layers invalidate the cache
+        mem_fs
+            .insert(
+                &Nfc::caseless_path("items.merge.json"),
+                br#"{ "b": 2 }"#.to_vec(),
+            )
+            .expect("insert");
+        assert_eq!(read(&vfs), json!({ "a": 1, "b": 2 }));
+        vfs.create(&Nfc::caseless_path("items.merge.json"))
+            .expect("create")
+            .write_all(br#"{ "c": 3 }"#)
+            .expect("write");
+        assert_eq!(read(&vfs), json!({ "a": 1, "b": 2, "c": 3 }));
+        let stats = vfs.json_cache_stats().expect("stats");
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 3, 1));
+
+        // open files invalidate the cache when they are written, not when they are opened
+        let mut file = vfs
+            .open_writable(&Nfc::caseless_path("items.merge.json"))
+            .expect("open_writable");
+        assert_eq!(read(&vfs), json!({ "a": 1, "b": 2, "c": 3 }));
+        file.write_all(br#"{ "c": 4 }"#).expect("write");
+        assert_eq!(read(&vfs), json!({ "a": 1, "b": 2, "c": 4 }));
+        file.seek(SeekFrom::Start(0)).expect("seek");
+        file.write_all(br#"{ "c": 5 }"#).expect("write");
+        drop(file);
+        assert_eq!(read(&vfs), json!({ "a": 1, "b": 2, "c": 5 }));
+        let stats = vfs.json_cache_stats().expect("stats");
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 5, 1));
+
+        vfs.clear_json_cache().expect("clear");
+        assert_eq!(vfs.json_cache_stats().expect("stats").entries, 0);
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn trace() {
+        let (temp, dir, _) = create_temp_dir();
+        create_file(&dir.join("data/a.txt"));
+        create_file(&dir.join("data/foo/b.txt"));
+        create_json_file(&dir.join("data/items.json"), &json!(["data"]));
+        let mem_fs = MemFs::new("mod");
+        mem_fs
+            .insert(&Nfc::caseless_path("foo/c.txt"), b"c".to_vec())
+            .expect("insert");
+        let patch = json!([ { "op": "add", "path": "/-", "value": "mod" } ]);
+        mem_fs
+            .insert(
+                &Nfc::caseless_path("items.patch.json"),
+                patch.to_string().into_bytes(),
+            )
+            .expect("insert");
+
+        let mut vfs = Vfs::new();
+        vfs.add_layer(Arc::new(mem_fs));
+        vfs.add_dir(&dir.join("data")).expect("data");
+        let mod_layer = vfs.entries[0].to_string();
+        let data_layer = vfs.entries[1].to_string();
+
+        vfs.open(&Nfc::caseless_path("a.txt"))
+            .expect("untraced open");
+        vfs.read_patched_json(&Nfc::caseless_path("items.json"))
+            .expect("untraced read_patched_json");
+        assert!(vfs.traced_accesses().expect("accesses").is_empty());
+
+        let dump_path = dir.join("trace.json");
+        vfs.start_trace(Some(&dump_path)).expect("start trace");
+        vfs.open(&Nfc::caseless_path("a.txt")).expect("open");
+        assert!(vfs.open(&Nfc::caseless_path("missing.txt")).is_err());
+        assert!(
+            vfs.exists(&Nfc::caseless_path("foo/c.txt"))
+                .expect("exists")
+        );
+        vfs.read_dir(&Nfc::caseless_path("foo")).expect("read_dir");
+        // repeated accesses are recorded once
+        vfs.open(&Nfc::caseless_path("a.txt")).expect("open");
+        // cached json documents are recorded with their patches
+        assert_eq!(
+            vfs.read_patched_json(&Nfc::caseless_path("items.json"))
+                .expect("read_patched_json"),
+            json!(["data", "mod"])
+        );
+        let accesses = vfs.traced_accesses().expect("accesses");
+        let summary: Vec<_> = accesses
+            .iter()
+            .map(|x| (x.kind, x.path.as_str(), x.layers.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (VfsAccessKind::Open, "a.txt", vec![data_layer.clone()]),
+                (VfsAccessKind::Open, "missing.txt", vec![]),
+                (VfsAccessKind::Exists, "foo/c.txt", vec![mod_layer.clone()]),
+                (
+                    VfsAccessKind::ReadDir,
+                    "foo",
+                    vec![mod_layer.clone(), data_layer.clone()]
+                ),
+                (VfsAccessKind::Open, "items.json", vec![data_layer.clone()]),
+                (
+                    VfsAccessKind::Open,
+                    "items.patch.json",
+                    vec![mod_layer.clone()]
+                ),
+            ]
+        );
+        assert!(accesses.iter().all(|x| x.timestamp_ms > 0));
+
+        // the trace is written when the VFS is dropped
+        drop(vfs);
+        let dump: Value =
+            serde_json::from_slice(&fs::read(&dump_path).expect("read dump")).expect("json");
+        assert_eq!(dump.as_array().map(|x| x.len()), Some(6));
+        assert_eq!(dump[2]["kind"], json!("exists"));
+        assert_eq!(dump[3]["path"], json!("foo"));
+
+        temp.close().expect("close temp dir");
+    }
+
     #[test]
     fn exists() {
         let (temp, dir, dir_fs) = create_temp_dir();
@@ -445,6 +653,937 @@ mod vfs {
         temp.close().expect("close temp dir");
     }
 
+    #[test]
+    fn writable_layer() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_foo_slf(&dir); // foo.slf
+        fs::create_dir(dir.join("Writable")).expect("create writable dir");
+
+        let mut vfs = Vfs::new();
+        vfs.add_writable_dir(&dir.join("Writable"))
+            .expect("writable dir");
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+        assert!(vfs.is_writable());
+
+        // created files land in the writable layer
+        let mut file = vfs
+            .create(&Nfc::caseless_path("foo/new.txt"))
+            .expect("create");
+        file.write_all(b"new.txt").expect("write");
+        drop(file);
+        assert_eq!(&read_file_data(&vfs, "FOO/NEW.TXT"), b"new.txt");
+        assert_eq!(
+            vfs.read_layers(&Nfc::caseless_path("foo/new.txt"))
+                .expect("read layers"),
+            vec![0]
+        );
+
+        // modified files are copied from lower layers and shadow them
+        let mut file = vfs
+            .open_writable(&Nfc::caseless_path("foo/bar.txt"))
+            .expect("open_writable");
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).expect("read_to_end");
+        assert_eq!(&data, b"foo.slf");
+        file.write_all(b"+modified").expect("write");
+        drop(file);
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"foo.slf+modified");
+        assert_eq!(
+            vfs.read_layers(&Nfc::caseless_path("foo/bar.txt"))
+                .expect("read layers"),
+            vec![0, 1]
+        );
+
+        // renaming and removing only affects the writable layer
+        vfs.rename(
+            &Nfc::caseless_path("foo/new.txt"),
+            &Nfc::caseless_path("baz/renamed.txt"),
+        )
+        .expect("rename");
+        assert!(!vfs.exists(&Nfc::caseless_path("foo/new.txt")).unwrap());
+        assert_eq!(&read_file_data(&vfs, "baz/renamed.txt"), b"new.txt");
+        vfs.remove(&Nfc::caseless_path("foo/bar.txt"))
+            .expect("remove");
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"foo.slf");
+        vfs.create_dir(&Nfc::caseless_path("empty/dir"))
+            .expect("create_dir");
+        assert!(dir.join("Writable/empty/dir").is_dir());
+
+        // read-only layers cannot be changed
+        assert_eq!(
+            vfs.rename(
+                &Nfc::caseless_path("foo/bar/baz.txt"),
+                &Nfc::caseless_path("foo/bar/moved.txt"),
+            )
+            .expect_err("rename in read-only layer")
+            .kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        let mut vfs = Vfs::new();
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+        assert!(!vfs.is_writable());
+        assert_eq!(
+            vfs.create(&Nfc::caseless_path("foo/new.txt"))
+                .expect_err("create without writable layer")
+                .kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn zip() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_zip(
+            &dir.join("foo.zip"),
+            &[
+                "Foo/Bar.txt",
+                "foo/bar/baz.txt",
+                "foo/bar/ὈΔΥΣΣΕΎΣ.baz",
+                "foo/empty/",
+            ],
+        );
+
+        let mut vfs = Vfs::new();
+        vfs.add_zip(
+            dir_fs.open(&"foo.zip".into()).expect("DirFs::open"),
+            &Nfc::caseless_path(""),
+        )
+        .expect("add_zip");
+
+        // case insensitive
+        assert_eq!(&read_file_data(&vfs, "FOO/bar.txt"), b"Foo/Bar.txt");
+        assert_eq!(
+            &read_file_data(&vfs, "foo\\BAR/baz.TXT"),
+            b"foo/bar/baz.txt"
+        );
+        assert_eq!(
+            &read_file_data(&vfs, "foo/bar/ὀδυσσεύς.baz"),
+            "foo/bar/ὈΔΥΣΣΕΎΣ.baz".as_bytes()
+        );
+        assert!(vfs.exists(&Nfc::caseless_path("foo/Empty")).unwrap());
+        assert!(!vfs.exists(&Nfc::caseless_path("fo")).unwrap());
+        assert_vfs_read_dir(&vfs, "", &["foo"]);
+        assert_vfs_read_dir(&vfs, "foo/", &["bar", "bar.txt", "empty"]);
+        assert_vfs_read_dir(&vfs, "foo/bar", &["baz.txt", "ὀδυσσεύς.baz"]);
+
+        // root inside of the archive
+        let mut vfs = Vfs::new();
+        vfs.add_zip(
+            dir_fs.open(&"foo.zip".into()).expect("DirFs::open"),
+            &Nfc::caseless_path("FOO/bar"),
+        )
+        .expect("add_zip");
+        assert_eq!(&read_file_data(&vfs, "baz.txt"), b"foo/bar/baz.txt");
+        assert_vfs_read_dir(&vfs, "/", &["baz.txt", "ὀδυσσεύς.baz"]);
+        assert!(
+            vfs.add_zip(
+                dir_fs.open(&"foo.zip".into()).expect("DirFs::open"),
+                &Nfc::caseless_path("nonexistant"),
+            )
+            .is_err()
+        );
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn mem() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_foo_slf(&dir); // foo.slf
+        let mem_fs = MemFs::new("generated");
+        mem_fs
+            .insert(&Nfc::caseless_path("Foo/Bar.txt"), b"mem".to_vec())
+            .expect("insert");
+        mem_fs
+            .insert(&Nfc::caseless_path("foo/generated/a.txt"), b"a".to_vec())
+            .expect("insert");
+
+        // can be mounted at any priority and modified after mounting
+        let mut vfs = Vfs::new();
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+        vfs.insert_layer(0, Arc::new(mem_fs.clone()))
+            .expect("insert_layer");
+        assert!(vfs.insert_layer(3, Arc::new(mem_fs.clone())).is_err());
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"mem");
+        assert_eq!(&read_file_data(&vfs, "FOO/GENERATED/A.TXT"), b"a");
+        assert_eq!(&read_file_data(&vfs, "foo/bar/baz.txt"), b"foo.slf");
+        mem_fs
+            .insert(&Nfc::caseless_path("foo/generated/b.txt"), b"b".to_vec())
+            .expect("insert");
+        assert_vfs_read_dir(&vfs, "foo/generated", &["a.txt", "b.txt"]);
+        assert_vfs_read_dir(&vfs, "foo", &["bar", "bar.txt", "generated"]);
+        assert!(vfs.exists(&Nfc::caseless_path("foo/generated/")).unwrap());
+        assert!(!vfs.exists(&Nfc::caseless_path("foo/gen")).unwrap());
+
+        let mut vfs = Vfs::new();
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+        vfs.add_layer(Arc::new(mem_fs.clone()));
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"foo.slf");
+
+        // files are writable
+        let mut file = mem_fs
+            .create(&Nfc::caseless_path("new/file.txt"))
+            .expect("create");
+        file.write_all(b"0123456789").expect("write");
+        file.seek(SeekFrom::Start(2)).expect("seek");
+        file.write_all(b"ab").expect("write");
+        assert_eq!(file.len().expect("len"), 10);
+        drop(file);
+        assert_eq!(&read_file_data(&vfs, "new/file.txt"), b"01ab456789");
+        mem_fs
+            .rename(&Nfc::caseless_path("new"), &Nfc::caseless_path("old"))
+            .expect("rename");
+        assert_eq!(&read_file_data(&vfs, "old/file.txt"), b"01ab456789");
+        assert!(mem_fs.remove(&Nfc::caseless_path("old")).is_err());
+        mem_fs
+            .remove(&Nfc::caseless_path("old/file.txt"))
+            .expect("remove");
+        mem_fs.remove(&Nfc::caseless_path("old")).expect("remove");
+        assert!(!vfs.exists(&Nfc::caseless_path("old")).unwrap());
+
+        // files cannot be inside of files
+        for path in ["foo/bar.txt/a.txt", "foo/bar.txt/a/b.txt"] {
+            let path = Nfc::caseless_path(path);
+            assert!(mem_fs.insert(&path, vec![]).is_err());
+            assert!(mem_fs.create(&path).is_err());
+            assert!(mem_fs.create_dir(&path).is_err());
+        }
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"foo.slf");
+        assert!(mem_fs.read_dir(&Nfc::caseless_path("foo/bar.txt")).is_err());
+        assert!(
+            vfs.open(&Nfc::caseless_path("foo/bar.txt"))
+                .expect("open")
+                .write_all(b"read-only")
+                .is_err()
+        );
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn watch() {
+        let (temp, dir, _) = create_temp_dir();
+        create_file(&dir.join("Data/existing.txt"));
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir(&dir.join("Data")).expect("dir");
+        vfs.add_layer(Arc::new(MemFs::new("mem")));
+        assert!(!vfs.exists(&Nfc::caseless_path("new.txt")).unwrap());
+        let changes = vfs.subscribe();
+        vfs.watch().expect("watch");
+
+        // external changes are reported with their logical path
+        create_file_with_content(&dir.join("Data/New.TXT"), b"new");
+        let timeout = Duration::from_secs(10);
+        loop {
+            let path = changes.recv_timeout(timeout).expect("change");
+            if path == Nfc::caseless_path("new.txt") {
+                break;
+            }
+        }
+        assert!(vfs.poll_changes().contains(&Nfc::caseless_path("new.txt")));
+
+        // stale path lookups are discarded
+        assert_eq!(&read_file_data(&vfs, "new.txt"), b"new");
+        assert_vfs_read_dir(&vfs, "", &["existing.txt", "new.txt"]);
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn metadata() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_file(&dir.join("Foo/Bar.txt"));
+        create_foo_slf(&dir);
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir(&dir).expect("dir");
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+
+        // files in a directory have the size and modification time of the filesystem
+        let metadata = vfs
+            .metadata(&Nfc::caseless_path("foo/bar.txt"))
+            .expect("metadata");
+        assert_eq!(metadata.len, 7);
+        assert!(!metadata.is_dir);
+        assert!(metadata.modified.is_some());
+        assert_eq!(metadata.layer_index, Some(0));
+        assert!(metadata.layer.starts_with("DirFs"));
+
+        // files in a SLF keep the file time of their entry
+        let metadata = vfs
+            .metadata(&Nfc::caseless_path("foo/bar/baz.txt"))
+            .expect("metadata");
+        assert_eq!(metadata.len, 7);
+        assert!(!metadata.is_dir);
+        assert_eq!(
+            metadata.modified,
+            UNIX_EPOCH.checked_sub(Duration::from_secs(11_644_473_600))
+        );
+        assert_eq!(metadata.layer_index, Some(1));
+
+        // directories
+        let metadata = vfs
+            .metadata(&Nfc::caseless_path("foo/bar"))
+            .expect("metadata");
+        assert!(metadata.is_dir);
+        assert_eq!(metadata.len, 0);
+        assert_eq!(metadata.layer_index, Some(1));
+        assert!(vfs.metadata(&Nfc::caseless_path("foo")).unwrap().is_dir);
+
+        assert_eq!(
+            vfs.metadata(&Nfc::caseless_path("foo/missing.txt"))
+                .expect_err("missing")
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn provenance() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_file(&dir.join("mod/foo/bar.txt"));
+        create_file(&dir.join("mod/only-mod.txt"));
+        create_json_file(&dir.join("mod/items.json"), &json!([]));
+        create_json_file(&dir.join("mod/weapons.patch.json"), &json!([]));
+        create_json_file(&dir.join("mod/orphan.patch.json"), &json!([]));
+        create_json_file(&dir.join("data/items.patch.json"), &json!([]));
+        create_json_file(&dir.join("data/weapons.json"), &json!([]));
+        create_foo_slf(&dir);
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir(&dir.join("mod")).expect("mod");
+        vfs.add_dir(&dir.join("data")).expect("data");
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+
+        let report = vfs.provenance(&Nfc::caseless_path("")).expect("provenance");
+        let get = |path: &str| {
+            report
+                .iter()
+                .find(|x| x.path == Nfc::caseless_path(path))
+                .expect("path in report")
+        };
+        assert_eq!(report.len(), 7);
+        assert_eq!(get("foo/bar.txt").layers, vec![0, 2]);
+        assert_eq!(get("foo/bar.txt").shadowed(), &[2]);
+        assert!(get("foo/bar.txt").is_shadowing());
+        assert_eq!(get("only-mod.txt").layers, vec![0]);
+        assert!(!get("only-mod.txt").is_shadowing());
+        assert_eq!(get("foo/bar/baz.txt").provider(), 2);
+        // patches are reported with the json file they apply to
+        assert_eq!(get("items.json").patches, Vec::<usize>::new());
+        assert_eq!(get("items.json").ignored_patches, vec![1]);
+        assert!(get("items.json").is_shadowing());
+        assert_eq!(get("weapons.json").layers, vec![1]);
+        assert_eq!(get("weapons.json").patches, vec![0]);
+        assert!(!get("weapons.json").is_shadowing());
+        assert_eq!(get("orphan.patch.json").layers, vec![0]);
+
+        let report = vfs
+            .provenance(&Nfc::caseless_path("foo/bar"))
+            .expect("provenance");
+        assert_eq!(report.len(), 2);
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn slf_sources() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_foo_slf(&dir);
+        let mem_fs = MemFs::new("mem");
+        mem_fs
+            .insert(
+                &Nfc::caseless_path("foo.slf"),
+                fs::read(dir.join("foo.slf")).expect("read"),
+            )
+            .expect("insert");
+
+        let file_slf = SlfFs::new(dir_fs.open(&"foo.slf".into()).expect("open")).expect("SlfFs");
+        assert!(matches!(*file_slf.slf_file, SlfSource::File(_)));
+        let generic_slf = SlfFs::new(mem_fs.open(&"foo.slf".into()).expect("open")).expect("SlfFs");
+        assert!(matches!(*generic_slf.slf_file, SlfSource::VfsFile(_)));
+
+        // concurrent reads of both sources see the same data
+        let threads: Vec<_> = [file_slf, generic_slf]
+            .iter()
+            .flat_map(|slf| [slf.clone(), slf.clone()])
+            .map(|slf| {
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        for path in ["foo/bar.txt", "foo/bar/baz.txt"] {
+                            let mut file = slf.open(&Nfc::caseless_path(path)).expect("open");
+                            file.seek(SeekFrom::Start(4)).expect("seek");
+                            let mut data = Vec::new();
+                            file.read_to_end(&mut data).expect("read_to_end");
+                            assert_eq!(&data, b"slf");
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("join");
+        }
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn index() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_file(&dir.join("Writable/Foo/Bar.txt"));
+        create_foo_slf(&dir);
+        let mem_fs = MemFs::new("mem");
+
+        let mut vfs = Vfs::new();
+        vfs.add_writable_dir(&dir.join("Writable"))
+            .expect("writable dir");
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+        vfs.build_index().expect("build_index");
+
+        assert_eq!(
+            vfs.read_layers(&Nfc::caseless_path("foo/bar.txt"))
+                .expect("read_layers"),
+            vec![0, 1]
+        );
+        assert_eq!(
+            vfs.read_layers(&Nfc::caseless_path("foo/bar"))
+                .expect("read_layers"),
+            vec![1]
+        );
+        assert_eq!(
+            vfs.read_layers(&Nfc::caseless_path(""))
+                .expect("read_layers"),
+            vec![0, 1]
+        );
+        assert!(vfs.exists(&Nfc::caseless_path("foo/bar/baz.txt")).unwrap());
+        assert!(!vfs.exists(&Nfc::caseless_path("foo/ba")).unwrap());
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"Bar.txt");
+        assert_eq!(&read_file_data(&vfs, "foo/bar/baz.txt"), b"foo.slf");
+
+        // writes through the VFS update the index
+        vfs.create(&Nfc::caseless_path("new/file.txt"))
+            .expect("create");
+        assert!(vfs.exists(&Nfc::caseless_path("new/file.txt")).unwrap());
+        vfs.remove(&Nfc::caseless_path("foo/bar.txt"))
+            .expect("remove");
+        assert_eq!(
+            vfs.read_layers(&Nfc::caseless_path("foo/bar.txt"))
+                .expect("read_layers"),
+            vec![1]
+        );
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"foo.slf");
+
+        // added layers and changes of mounted layers update the index
+        vfs.add_layer(Arc::new(mem_fs.clone()));
+        assert!(!vfs.exists(&Nfc::caseless_path("mem.txt")).unwrap());
+        mem_fs
+            .insert(&Nfc::caseless_path("mem.txt"), b"mem".to_vec())
+            .expect("insert");
+        assert_eq!(
+            vfs.read_layers(&Nfc::caseless_path("mem.txt"))
+                .expect("read_layers"),
+            vec![2]
+        );
+        assert_eq!(&read_file_data(&vfs, "mem.txt"), b"mem");
+
+        vfs.drop_index().expect("drop_index");
+        assert!(vfs.exists(&Nfc::caseless_path("mem.txt")).unwrap());
+
+        // lookups ask the layers when a changed layer cannot be indexed
+        vfs.build_index().expect("build_index");
+        vfs.create(&Nfc::caseless_path("new/other.txt"))
+            .expect("create");
+        std::fs::remove_dir_all(dir.join("Writable")).expect("remove dir");
+        create_file(&dir.join("Writable"));
+        assert!(!vfs.exists(&Nfc::caseless_path("new/file.txt")).unwrap());
+        assert!(vfs.exists(&Nfc::caseless_path("foo/bar/baz.txt")).unwrap());
+        assert!(vfs.exists(&Nfc::caseless_path("mem.txt")).unwrap());
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn index_empty_dirs() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        fs::create_dir_all(dir.join("Data/Empty/Sub")).expect("create dir");
+        create_file(&dir.join("Data/Foo/Bar.txt"));
+        let mem_fs = MemFs::new("mem");
+        mem_fs
+            .create_dir(&Nfc::caseless_path("mem/dir"))
+            .expect("create_dir");
+        create_zip(&dir.join("mod.zip"), &["Zip/Dir/", "Zip/Foo.txt"]);
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir(&dir.join("Data")).expect("dir");
+        vfs.add_layer(Arc::new(mem_fs));
+        vfs.add_zip(
+            dir_fs.open(&"mod.zip".into()).expect("DirFs::open"),
+            &Nfc::caseless_path(""),
+        )
+        .expect("add_zip");
+        vfs.build_index().expect("build_index");
+
+        for path in ["empty", "empty/sub", "foo", "mem", "mem/dir", "zip/dir"] {
+            assert!(vfs.exists(&Nfc::caseless_path(path)).unwrap(), "{}", path);
+            let metadata = vfs.metadata(&Nfc::caseless_path(path)).expect("metadata");
+            assert!(metadata.is_dir, "{}", path);
+        }
+        assert_vfs_read_dir(&vfs, "empty", &["sub"]);
+        assert!(!vfs.exists(&Nfc::caseless_path("empty/missing")).unwrap());
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn walk_and_glob() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_file(&dir.join("Mod/Foo/Bar.txt"));
+        create_file(&dir.join("Mod/Maps/A1.dat"));
+        create_file(&dir.join("Mod/Maps/Deep/B2.dat"));
+        create_file(&dir.join("Mod/Maps/readme.txt"));
+        create_foo_slf(&dir);
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir(&dir.join("Mod")).expect("mod");
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+
+        let entries = vfs.walk(&Nfc::caseless_path("foo")).expect("walk");
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|x| (x.path.as_str(), x.layer_index))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("foo/bar.txt", 0),
+                ("foo/bar/baz.txt", 1),
+                ("foo/bar/ὀδυσσεύσ.baz", 1)
+            ]
+        );
+        assert!(vfs.walk(&Nfc::caseless_path("missing")).is_err());
+
+        let glob = |pattern: &str| -> Vec<String> {
+            vfs.glob(pattern)
+                .expect("glob")
+                .into_iter()
+                .map(|x| x.path.to_string())
+                .collect()
+        };
+        assert_eq!(glob("MAPS/*.DAT"), vec!["maps/a1.dat"]);
+        assert_eq!(
+            glob("maps/**/*.dat"),
+            vec!["maps/a1.dat", "maps/deep/b2.dat"]
+        );
+        assert_eq!(
+            glob("maps/**"),
+            vec!["maps/a1.dat", "maps/deep/b2.dat", "maps/readme.txt"]
+        );
+        assert_eq!(glob("*/bar.txt"), vec!["foo/bar.txt"]);
+        assert_eq!(glob("foo/bar/???.txt"), vec!["foo/bar/baz.txt"]);
+        assert_eq!(glob("foo/bar.txt"), vec!["foo/bar.txt"]);
+        assert!(glob("missing/*.dat").is_empty());
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn nested_slf_files() {
+        let (temp, dir, _) = create_temp_dir();
+        fs::create_dir_all(dir.join("Data/Sub")).expect("create dir");
+        // outer.slf contains the files of inner.slf
+        create_slf(&dir, "inner.slf", "inner\\", &["baz.txt"]);
+        let inner = fs::read(dir.join("inner.slf")).expect("read inner.slf");
+        create_slf_with_data(
+            &dir.join("Data/Sub"),
+            "outer.slf",
+            "outer\\",
+            &[("foo.txt", b"outer.slf"), ("nested\\inner.slf", &inner)],
+        );
+        create_foo_slf(&dir.join("Data"));
+
+        let mut vfs = Vfs::new();
+        let layer = vfs.add_dir(&dir.join("Data")).expect("dir");
+        vfs.add_slf_files_from(layer.clone(), false)
+            .expect("add_slf_files_from");
+        assert_eq!(vfs.entries.len(), 2);
+        assert!(
+            !vfs.exists(&Nfc::caseless_path("sub/outer/foo.txt"))
+                .unwrap()
+        );
+
+        let mut vfs = Vfs::new();
+        vfs.entries.push(layer.clone());
+        vfs.add_nested_slf_files_from(layer, true)
+            .expect("add_nested_slf_files_from");
+        assert_eq!(vfs.entries.len(), 4);
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"foo.slf");
+        assert_eq!(&read_file_data(&vfs, "sub/outer/foo.txt"), b"outer.slf");
+        assert_eq!(
+            &read_file_data(&vfs, "sub/outer/nested/inner/baz.txt"),
+            b"inner.slf"
+        );
+        assert_vfs_read_dir(&vfs, "sub", &["outer", "outer.slf"]);
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn nested_slf_files_without_ok_entries() {
+        let (temp, dir, _) = create_temp_dir();
+        fs::create_dir_all(dir.join("Data")).expect("create dir");
+        create_foo_slf(&dir.join("Data"));
+        // deleted.slf only has a Deleted entry
+        let header = SlfHeader {
+            library_name: "deleted.slf".to_owned(),
+            num_entries: 1,
+            ok_entries: 0,
+            sort: 0xFFFF,
+            version: 0x200,
+            ..SlfHeader::default()
+        };
+        let mut file = fs::File::create(dir.join("Data/deleted.slf")).expect("create slf");
+        header.to_output(&mut file).expect("write header");
+        let entry = SlfEntry {
+            file_path: "gone.txt".to_owned(),
+            state: SlfEntryState::Deleted,
+            ..SlfEntry::default()
+        };
+        header
+            .entries_to_output(&mut file, &[entry])
+            .expect("write entries");
+        file.sync_all().expect("sync_all");
+
+        let mut vfs = Vfs::new();
+        let layer = vfs.add_dir(&dir.join("Data")).expect("dir");
+        vfs.add_nested_slf_files_from(layer, true)
+            .expect("add_nested_slf_files_from");
+        assert_eq!(vfs.entries.len(), 3);
+        assert_eq!(&read_file_data(&vfs, "foo/bar.txt"), b"foo.slf");
+        assert!(!vfs.exists(&Nfc::caseless_path("gone.txt")).unwrap());
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn export() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_file(&dir.join("mod/foo/bar.txt"));
+        create_json_file(
+            &dir.join("mod/items.patch.json"),
+            &json!([ { "op": "add", "path": "/-", "value": "mod" } ]),
+        );
+        create_json_file(&dir.join("mod/orphan.patch.json"), &json!([]));
+        create_json_file(&dir.join("data/items.json"), &json!(["data"]));
+        create_foo_slf(&dir);
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir(&dir.join("mod")).expect("mod");
+        vfs.add_dir(&dir.join("data")).expect("data");
+        add_slf(&mut vfs, &dir_fs, "foo.slf");
+        let root = Nfc::caseless_path("");
+
+        // without patches every file is copied from the highest priority layer
+        let count = vfs
+            .export_to_dir(&root, &dir.join("plain"), false)
+            .expect("export_to_dir");
+        assert_eq!(count, 6);
+        assert_eq!(
+            fs::read(dir.join("plain/foo/bar.txt")).expect("read"),
+            b"bar.txt"
+        );
+        assert_eq!(
+            fs::read(dir.join("plain/foo/bar/baz.txt")).expect("read"),
+            b"foo.slf"
+        );
+        assert!(dir.join("plain/items.patch.json").exists());
+
+        // applied patches are merged into the json file
+        let count = vfs
+            .export_to_dir(&root, &dir.join("patched"), true)
+            .expect("export_to_dir");
+        assert_eq!(count, 5);
+        let items: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("patched/items.json")).expect("read"))
+                .expect("json");
+        assert_eq!(items, json!(["data", "mod"]));
+        assert!(!dir.join("patched/items.patch.json").exists());
+        assert!(dir.join("patched/orphan.patch.json").exists());
+
+        // mounting the exported SLF provides the same files
+        let count = vfs
+            .export_to_slf(&Nfc::caseless_path("foo"), &dir.join("export.slf"), true)
+            .expect("export_to_slf");
+        assert_eq!(count, 3);
+        let mut exported = Vfs::new();
+        // new DirFs, the old one cached the directory contents
+        let dir_fs: Arc<dyn VfsLayer> = DirFs::new(&dir).expect("DirFs");
+        add_slf(&mut exported, &dir_fs, "export.slf");
+        assert_eq!(&read_file_data(&exported, "foo/bar.txt"), b"bar.txt");
+        assert_eq!(&read_file_data(&exported, "foo/bar/baz.txt"), b"foo.slf");
+        assert_vfs_read_dir(&exported, "foo/bar", &["baz.txt", "ὀδυσσεύσ.baz"]);
+        let metadata = exported
+            .metadata(&Nfc::caseless_path("foo/bar.txt"))
+            .expect("metadata");
+        assert!(metadata.modified.is_some());
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn whiteouts() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_file(&dir.join("mod/.wh.foo"));
+        create_file(&dir.join("mod/foo/new.txt"));
+        create_file(&dir.join("mod/.wh.items.json"));
+        create_file(&dir.join("mod/maps/.wh.a1.dat"));
+        create_file(&dir.join("data/items.json"));
+        create_file(&dir.join("data/maps/a1.dat"));
+        create_file(&dir.join("data/maps/b2.dat"));
+        create_file(&dir.join("data/.wh.mod-only.txt"));
+        create_file(&dir.join("data/mod-only.txt"));
+        create_foo_slf(&dir);
+
+        for indexed in [false, true] {
+            let mut vfs = Vfs::new();
+            vfs.add_dir(&dir.join("mod")).expect("mod");
+            vfs.add_dir(&dir.join("data")).expect("data");
+            add_slf(&mut vfs, &dir_fs, "foo.slf");
+            if indexed {
+                vfs.build_index().expect("build_index");
+            }
+            let exists = |path: &str| vfs.exists(&Nfc::caseless_path(path)).expect("exists");
+
+            // whiteouts of files
+            assert!(!exists("items.json"));
+            assert!(vfs.open(&Nfc::caseless_path("items.json")).is_err());
+            assert!(!exists("maps/a1.dat"));
+            assert!(exists("maps/b2.dat"));
+            assert_vfs_read_dir(&vfs, "maps", &["b2.dat"]);
+            // whiteouts of directories keep the files of their own layer
+            assert!(!exists("foo/bar.txt"));
+            assert!(!exists("foo/bar/baz.txt"));
+            assert!(exists("foo/new.txt"));
+            assert_vfs_read_dir(&vfs, "foo", &["new.txt"]);
+            // whiteouts only hide lower priority layers and are not visible
+            assert!(exists("mod-only.txt"));
+            assert!(!exists(".wh.foo"));
+            assert_vfs_read_dir(&vfs, "", &["foo", "maps", "mod-only.txt"]);
+            let walk: Vec<_> = vfs
+                .walk(&Nfc::caseless_path(""))
+                .expect("walk")
+                .into_iter()
+                .map(|x| x.path.to_string())
+                .collect();
+            assert_eq!(walk, vec!["foo/new.txt", "maps/b2.dat", "mod-only.txt"]);
+        }
+
+        // whiteouts are looked up per path, so new whiteouts are found and layers are not listed
+        fs::create_dir(dir.join("broken")).expect("create dir");
+        let mut vfs = Vfs::new();
+        vfs.add_writable_dir(&dir.join("mod")).expect("mod");
+        vfs.add_dir(&dir.join("data")).expect("data");
+        vfs.add_dir(&dir.join("broken")).expect("broken");
+        std::fs::remove_dir(dir.join("broken")).expect("remove dir");
+        create_file(&dir.join("broken"));
+        assert!(vfs.exists(&Nfc::caseless_path("maps/b2.dat")).unwrap());
+        vfs.create(&Nfc::caseless_path("maps/.wh.b2.dat"))
+            .expect("create");
+        assert!(!vfs.exists(&Nfc::caseless_path("maps/b2.dat")).unwrap());
+        assert!(vfs.open(&Nfc::caseless_path("mod-only.txt")).is_ok());
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn prefix() {
+        let (temp, dir, dir_fs) = create_temp_dir();
+        create_file(&dir.join("tiles/Grass.sti"));
+        create_file(&dir.join("tiles/sub/Rock.sti"));
+        create_foo_slf(&dir);
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir_at(&dir.join("tiles"), &Nfc::caseless_path("Tilesets/50"))
+            .expect("add_dir_at");
+        let slf_fs = SlfFs::new(dir_fs.open(&"foo.slf".into()).expect("open")).expect("SlfFs");
+        vfs.add_layer_at(
+            slf_fs,
+            &Nfc::caseless_path("foo/bar"),
+            &Nfc::caseless_path("moved"),
+        )
+        .expect("add_layer_at");
+
+        assert_eq!(&read_file_data(&vfs, "tilesets/50/grass.sti"), b"Grass.sti");
+        assert_eq!(
+            &read_file_data(&vfs, "tilesets/50/sub/rock.sti"),
+            b"Rock.sti"
+        );
+        assert_eq!(&read_file_data(&vfs, "moved/baz.txt"), b"foo.slf");
+        assert!(!vfs.exists(&Nfc::caseless_path("grass.sti")).unwrap());
+        assert!(!vfs.exists(&Nfc::caseless_path("foo/bar.txt")).unwrap());
+        assert!(!vfs.exists(&Nfc::caseless_path("tilesets/5")).unwrap());
+        // parent directories of the prefix are directories
+        assert_vfs_read_dir(&vfs, "", &["moved", "tilesets"]);
+        assert_vfs_read_dir(&vfs, "tilesets", &["50"]);
+        assert_vfs_read_dir(&vfs, "tilesets/50", &["grass.sti", "sub"]);
+        assert!(
+            vfs.metadata(&Nfc::caseless_path("tilesets"))
+                .expect("metadata")
+                .is_dir
+        );
+        assert_eq!(
+            vfs.read_dir_recursive(&Nfc::caseless_path("tilesets"))
+                .expect("read_dir_recursive"),
+            BTreeSet::from([
+                Nfc::caseless_path("tilesets/50/grass.sti"),
+                Nfc::caseless_path("tilesets/50/sub/rock.sti"),
+            ])
+        );
+        let walk: Vec<_> = vfs
+            .walk(&Nfc::caseless_path(""))
+            .expect("walk")
+            .into_iter()
+            .map(|x| x.path.to_string())
+            .collect();
+        assert_eq!(
+            walk,
+            vec![
+                "moved/baz.txt",
+                "moved/ὀδυσσεύσ.baz",
+                "tilesets/50/grass.sti",
+                "tilesets/50/sub/rock.sti"
+            ]
+        );
+        // the root has to exist
+        let slf_fs = SlfFs::new(dir_fs.open(&"foo.slf".into()).expect("open")).expect("SlfFs");
+        assert!(
+            vfs.add_layer_at(
+                slf_fs,
+                &Nfc::caseless_path("missing"),
+                &Nfc::caseless_path("")
+            )
+            .is_err()
+        );
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn compressed_files() {
+        let (temp, dir, _) = create_temp_dir();
+        let data: Vec<u8> = (0..100_000u32).map(|x| (x % 251) as u8).collect();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).expect("compress");
+        fs::create_dir_all(dir.join("mod/maps")).expect("create dir");
+        fs::write(
+            dir.join("mod/maps/a1.dat.gz"),
+            encoder.finish().expect("compress"),
+        )
+        .expect("write");
+        // two zstd frames with "zstd " and "data"
+        fs::write(
+            dir.join("mod/frames.txt.zst"),
+            [
+                0x28, 0xb5, 0x2f, 0xfd, 0x24, 0x05, 0x29, 0x00, 0x00, 0x7a, 0x73, 0x74, 0x64, 0x20,
+                0x58, 0x87, 0xdd, 0xb9, 0x28, 0xb5, 0x2f, 0xfd, 0x24, 0x04, 0x21, 0x00, 0x00, 0x64,
+                0x61, 0x74, 0x61, 0xa3, 0x1d, 0x2d, 0x55,
+            ],
+        )
+        .expect("write");
+        create_file(&dir.join("mod/plain.txt"));
+        create_file(&dir.join("mod/plain.txt.gz"));
+
+        let mut vfs = Vfs::new();
+        vfs.add_compressed_dir(&dir.join("mod"))
+            .expect("add_compressed_dir");
+
+        assert_eq!(read_file_data(&vfs, "maps/a1.dat"), data);
+        assert_eq!(&read_file_data(&vfs, "frames.txt"), b"zstd data");
+        // plain files are preferred
+        assert_eq!(&read_file_data(&vfs, "plain.txt"), b"plain.txt");
+        assert_eq!(&read_file_data(&vfs, "plain.txt.gz"), b"plain.txt.gz");
+        // compressed files that are not used for the uncompressed path keep their name
+        assert_vfs_read_dir(
+            &vfs,
+            "",
+            &["frames.txt", "maps", "plain.txt", "plain.txt.gz"],
+        );
+        assert_vfs_read_dir(&vfs, "maps", &["a1.dat"]);
+        for (path, len) in [("maps/a1.dat", data.len()), ("frames.txt", 9)] {
+            let metadata = vfs.metadata(&Nfc::caseless_path(path)).expect("metadata");
+            assert_eq!(metadata.len, len as u64);
+        }
+
+        // seeking in both directions
+        let mut file = vfs.open(&Nfc::caseless_path("maps/a1.dat")).expect("open");
+        assert_eq!(file.len().expect("len"), data.len() as u64);
+        let mut buf = [0u8; 4];
+        for position in [90_000u64, 10, 99_996, 251] {
+            file.seek(SeekFrom::Start(position)).expect("seek");
+            file.read_exact(&mut buf).expect("read");
+            assert_eq!(&buf, &data[position as usize..position as usize + 4]);
+        }
+        assert_eq!(file.seek(SeekFrom::End(-4)).expect("seek"), 99_996);
+        assert_eq!(file.seek(SeekFrom::Current(-96)).expect("seek"), 99_900);
+        assert!(file.seek(SeekFrom::Current(-100_000)).is_err());
+        assert!(file.write_all(b"read-only").is_err());
+
+        temp.close().expect("close temp dir");
+    }
+
+    #[test]
+    fn case_collisions() {
+        let (temp, dir, _) = create_temp_dir();
+        create_file(&dir.join("data/Maps/A9.dat"));
+        create_file(&dir.join("data/maps/a9.DAT"));
+        create_file(&dir.join("data/maps/b9.dat"));
+        create_file(&dir.join("data/readme.txt"));
+        if fs::read_dir(dir.join("data")).expect("read_dir").count() < 3 {
+            // case-insensitive filesystem
+            return;
+        }
+
+        let mut vfs = Vfs::new();
+        vfs.add_dir_at(&dir.join("data"), &Nfc::caseless_path("mod"))
+            .expect("add_dir_at");
+        vfs.add_dir(&dir.join("data/maps")).expect("add_dir");
+        let collisions = vfs.case_collisions_by_layer().expect("case collisions");
+        let summary: Vec<Vec<_>> = collisions
+            .iter()
+            .map(|x| {
+                x.iter()
+                    .map(|x| (x.path.as_str(), x.candidates.clone()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                vec![
+                    (
+                        "mod/maps",
+                        vec![PathBuf::from("Maps"), PathBuf::from("maps")]
+                    ),
+                    (
+                        "mod/maps/a9.dat",
+                        vec![
+                            PathBuf::from("Maps").join("A9.dat"),
+                            PathBuf::from("maps").join("a9.DAT")
+                        ]
+                    ),
+                ],
+                vec![],
+            ]
+        );
+
+        temp.close().expect("close temp dir");
+    }
+
     // end of vfs tests
     //------------------
 
@@ -453,13 +1592,19 @@ mod vfs {
     use std::iter::FromIterator;
     use std::path::{Path, PathBuf};
     use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
 
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
     use serde_json::{Value, json};
-    use stracciatella::file_formats::slf::{SlfEntry, SlfEntryState, SlfHeader};
+    use stracciatella::file_formats::slf::{SlfEncoding, SlfEntry, SlfEntryState, SlfHeader};
     use stracciatella::fs;
     use stracciatella::fs::{OpenOptions, TempDir};
     use stracciatella::unicode::Nfc;
     use stracciatella::vfs::dir::DirFs;
+    use stracciatella::vfs::mem::MemFs;
+    use stracciatella::vfs::slf::{SlfFs, SlfSource};
+    use stracciatella::vfs::trace::VfsAccessKind;
     use stracciatella::vfs::{Vfs, VfsLayer};
 
     fn read_file_data(vfs: &Vfs, path: &str) -> Vec<u8> {
@@ -493,14 +1638,28 @@ mod vfs {
 
     /// The inner file data is the name.
     fn create_slf(dir: &Path, name: &str, library_path: &str, entry_paths: &[&str]) -> PathBuf {
+        let entries: Vec<_> = entry_paths
+            .iter()
+            .map(|&entry_path| (entry_path, name.as_bytes()))
+            .collect();
+        create_slf_with_data(dir, name, library_path, &entries)
+    }
+
+    fn create_slf_with_data(
+        dir: &Path,
+        name: &str,
+        library_path: &str,
+        entries: &[(&str, &[u8])],
+    ) -> PathBuf {
         let header = SlfHeader {
             library_name: name.to_owned(),
             library_path: library_path.to_owned(),
-            num_entries: entry_paths.len() as i32,
-            ok_entries: entry_paths.len() as i32,
+            num_entries: entries.len() as i32,
+            ok_entries: entries.len() as i32,
             sort: 0xFFFF,
             version: 0x200,
             contains_subdirectories: if library_path.is_empty() { 0 } else { 1 },
+            encoding: SlfEncoding::Utf8,
         };
         let path = dir.join(name);
         let mut file = OpenOptions::new()
@@ -509,11 +1668,10 @@ mod vfs {
             .open(&path)
             .expect("open new file for writing");
         header.to_output(&mut file).expect("write header");
-        let mut entries = entry_paths
+        let mut entries = entries
             .iter()
-            .map(|&entry_path| {
+            .map(|&(entry_path, data)| {
                 let offset = file.stream_position().expect("seek to entry data");
-                let data = name.as_bytes();
                 file.write_all(data).expect("write entry data");
                 SlfEntry {
                     file_path: entry_path.to_owned(),
@@ -532,6 +1690,24 @@ mod vfs {
         path
     }
 
+    /// The inner file data is the path, entries ending in '/' are directories.
+    fn create_zip(path: &Path, entry_paths: &[&str]) {
+        let file = fs::File::create(path).expect("create zip");
+        let mut zip = zip::ZipWriter::new(file);
+        for entry_path in entry_paths {
+            if entry_path.ends_with('/') {
+                zip.add_directory(*entry_path, zip::write::SimpleFileOptions::default())
+                    .expect("add zip directory");
+            } else {
+                zip.start_file(*entry_path, zip::write::SimpleFileOptions::default())
+                    .expect("start zip file");
+                zip.write_all(entry_path.as_bytes())
+                    .expect("write zip file");
+            }
+        }
+        zip.finish().expect("finish zip");
+    }
+
     /// The file data is the same as the filename.
     fn create_file(path: &Path) {
         let name = path
@@ -0,0 +1,536 @@
+//! This file contains the code for the slf executable.
+//!
+//! It inspects, extracts, creates and compares SLF archives.
+//!
+//!
+//! # List the entries of an archive:
+//!
+//! Example:
+//! ```
+//! slf list /path/to/Data/Maps.slf
+//! ```
+//!
+//!
+//! # Extract files of an archive:
+//!
+//! Example:
+//! ```
+//! slf extract --glob "*.dat" /path/to/Data/Maps.slf /path/to/output
+//! ```
+//!
+//!
+//! # Create an archive from a directory:
+//!
+//! Example:
+//! ```
+//! slf create --library-path maps /path/to/MyMaps.slf /path/to/maps
+//! ```
+//!
+//!
+//! # Check archives for corruption:
+//!
+//! Example:
+//! ```
+//! slf verify /path/to/Data/*.slf
+//! ```
+//!
+//!
+//! # Compare two archives:
+//!
+//! Example:
+//! ```
+//! slf diff /path/to/old/Maps.slf /path/to/new/Maps.slf
+//! ```
+//!
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{App, Arg, ArgMatches, SubCommand, crate_version};
+use serde_json::json;
+
+use stracciatella::file_formats::slf::{
+    SlfBuilder, SlfEntry, SlfEntryState, SlfHeader, SlfValidator,
+};
+use stracciatella::unicode::Nfc;
+use stracciatella::vfs::dir::DirFsFile;
+use stracciatella::vfs::slf::SlfFs;
+use stracciatella::vfs::{Vfs, VfsLayer};
+
+/// Entry point of the slf executable.
+fn main() {
+    let cmd_list = SubCommand::with_name("list")
+        .about("Lists the entries of an archive with their state, size and time.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("json")
+                .help("Outputs json instead of text")
+                .long("json"),
+        )
+        .arg(
+            Arg::with_name("archive")
+                .help("SLF archive")
+                .value_name("ARCHIVE")
+                .required(true),
+        );
+
+    let cmd_extract = SubCommand::with_name("extract")
+        .about("Extracts the files of an archive and keeps their modification times.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("glob")
+                .help("Only extracts the files that match a case-insensitive glob pattern, e.g. \"tilesets/**/*.sti\"")
+                .long("glob")
+                .value_name("PATTERN")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("archive")
+                .help("SLF archive")
+                .value_name("ARCHIVE")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("target")
+                .help("Target directory")
+                .value_name("TARGET")
+                .required(true),
+        );
+
+    let cmd_create = SubCommand::with_name("create")
+        .about("Creates an archive from the files in a directory and its subdirectories.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("library-name")
+                .help("Library name, defaults to the file name of the archive in uppercase")
+                .long("library-name")
+                .value_name("NAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("library-path")
+                .help("Library path relative to the Data dir, defaults to the Data dir itself")
+                .long("library-path")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("archive")
+                .help("SLF archive to create, an existing file is replaced")
+                .value_name("ARCHIVE")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("dir")
+                .help("Directory with the files")
+                .value_name("DIR")
+                .required(true),
+        );
+
+    let cmd_verify = SubCommand::with_name("verify")
+        .about("Checks archives for corruption, exits with 1 if there are problems.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("ignore-order")
+                .help("Does not report entries that are not sorted")
+                .long("ignore-order"),
+        )
+        .arg(
+            Arg::with_name("archives")
+                .help("SLF archives")
+                .value_name("ARCHIVE")
+                .required(true)
+                .multiple(true),
+        );
+
+    let cmd_diff = SubCommand::with_name("diff")
+        .about("Lists the files that were removed, added or modified, exits with 1 if there are differences.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("old")
+                .help("Old SLF archive")
+                .value_name("OLD")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("new")
+                .help("New SLF archive")
+                .value_name("NEW")
+                .required(true),
+        );
+
+    let matches = App::new("slf")
+        .about("Tool that inspects and creates SLF archives.")
+        .version(crate_version!())
+        .subcommand(cmd_list)
+        .subcommand(cmd_extract)
+        .subcommand(cmd_create)
+        .subcommand(cmd_verify)
+        .subcommand(cmd_diff)
+        .get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("list") {
+        subcommand_list(matches);
+    } else if let Some(matches) = matches.subcommand_matches("extract") {
+        subcommand_extract(matches);
+    } else if let Some(matches) = matches.subcommand_matches("create") {
+        subcommand_create(matches);
+    } else if let Some(matches) = matches.subcommand_matches("verify") {
+        subcommand_verify(matches);
+    } else if let Some(matches) = matches.subcommand_matches("diff") {
+        subcommand_diff(matches);
+    }
+}
+
+/// Lists the entries of an archive.
+fn subcommand_list(matches: &ArgMatches) {
+    let archive = PathBuf::from(matches.value_of_os("archive").expect("archive"));
+    let (header, entries) = read_archive(&archive);
+
+    if matches.is_present("json") {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|x| {
+                json!({
+                    "path": x.file_path,
+                    "state": format!("{:?}", x.state),
+                    "offset": x.offset,
+                    "length": x.length,
+                    "file_time": x.file_time,
+                })
+            })
+            .collect();
+        let report = json!({
+            "library_name": header.library_name,
+            "library_path": header.library_path,
+            "num_entries": header.num_entries,
+            "ok_entries": header.ok_entries,
+            "entries": entries,
+        });
+        let json = graceful_unwrap("Serializing to json", serde_json::to_string_pretty(&report));
+        println!("{}", json);
+        return;
+    }
+
+    println!(
+        "{:?} with library path {:?}, {} entries, {} with state Ok",
+        header.library_name, header.library_path, header.num_entries, header.ok_entries
+    );
+    for entry in &entries {
+        let time = entry
+            .to_system_time()
+            .map(format_time)
+            .unwrap_or_else(|| String::from("-"));
+        println!(
+            "{:<8} {:>10} {:<19} {}",
+            format!("{:?}", entry.state),
+            entry.length,
+            time,
+            entry.file_path
+        );
+    }
+}
+
+/// Extracts the files of an archive.
+fn subcommand_extract(matches: &ArgMatches) {
+    let archive = PathBuf::from(matches.value_of_os("archive").expect("archive"));
+    let target = PathBuf::from(matches.value_of_os("target").expect("target"));
+    let pattern = matches.value_of("glob").unwrap_or("**");
+    let count = graceful_unwrap(
+        "Extracting archive",
+        extract_archive(&archive, &target, pattern),
+    );
+    println!("Extracted {} files to {:?}", count, target);
+}
+
+/// Extracts the files of an archive that match a glob pattern to a target directory.
+///
+/// Files with unsafe paths are skipped. Returns the number of extracted files.
+fn extract_archive(archive: &Path, target: &Path, pattern: &str) -> io::Result<usize> {
+    let mut file = fs::File::open(archive)?;
+    let (_, entries) = SlfHeader::with_entries_from_input(&mut file, None)?;
+    let slf_fs = SlfFs::new(Box::new(DirFsFile::open(archive)?))?;
+    let library_path = slf_fs.prefix.clone();
+    let mut vfs = Vfs::new();
+    vfs.add_layer_at(slf_fs, &library_path, &Nfc::caseless_path(""))?;
+
+    // Keep the original case of the file paths
+    let entry_by_path: HashMap<Nfc, &SlfEntry> = entries
+        .iter()
+        .filter(|x| x.state == SlfEntryState::Ok)
+        .map(|x| (Nfc::caseless_path(&x.file_path.replace('\\', "/")), x))
+        .collect();
+    let mut count = 0;
+    for file in vfs.glob(pattern)? {
+        let entry = entry_by_path.get(&file.path);
+        let file_path = entry.map_or(file.path.to_string(), |x| x.file_path.replace('\\', "/"));
+        if file_path
+            .split('/')
+            .any(|x| x.is_empty() || x == "." || x == "..")
+        {
+            eprintln!("Skipping unsafe path {:?}", file_path);
+            continue;
+        }
+        let target_path = file_path
+            .split('/')
+            .fold(target.to_owned(), |path, component| path.join(component));
+        extract_file(
+            &vfs,
+            &file.path,
+            &target_path,
+            entry.and_then(|x| x.to_system_time()),
+        )
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", file_path, err)))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Creates an archive from a directory.
+fn subcommand_create(matches: &ArgMatches) {
+    let archive = PathBuf::from(matches.value_of_os("archive").expect("archive"));
+    let dir = PathBuf::from(matches.value_of_os("dir").expect("dir"));
+    let library_name = match matches.value_of("library-name") {
+        Some(name) => name.to_owned(),
+        None => archive
+            .file_name()
+            .map(|x| x.to_string_lossy().to_uppercase())
+            .unwrap_or_default(),
+    };
+    let library_path = matches.value_of("library-path").unwrap_or("");
+    let mut builder = SlfBuilder::new(&library_name, library_path);
+    graceful_unwrap("Adding files", builder.add_dir(&dir));
+    let entries = graceful_unwrap("Writing archive", builder.write_to_path(&archive));
+    println!("Created {:?} with {} files", archive, entries.len());
+}
+
+/// Checks archives for corruption.
+fn subcommand_verify(matches: &ArgMatches) {
+    let validator = SlfValidator {
+        check_order: !matches.is_present("ignore-order"),
+        ..SlfValidator::default()
+    };
+    let mut has_problems = false;
+    for archive in matches.values_of_os("archives").expect("archives") {
+        let archive = PathBuf::from(archive);
+        let problems = graceful_unwrap(
+            "Reading archive",
+            fs::File::open(&archive).and_then(|mut x| validator.validate(&mut x)),
+        );
+        if problems.is_empty() {
+            println!("{}: ok", archive.display());
+        }
+        for problem in &problems {
+            println!("{}: {}", archive.display(), problem);
+        }
+        has_problems |= !problems.is_empty();
+    }
+    if has_problems {
+        process::exit(1);
+    }
+}
+
+/// Compares the files of two archives.
+fn subcommand_diff(matches: &ArgMatches) {
+    let old = PathBuf::from(matches.value_of_os("old").expect("old"));
+    let new = PathBuf::from(matches.value_of_os("new").expect("new"));
+    let mut old_file = graceful_unwrap("Opening old archive", fs::File::open(&old));
+    let mut new_file = graceful_unwrap("Opening new archive", fs::File::open(&new));
+    let ok_entries = |archive: &Path| -> BTreeMap<String, SlfEntry> {
+        let (header, entries) = read_archive(archive);
+        entries
+            .into_iter()
+            .filter(|x| x.state == SlfEntryState::Ok)
+            .map(|x| {
+                let path = format!("{}{}", header.library_path, x.file_path);
+                (path.replace('\\', "/").to_ascii_lowercase(), x)
+            })
+            .collect()
+    };
+    let old_entries = ok_entries(&old);
+    let mut new_entries = ok_entries(&new);
+
+    let mut changes = BTreeMap::new();
+    for (path, old_entry) in old_entries {
+        match new_entries.remove(&path) {
+            None => {
+                changes.insert(path, '-');
+            }
+            Some(new_entry) => {
+                let old_data = graceful_unwrap(
+                    "Reading old archive",
+                    old_entry.data_from_input(&mut old_file),
+                );
+                let new_data = graceful_unwrap(
+                    "Reading new archive",
+                    new_entry.data_from_input(&mut new_file),
+                );
+                if old_data != new_data {
+                    changes.insert(path, 'M');
+                }
+            }
+        }
+    }
+    for path in new_entries.into_keys() {
+        changes.insert(path, '+');
+    }
+    for (path, change) in &changes {
+        println!("{} {}", change, path);
+    }
+    if !changes.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// Reads the header and the entries of an archive.
+fn read_archive(archive: &Path) -> (SlfHeader, Vec<SlfEntry>) {
+    let mut file = graceful_unwrap("Opening archive", fs::File::open(archive));
+    graceful_unwrap(
+        "Reading archive",
+        SlfHeader::with_entries_from_input(&mut file, None),
+    )
+}
+
+/// Copies a file of the VFS to a filesystem path and sets the modification time.
+fn extract_file(
+    vfs: &Vfs,
+    path: &Nfc,
+    target_path: &Path,
+    modified: Option<SystemTime>,
+) -> io::Result<()> {
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut output = fs::File::create(target_path)?;
+    io::copy(&mut vfs.open(path)?, &mut output)?;
+    if let Some(modified) = modified {
+        output.set_modified(modified)?;
+    }
+    Ok(())
+}
+
+/// Formats a time as UTC date and time, "YYYY-MM-DD hh:mm:ss".
+fn format_time(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+    };
+    let (days, secs_of_day) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // civil_from_days from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Either unwraps a result or prints an error to stderr and exits with 1.
+fn graceful_unwrap<T, E: Debug>(desc: &str, result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{}: {:?}", desc, err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use stracciatella::file_formats::slf::{HEADER_BYTES, SlfEntry, SlfEntryState, SlfHeader};
+    use stracciatella::fs;
+
+    use super::{extract_archive, format_time};
+
+    #[test]
+    fn format_time_utc() {
+        assert_eq!(format_time(UNIX_EPOCH), "1970-01-01 00:00:00");
+        // leap day
+        assert_eq!(
+            format_time(UNIX_EPOCH + Duration::from_secs(951_825_600)),
+            "2000-02-29 12:00:00"
+        );
+        assert_eq!(
+            format_time(UNIX_EPOCH - Duration::from_secs(1)),
+            "1969-12-31 23:59:59"
+        );
+        assert_eq!(
+            format_time(UNIX_EPOCH - Duration::from_millis(86_400_500)),
+            "1969-12-30 23:59:59"
+        );
+    }
+
+    #[test]
+    fn extract_skips_unsafe_paths() {
+        let temp_dir = fs::TempDir::new().expect("temp dir");
+        let archive = temp_dir.path().join("test.slf");
+        let target = temp_dir.path().join("target");
+        let entries = [("..\\x", b"unsafe"), ("Foo\\Bar.txt", b"safe!!")];
+        let header = SlfHeader {
+            library_name: "test.slf".to_owned(),
+            num_entries: 2,
+            ok_entries: 2,
+            sort: 0xFFFF,
+            version: 0x200,
+            contains_subdirectories: 1,
+            ..SlfHeader::default()
+        };
+        let mut file = fs::File::create(&archive).expect("create archive");
+        header.to_output(&mut file).expect("write header");
+        let mut offset = HEADER_BYTES;
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(file_path, data)| {
+                file.write_all(*data).expect("write data");
+                let entry = SlfEntry {
+                    file_path: file_path.to_string(),
+                    offset,
+                    length: data.len() as u32,
+                    state: SlfEntryState::Ok,
+                    file_time: 0,
+                };
+                offset += data.len() as u32;
+                entry
+            })
+            .collect();
+        header
+            .entries_to_output(&mut file, &entries)
+            .expect("write entries");
+        drop(file);
+
+        let count = extract_archive(&archive, &target, "**").expect("extract");
+        assert_eq!(count, 1);
+        assert!(!temp_dir.path().join("x").exists());
+        // the original case is kept
+        let names: Vec<_> = fs::read_dir(&target)
+            .expect("read target")
+            .map(|x| x.expect("entry").file_name())
+            .collect();
+        assert_eq!(names, vec!["Foo"]);
+        assert_eq!(
+            fs::read(target.join("Foo").join("Bar.txt")).expect("read"),
+            b"safe!!"
+        );
+    }
+}
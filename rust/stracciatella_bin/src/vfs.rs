@@ -0,0 +1,306 @@
+//! This file contains the code for the vfs executable.
+//!
+//! It inspects the virtual filesystem that the game would use with the same configuration.
+//!
+//!
+//! # Show which layer provides each file:
+//!
+//! Example:
+//! ```
+//! vfs provenance --shadowing-only --home /path/to/stracciatella/home -- --mod my-mod
+//! ```
+//!
+//!
+//! # Export the files as the game sees them:
+//!
+//! Example:
+//! ```
+//! vfs export --apply-patches --slf /path/to/export.slf -- --mod my-mod
+//! ```
+//!
+//!
+//! # List paths that differ only in case, they are ambiguous on case-sensitive filesystems:
+//!
+//! Example:
+//! ```
+//! vfs case-collisions -- --mod my-mod
+//! ```
+//!
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{App, Arg, ArgMatches, SubCommand, crate_version};
+use serde_json::json;
+
+use stracciatella::config::{EngineOptions, find_stracciatella_home};
+use stracciatella::mods::ModManager;
+use stracciatella::unicode::Nfc;
+use stracciatella::vfs::Vfs;
+
+/// Entry point of the vfs executable.
+fn main() {
+    let cmd_provenance = SubCommand::with_name("provenance")
+        .about("Lists the layer that provides each file and the layers it shadows.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("home")
+                .help("Stracciatella home directory, defaults to the one of the game")
+                .long("home")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json")
+                .help("Outputs json instead of text")
+                .long("json"),
+        )
+        .arg(
+            Arg::with_name("shadowing-only")
+                .help("Only lists files that shadow files or patches of lower priority layers")
+                .long("shadowing-only"),
+        )
+        .arg(
+            Arg::with_name("nested-slf")
+                .help("Mounts SLF files in subdirectories and inside of other archives")
+                .long("nested-slf"),
+        )
+        .arg(
+            Arg::with_name("path")
+                .help("Directory in the VFS, defaults to the root directory")
+                .long("path")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("engine-args")
+                .help("Game arguments, e.g. the enabled mods")
+                .value_name("ARGS")
+                .multiple(true)
+                .last(true),
+        );
+
+    let cmd_export = SubCommand::with_name("export")
+        .about("Exports the files of the VFS to a directory or an SLF file.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("home")
+                .help("Stracciatella home directory, defaults to the one of the game")
+                .long("home")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("apply-patches")
+                .help("Applies json patches instead of exporting them")
+                .long("apply-patches"),
+        )
+        .arg(
+            Arg::with_name("slf")
+                .help("Exports to an SLF file instead of a directory")
+                .long("slf"),
+        )
+        .arg(
+            Arg::with_name("nested-slf")
+                .help("Mounts SLF files in subdirectories and inside of other archives")
+                .long("nested-slf"),
+        )
+        .arg(
+            Arg::with_name("path")
+                .help("Directory in the VFS, defaults to the root directory")
+                .long("path")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("target")
+                .help("Target directory or SLF file")
+                .value_name("TARGET")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("engine-args")
+                .help("Game arguments, e.g. the enabled mods")
+                .value_name("ARGS")
+                .multiple(true)
+                .last(true),
+        );
+
+    let cmd_case_collisions = SubCommand::with_name("case-collisions")
+        .about("Lists paths that match several files case insensitively, exits with 1 if there are any.")
+        .version("1.0")
+        .arg(
+            Arg::with_name("home")
+                .help("Stracciatella home directory, defaults to the one of the game")
+                .long("home")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json")
+                .help("Outputs json instead of text")
+                .long("json"),
+        )
+        .arg(
+            Arg::with_name("nested-slf")
+                .help("Mounts SLF files in subdirectories and inside of other archives")
+                .long("nested-slf"),
+        )
+        .arg(
+            Arg::with_name("engine-args")
+                .help("Game arguments, e.g. the enabled mods")
+                .value_name("ARGS")
+                .multiple(true)
+                .last(true),
+        );
+
+    let matches = App::new("vfs")
+        .about("Tool that inspects the virtual filesystem of the game.")
+        .version(crate_version!())
+        .subcommand(cmd_provenance)
+        .subcommand(cmd_export)
+        .subcommand(cmd_case_collisions)
+        .get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("provenance") {
+        subcommand_provenance(matches);
+    } else if let Some(matches) = matches.subcommand_matches("export") {
+        subcommand_export(matches);
+    } else if let Some(matches) = matches.subcommand_matches("case-collisions") {
+        subcommand_case_collisions(matches);
+    }
+}
+
+/// Lists the provenance of the files in the VFS.
+fn subcommand_provenance(matches: &ArgMatches) {
+    let vfs = init_vfs(matches);
+    let path = Nfc::caseless_path(matches.value_of("path").unwrap_or(""));
+    let shadowing_only = matches.is_present("shadowing-only");
+    let report: Vec<_> = graceful_unwrap("Reading VFS", vfs.provenance(&path))
+        .into_iter()
+        .filter(|x| !shadowing_only || x.is_shadowing())
+        .collect();
+    let layer_name = |index: &usize| vfs.entries[*index].to_string();
+
+    if matches.is_present("json") {
+        let report: Vec<_> = report
+            .iter()
+            .map(|x| {
+                json!({
+                    "path": x.path.as_str(),
+                    "provider": layer_name(&x.provider()),
+                    "shadowed": x.shadowed().iter().map(layer_name).collect::<Vec<_>>(),
+                    "patches": x.patches.iter().map(layer_name).collect::<Vec<_>>(),
+                    "ignored_patches": x.ignored_patches.iter().map(layer_name).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        let json = graceful_unwrap("Serializing to json", serde_json::to_string_pretty(&report));
+        println!("{}", json);
+        return;
+    }
+
+    for provenance in &report {
+        println!("{}", provenance.path);
+        println!("  provided by {}", layer_name(&provenance.provider()));
+        for index in provenance.shadowed() {
+            println!("  shadows {}", layer_name(index));
+        }
+        for index in &provenance.patches {
+            println!("  patched by {}", layer_name(index));
+        }
+        for index in &provenance.ignored_patches {
+            println!("  ignores patch in {}", layer_name(index));
+        }
+    }
+}
+
+/// Exports the files in the VFS.
+fn subcommand_export(matches: &ArgMatches) {
+    let vfs = init_vfs(matches);
+    let path = Nfc::caseless_path(matches.value_of("path").unwrap_or(""));
+    let target = PathBuf::from(matches.value_of_os("target").expect("target"));
+    let apply_patches = matches.is_present("apply-patches");
+    let count = if matches.is_present("slf") {
+        graceful_unwrap(
+            "Exporting to SLF",
+            vfs.export_to_slf(&path, &target, apply_patches),
+        )
+    } else {
+        graceful_unwrap(
+            "Exporting to directory",
+            vfs.export_to_dir(&path, &target, apply_patches),
+        )
+    };
+    println!("Exported {} files to {:?}", count, target);
+}
+
+/// Lists the ambiguous paths of each layer of the VFS.
+fn subcommand_case_collisions(matches: &ArgMatches) {
+    let vfs = init_vfs(matches);
+    let collisions_by_layer = graceful_unwrap("Reading VFS", vfs.case_collisions_by_layer());
+    let report: Vec<_> = collisions_by_layer
+        .iter()
+        .enumerate()
+        .flat_map(|(index, collisions)| collisions.iter().map(move |x| (index, x)))
+        .collect();
+
+    if matches.is_present("json") {
+        let report: Vec<_> = report
+            .iter()
+            .map(|(index, x)| {
+                json!({
+                    "path": x.path.as_str(),
+                    "layer": vfs.entries[*index].to_string(),
+                    "candidates": x.candidates,
+                })
+            })
+            .collect();
+        let json = graceful_unwrap("Serializing to json", serde_json::to_string_pretty(&report));
+        println!("{}", json);
+    } else {
+        for (index, collision) in &report {
+            println!("{}", collision.path);
+            println!("  in {}", vfs.entries[*index]);
+            for candidate in &collision.candidates {
+                println!("  matches {:?}", candidate);
+            }
+        }
+    }
+    if !report.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// Initializes the VFS like the game does.
+fn init_vfs(matches: &ArgMatches) -> Vfs {
+    let home = match matches.value_of_os("home") {
+        Some(home) => PathBuf::from(home),
+        None => graceful_unwrap("Finding stracciatella home", find_stracciatella_home()),
+    };
+    let mut args = vec![String::from("ja2")];
+    if let Some(values) = matches.values_of("engine-args") {
+        args.extend(values.map(String::from));
+    }
+    let engine_options = graceful_unwrap(
+        "Reading engine options",
+        EngineOptions::from_home_and_args(&home, &args),
+    );
+    let mod_manager = graceful_unwrap("Reading mods", ModManager::new(&engine_options));
+    let mut vfs = Vfs::new();
+    vfs.nested_slf_files = matches.is_present("nested-slf");
+    graceful_unwrap("Initializing VFS", vfs.init(&engine_options, &mod_manager));
+    vfs
+}
+
+/// Either unwraps a result or prints an error to stderr and exits with 1.
+fn graceful_unwrap<T, E: Debug>(desc: &str, result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{}: {:?}", desc, err);
+            process::exit(1);
+        }
+    }
+}
@@ -113,7 +113,7 @@ pub extern "C" fn VecU8_push(vec: *mut VecU8, value: u8) {
 /// A wrapper around `Vec<usize>` for C.
 #[derive(Default)]
 pub struct VecUSize {
-    inner: Vec<usize>,
+    pub inner: Vec<usize>,
 }
 
 impl From<Vec<usize>> for VecUSize {
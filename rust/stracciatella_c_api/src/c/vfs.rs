@@ -2,11 +2,16 @@
 //!
 //! [`stracciatella::vfs`]: ../../../stracciatella/vfs/index.html
 
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
 use stracciatella::config::EngineOptions;
 use stracciatella::mods::ModManager;
 use stracciatella::unicode::Nfc;
 use stracciatella::vfile::VFile;
-use stracciatella::vfs::{Vfs, VfsLayer};
+use stracciatella::vfs::mem::MemFs;
+use stracciatella::vfs::walk::VfsEntry;
+use stracciatella::vfs::{Vfs, VfsLayer, VfsMetadata};
 
 use crate::c::common::*;
 use crate::c::vec::VecCString;
@@ -47,6 +52,29 @@ pub extern "C" fn Vfs_init(
     no_rust_error()
 }
 
+/// Sets whether `Vfs_init` mounts SLF files in subdirectories and inside of other archives.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_setNestedSlfFiles(vfs: *mut Vfs, nested_slf_files: bool) {
+    let vfs = unsafe_mut(vfs);
+    vfs.nested_slf_files = nested_slf_files;
+}
+
+/// Sets whether `Vfs_init` builds a path index for lookups.
+/// Files that are added to read-only directories after `Vfs_init` are not found with the index.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_setPathIndex(vfs: *mut Vfs, path_index: bool) {
+    let vfs = unsafe_mut(vfs);
+    vfs.path_index = path_index;
+}
+
+/// Sets whether `Vfs_init` logs a warning for every ambiguous path of the mounted directories.
+/// The check walks every directory, so it slows down `Vfs_init`.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_setCheckCaseCollisions(vfs: *mut Vfs, check_case_collisions: bool) {
+    let vfs = unsafe_mut(vfs);
+    vfs.check_case_collisions = check_case_collisions;
+}
+
 /// Adds an overlay filesystem backed by a filesystem directory.
 /// Returns true if successful, false otherwise.
 /// Sets the rust error.
@@ -61,6 +89,50 @@ pub extern "C" fn Vfs_addDir(vfs: *mut Vfs, path: *const c_char) -> bool {
     no_rust_error()
 }
 
+/// Adds an overlay filesystem backed by a filesystem directory that is mounted at a prefix like `tilesets/50`.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_addDirAt(vfs: *mut Vfs, path: *const c_char, prefix: *const c_char) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = path_buf_from_c_str_or_panic(unsafe_c_str(path));
+    let prefix = Nfc::caseless_path(str_from_c_str_or_panic(unsafe_c_str(prefix)));
+    if let Err(err) = vfs.add_dir_at(&path, &prefix) {
+        remember_rust_error(format!("Vfs_addDirAt {:?} {:?}: {}", path, prefix, err));
+    }
+    no_rust_error()
+}
+
+/// Adds a writable overlay filesystem backed by a filesystem directory.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_addWritableDir(vfs: *mut Vfs, path: *const c_char) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = path_buf_from_c_str_or_panic(unsafe_c_str(path));
+    if let Err(err) = vfs.add_writable_dir(&path) {
+        remember_rust_error(format!("Vfs_addWritableDir {:?}: {}", path, err));
+    }
+    no_rust_error()
+}
+
+/// Mounts an in-memory filesystem at a priority index, 0 is the highest priority.
+/// The filesystem can still be modified with `MemFs_insert` after it is mounted.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_insertMemFs(vfs: *mut Vfs, mem_fs: *const MemFs, index: usize) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let mem_fs = unsafe_ref(mem_fs);
+    if let Err(err) = vfs.insert_layer(index, Arc::new(mem_fs.clone())) {
+        remember_rust_error(format!("Vfs_insertMemFs {}: {}", index, err));
+    }
+    no_rust_error()
+}
+
 /// Lists a directory in the VFS with an optional filter on the extension (pass null otherwise).
 /// Returns a list of files on success and null otherwise
 /// Sets the rust error.
@@ -98,6 +170,108 @@ pub extern "C" fn Vfs_readDir(
     }
 }
 
+/// Lists all files in a directory of the VFS and its subdirectories.
+/// If layers is not null, the index of the layer that provides each file is added to it.
+/// Returns a list of files on success and null otherwise.
+/// Sets the rust error.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_walk(
+    vfs: *mut Vfs,
+    path: *const c_char,
+    layers: *mut VecUSize,
+) -> *mut VecCString {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = Nfc::caseless_path(str_from_c_str_or_panic(unsafe_c_str(path)));
+    match vfs.walk(&path) {
+        Err(err) => {
+            remember_rust_error(format!("Vfs_walk {:?}: {}", path, err));
+            std::ptr::null_mut()
+        }
+        Ok(entries) => into_ptr(entries_to_c_vec(entries, layers)),
+    }
+}
+
+/// Lists all files in the VFS that match a glob pattern like `maps/*.dat`.
+/// `?` and `*` do not match `/`, a `**` path component matches any number of directories.
+/// If layers is not null, the index of the layer that provides each file is added to it.
+/// Returns a list of files on success and null otherwise.
+/// Sets the rust error.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_glob(
+    vfs: *mut Vfs,
+    pattern: *const c_char,
+    layers: *mut VecUSize,
+) -> *mut VecCString {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let pattern = str_from_c_str_or_panic(unsafe_c_str(pattern));
+    match vfs.glob(pattern) {
+        Err(err) => {
+            remember_rust_error(format!("Vfs_glob {:?}: {}", pattern, err));
+            std::ptr::null_mut()
+        }
+        Ok(entries) => into_ptr(entries_to_c_vec(entries, layers)),
+    }
+}
+
+/// Exports all files in a VFS directory and its subdirectories to a filesystem directory.
+/// If apply_patches is true, json files are exported with their patches applied.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_exportToDir(
+    vfs: *mut Vfs,
+    dir_path: *const c_char,
+    target: *const c_char,
+    apply_patches: bool,
+) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let dir_path = Nfc::caseless_path(str_from_c_str_or_panic(unsafe_c_str(dir_path)));
+    let target = path_buf_from_c_str_or_panic(unsafe_c_str(target));
+    if let Err(err) = vfs.export_to_dir(&dir_path, &target, apply_patches) {
+        remember_rust_error(format!("Vfs_exportToDir {:?}: {}", target, err));
+    }
+    no_rust_error()
+}
+
+/// Exports all files in a VFS directory and its subdirectories to a new SLF archive.
+/// If apply_patches is true, json files are exported with their patches applied.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_exportToSlf(
+    vfs: *mut Vfs,
+    dir_path: *const c_char,
+    target: *const c_char,
+    apply_patches: bool,
+) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let dir_path = Nfc::caseless_path(str_from_c_str_or_panic(unsafe_c_str(dir_path)));
+    let target = path_buf_from_c_str_or_panic(unsafe_c_str(target));
+    if let Err(err) = vfs.export_to_slf(&dir_path, &target, apply_patches) {
+        remember_rust_error(format!("Vfs_exportToSlf {:?}: {}", target, err));
+    }
+    no_rust_error()
+}
+
+/// Converts the paths to C strings and adds the layers to the optional vector.
+fn entries_to_c_vec(entries: Vec<VfsEntry>, layers: *mut VecUSize) -> VecCString {
+    if !layers.is_null() {
+        let layers = unsafe_mut(layers);
+        layers.inner.extend(entries.iter().map(|x| x.layer_index));
+    }
+    let vec: Vec<_> = entries
+        .into_iter()
+        .map(|x| c_string_from_str(&x.path))
+        .collect();
+    VecCString::from(vec)
+}
+
 /// Opens a virtual file for reading.
 /// Returns the file on success, null otherwise.
 /// Sets the rust error.
@@ -156,6 +330,88 @@ pub extern "C" fn Vfs_readPatchedJson(vfs: *mut Vfs, path: *const c_char) -> *mu
     }
 }
 
+/// Gets the statistics of the cache of `Vfs_readPatchedJson`.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_jsonCacheStats(
+    vfs: *const Vfs,
+    hits: *mut u64,
+    misses: *mut u64,
+    entries: *mut usize,
+) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_ref(vfs);
+    match vfs.json_cache_stats() {
+        Err(err) => remember_rust_error(format!("Vfs_jsonCacheStats: {}", err)),
+        Ok(stats) => {
+            *unsafe_mut(hits) = stats.hits;
+            *unsafe_mut(misses) = stats.misses;
+            *unsafe_mut(entries) = stats.entries;
+        }
+    }
+    no_rust_error()
+}
+
+/// Removes all values from the cache of `Vfs_readPatchedJson`.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_clearJsonCache(vfs: *const Vfs) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_ref(vfs);
+    if let Err(err) = vfs.clear_json_cache() {
+        remember_rust_error(format!("Vfs_clearJsonCache: {}", err));
+    }
+    no_rust_error()
+}
+
+/// Starts recording the paths that are opened, checked or listed.
+/// If dump_path is not null, the trace is written to it as JSON when tracing stops or the VFS is destroyed.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_startTrace(vfs: *const Vfs, dump_path: *const c_char) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_ref(vfs);
+    let dump_path = if dump_path.is_null() {
+        None
+    } else {
+        Some(path_buf_from_c_str_or_panic(unsafe_c_str(dump_path)))
+    };
+    if let Err(err) = vfs.start_trace(dump_path.as_deref()) {
+        remember_rust_error(format!("Vfs_startTrace: {}", err));
+    }
+    no_rust_error()
+}
+
+/// Stops recording the accessed paths and writes the trace to its dump path.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_stopTrace(vfs: *const Vfs) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_ref(vfs);
+    if let Err(err) = vfs.stop_trace() {
+        remember_rust_error(format!("Vfs_stopTrace: {}", err));
+    }
+    no_rust_error()
+}
+
+/// Writes the accessed paths recorded so far to a file as JSON.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_dumpTrace(vfs: *const Vfs, path: *const c_char) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_ref(vfs);
+    let path = path_buf_from_c_str_or_panic(unsafe_c_str(path));
+    if let Err(err) = vfs.dump_trace(&path) {
+        remember_rust_error(format!("Vfs_dumpTrace: {}", err));
+    }
+    no_rust_error()
+}
+
 /// Returns a vector of all VFS layers a file can be found in.
 /// Returns the vector on success, null otherwise.
 /// Sets the rust error.
@@ -173,3 +429,224 @@ pub extern "C" fn Vfs_readLayers(vfs: *mut Vfs, path: *const c_char) -> *mut Vec
         Ok(v) => into_ptr(v.into()),
     }
 }
+
+/// Creates a virtual file for reading and writing in the highest priority writable layer.
+/// An existing file is truncated.
+/// Returns the file on success, null otherwise.
+/// Sets the rust error.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_createFile(vfs: *mut Vfs, path: *const c_char) -> *mut VFile {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = str_from_c_str_or_panic(unsafe_c_str(path));
+    match vfs.create(&Nfc::caseless_path(path)) {
+        Err(err) => {
+            remember_rust_error(format!("Vfs_createFile {:?}: {}", path, err));
+            std::ptr::null_mut()
+        }
+        Ok(file) => into_ptr(VFile::rw_vfs_file(file)),
+    }
+}
+
+/// Opens a virtual file for reading and writing in the highest priority writable layer.
+/// Files of lower priority layers are copied to the writable layer first.
+/// Returns the file on success, null otherwise.
+/// Sets the rust error.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_openWritable(vfs: *mut Vfs, path: *const c_char) -> *mut VFile {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = str_from_c_str_or_panic(unsafe_c_str(path));
+    match vfs.open_writable(&Nfc::caseless_path(path)) {
+        Err(err) => {
+            remember_rust_error(format!("Vfs_openWritable {:?}: {}", path, err));
+            std::ptr::null_mut()
+        }
+        Ok(file) => into_ptr(VFile::rw_vfs_file(file)),
+    }
+}
+
+/// Creates a directory and all missing parents in the highest priority writable layer.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_createDir(vfs: *mut Vfs, path: *const c_char) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = str_from_c_str_or_panic(unsafe_c_str(path));
+    if let Err(err) = vfs.create_dir(&Nfc::caseless_path(path)) {
+        remember_rust_error(format!("Vfs_createDir {:?}: {}", path, err));
+    }
+    no_rust_error()
+}
+
+/// Removes a file or an empty directory from the highest priority writable layer.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_remove(vfs: *mut Vfs, path: *const c_char) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = str_from_c_str_or_panic(unsafe_c_str(path));
+    if let Err(err) = vfs.remove(&Nfc::caseless_path(path)) {
+        remember_rust_error(format!("Vfs_remove {:?}: {}", path, err));
+    }
+    no_rust_error()
+}
+
+/// Renames a file or directory in the highest priority writable layer.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_rename(vfs: *mut Vfs, from: *const c_char, to: *const c_char) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let from = str_from_c_str_or_panic(unsafe_c_str(from));
+    let to = str_from_c_str_or_panic(unsafe_c_str(to));
+    if let Err(err) = vfs.rename(&Nfc::caseless_path(from), &Nfc::caseless_path(to)) {
+        remember_rust_error(format!("Vfs_rename {:?} {:?}: {}", from, to, err));
+    }
+    no_rust_error()
+}
+
+/// Gets the metadata of a file or directory from the highest priority layer that provides it.
+/// Returns the metadata on success, null otherwise.
+/// Sets the rust error.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_metadata(vfs: *mut Vfs, path: *const c_char) -> *mut VfsMetadata {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    let path = str_from_c_str_or_panic(unsafe_c_str(path));
+    match vfs.metadata(&Nfc::caseless_path(path)) {
+        Err(err) => {
+            remember_rust_error(format!("Vfs_metadata {:?}: {}", path, err));
+            std::ptr::null_mut()
+        }
+        Ok(metadata) => into_ptr(metadata),
+    }
+}
+
+/// Destroys the metadata.
+/// coverity[+free : arg-0]
+#[unsafe(no_mangle)]
+pub extern "C" fn VfsMetadata_destroy(metadata: *mut VfsMetadata) {
+    let _drop_me = from_ptr(metadata);
+}
+
+/// Gets the length of the file in bytes, 0 for directories.
+#[unsafe(no_mangle)]
+pub extern "C" fn VfsMetadata_len(metadata: *const VfsMetadata) -> u64 {
+    unsafe_ref(metadata).len
+}
+
+/// Returns true if the path is a directory.
+#[unsafe(no_mangle)]
+pub extern "C" fn VfsMetadata_isDir(metadata: *const VfsMetadata) -> bool {
+    unsafe_ref(metadata).is_dir
+}
+
+/// Gets the modified time in seconds since the unix epoch.
+/// Returns false if the layer does not know the modified time.
+#[unsafe(no_mangle)]
+pub extern "C" fn VfsMetadata_modifiedSecs(
+    metadata: *const VfsMetadata,
+    modified_secs: *mut f64,
+) -> bool {
+    let metadata = unsafe_ref(metadata);
+    let modified_secs = unsafe_mut(modified_secs);
+    match metadata.modified {
+        Some(modified) => {
+            *modified_secs = match modified.duration_since(UNIX_EPOCH) {
+                Ok(duration) => duration.as_secs_f64(),
+                Err(err) => -err.duration().as_secs_f64(),
+            };
+            true
+        }
+        None => {
+            *modified_secs = f64::MIN;
+            false
+        }
+    }
+}
+
+/// Gets the index of the layer that provides the path.
+#[unsafe(no_mangle)]
+pub extern "C" fn VfsMetadata_layerIndex(metadata: *const VfsMetadata) -> usize {
+    unsafe_ref(metadata).layer_index.unwrap_or(usize::MAX)
+}
+
+/// Gets the display name of the layer that provides the path.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn VfsMetadata_layer(metadata: *const VfsMetadata) -> *mut c_char {
+    c_string_from_str(&unsafe_ref(metadata).layer).into_raw()
+}
+
+/// Starts watching all layers of the VFS that support it for changes.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_watch(vfs: *mut Vfs) -> bool {
+    forget_rust_error();
+    let vfs = unsafe_mut(vfs);
+    if let Err(err) = vfs.watch() {
+        remember_rust_error(format!("Vfs_watch: {}", err));
+    }
+    no_rust_error()
+}
+
+/// Returns the paths that changed in watched layers since the last call.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn Vfs_pollChanges(vfs: *mut Vfs) -> *mut VecCString {
+    let vfs = unsafe_mut(vfs);
+    let vec: Vec<_> = vfs
+        .poll_changes()
+        .into_iter()
+        .map(|x| c_string_from_str(&x))
+        .collect();
+    into_ptr(VecCString::from(vec))
+}
+
+/// Creates an empty in-memory filesystem.
+/// coverity[+alloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn MemFs_create(name: *const c_char) -> *mut MemFs {
+    let name = str_from_c_str_or_panic(unsafe_c_str(name));
+    into_ptr(MemFs::new(name))
+}
+
+/// Destroys the in-memory filesystem handle.
+/// Mounted filesystems keep their files.
+/// coverity[+free : arg-0]
+#[unsafe(no_mangle)]
+pub extern "C" fn MemFs_destroy(mem_fs: *mut MemFs) {
+    let _drop_me = from_ptr(mem_fs);
+}
+
+/// Adds a file with a copy of the data to the in-memory filesystem, replacing an existing file.
+/// Returns true if successful, false otherwise.
+/// Sets the rust error.
+#[unsafe(no_mangle)]
+pub extern "C" fn MemFs_insert(
+    mem_fs: *mut MemFs,
+    path: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    forget_rust_error();
+    let mem_fs = unsafe_ref(mem_fs);
+    let path = str_from_c_str_or_panic(unsafe_c_str(path));
+    let data = if len == 0 {
+        vec![]
+    } else {
+        unsafe_slice(data, len).to_vec()
+    };
+    if let Err(err) = mem_fs.insert(&Nfc::caseless_path(path), data) {
+        remember_rust_error(format!("MemFs_insert {:?}: {}", path, err));
+    }
+    no_rust_error()
+}